@@ -1,19 +1,83 @@
 use std::{collections::HashSet, fmt::Debug, process::exit};
+use yaml_rust::Yaml;
 use crate::effect::{Effect, EffectSpawner};
+use crate::geometry::PositionComponent;
+use crate::animation::AnimationRequest;
+use log::warn;
+use sdl2::pixels::Color;
+
+/// Commands queued by actions for the world's systems, kept in the order the actions ran
+/// until the system responsible next runs
+#[derive(Debug, Clone, Default)]
+pub struct Commands {
+    /// Entity whose actions are running, none for dialogs, expiring effects, and world events
+    pub entity: Option<usize>,
+    /// Paths of sounds to play, played and cleared by the sound system every frame
+    pub sounds: Vec<String>,
+    /// Entities to add by `World::spawn_pending` once the frame's systems have run
+    pub spawns: Vec<PendingSpawn>,
+    /// World and entrance the player is moving to, taken by the game once the frame's systems have run
+    pub world_change: Option<(String, String)>,
+    /// Controller rumbles as low and high motor intensities and a duration in milliseconds,
+    /// played and cleared by the input system
+    pub rumbles: Vec<(u16, u16, u32)>,
+    /// Animations to start or stop on entities, applied in order by the animation system
+    pub animations: Vec<(usize, AnimationRequest)>,
+    /// Screen flashes as a color and seconds to fade out, started by the graphics system
+    pub flashes: Vec<(Color, f32)>,
+    /// World tints, an alpha of 0 removing the tint, applied in order by the graphics system
+    pub overlays: Vec<Color>,
+    /// Camera shakes as a magnitude and seconds to settle, started by the graphics system
+    pub shakes: Vec<(f32, f32)>,
+    /// Entities to remove by `World::despawn_pending` once the frame's systems have run
+    pub despawns: Vec<usize>
+}
+
+impl Commands {
+    /// Drop every queued command, such as when the world they were queued in is deloaded
+    pub fn clear(&mut self) {
+        *self = Commands::default();
+    }
+}
 
 /// Trait to define an action caused by a change in state or world event
 ///
 /// An action is able to modify the states of the entity who spawned it, the global effects in the
-/// current world, or the currently displayed Dialog.
+/// current world, the currently displayed Dialog, or queue commands for the world's systems,
+/// such as sounds to play, entities to spawn, or the world the player is moved to next.
 /// Most commonly actions coincide with a set of states defined on an entity in an ActionComponent,
 /// but actions can also be spawned after certain events have finished, such as an animation
 pub trait Actionable {
-    /// Run the desired action, modifying entity state, world effects, or the current dialog
-    fn tick(&mut self, states: &mut HashSet<String>, effects: &mut Vec<Effect>, dialog: &mut Option<String>);
+    /// Run the desired action, modifying entity state, world effects, the current dialog,
+    /// or queueing commands for the world's systems
+    fn tick(&mut self, states: &mut HashSet<String>, effects: &mut Vec<Effect>, dialog: &mut Option<String>, commands: &mut Commands);
+
+    /// Names of the dialogs this action can show, used to validate a world once it is parsed
+    fn dialogs(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
-/// Wrapper trait to allow printing of actions
-pub trait Action: Actionable + Debug {}
+/// Wrapper trait to allow printing and cloning of actions
+pub trait Action: Actionable + Debug + ActionClone {}
+
+/// Helper trait to allow cloning boxed actions
+pub trait ActionClone {
+    /// Clone the action into a new box
+    fn clone_box(&self) -> Box<dyn Action>;
+}
+
+impl<T: 'static + Action + Clone> ActionClone for T {
+    fn clone_box(&self) -> Box<dyn Action> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Action> {
+    fn clone(&self) -> Box<dyn Action> {
+        self.clone_box()
+    }
+}
 
 /// An action which adds a state to the entity who spawned it
 #[derive(Debug, Clone)]
@@ -23,7 +87,7 @@ pub struct AddState {
 }
 
 impl Actionable for AddState {
-    fn tick(&mut self, states: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>) {
+    fn tick(&mut self, states: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, _: &mut Commands) {
         states.insert(self.state.clone());
     }
 }
@@ -38,7 +102,7 @@ pub struct RemoveState {
 }
 
 impl Actionable for RemoveState {
-    fn tick(&mut self, states: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>) {
+    fn tick(&mut self, states: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, _: &mut Commands) {
         states.remove(&self.state);
     }
 }
@@ -53,9 +117,13 @@ pub struct AddEffect {
 }
 
 impl Actionable for AddEffect {
-    fn tick(&mut self, _: &mut HashSet<String>, effects: &mut Vec<Effect>, _: &mut Option<String>) {
+    fn tick(&mut self, _: &mut HashSet<String>, effects: &mut Vec<Effect>, _: &mut Option<String>, _: &mut Commands) {
         effects.push(self.effect.spawn())
     }
+
+    fn dialogs(&self) -> Vec<String> {
+        self.effect.dialogs()
+    }
 }
 
 impl Action for AddEffect {}
@@ -64,12 +132,26 @@ impl Action for AddEffect {}
 #[derive(Debug, Clone)]
 pub struct ShowDialog {
     /// Name of dialog to display
-    pub dialog: String
+    pub dialog: String,
+    /// Alternate dialogs shown instead if the entity who spawned the action has all of their states,
+    /// checked in order
+    pub variants: Vec<(Vec<String>, String)>
 }
 
 impl Actionable for ShowDialog {
-    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, dialog: &mut Option<String>) {
-        *dialog = Some(self.dialog.clone());
+    fn tick(&mut self, states: &mut HashSet<String>, _: &mut Vec<Effect>, dialog: &mut Option<String>, _: &mut Commands) {
+        let name = self.variants.iter()
+            .find(|(required, _)| required.iter().all(|s| states.contains(s)))
+            .map(|(_, name)| name)
+            .unwrap_or(&self.dialog);
+
+        *dialog = Some(name.clone());
+    }
+
+    fn dialogs(&self) -> Vec<String> {
+        std::iter::once(self.dialog.clone())
+            .chain(self.variants.iter().map(|(_, name)| name.clone()))
+            .collect()
     }
 }
 
@@ -79,21 +161,263 @@ impl Action for ShowDialog {}
 pub struct ExitGame;
 
 impl Actionable for ExitGame {
-    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>) {
+    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, _: &mut Commands) {
         exit(0);
     }
 }
 
 impl Action for ExitGame {}
 
-#[derive(Debug)]
-pub struct Goto {
-    pub location: String
+/// An action which moves the player to an entrance of another world,
+/// fading out before the world is swapped
+#[derive(Debug, Clone)]
+pub struct ChangeWorld {
+    /// Name of the world to load
+    pub world: String,
+    /// Name of the entrance the player is placed at, keeping the player's position if it is unknown
+    pub entrance: String
+}
+
+impl Actionable for ChangeWorld {
+    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, commands: &mut Commands) {
+        commands.world_change = Some((self.world.clone(), self.entrance.clone()));
+    }
+}
+impl Action for ChangeWorld {}
+
+/// An action which makes the active controller rumble
+#[derive(Debug, Clone)]
+pub struct Rumble {
+    /// Intensity of the low frequency motor
+    pub low: u16,
+    /// Intensity of the high frequency motor
+    pub high: u16,
+    /// Duration of the rumble in milliseconds
+    pub duration_ms: u32
+}
+
+impl Actionable for Rumble {
+    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, commands: &mut Commands) {
+        commands.rumbles.push((self.low, self.high, self.duration_ms));
+    }
+}
+impl Action for Rumble {}
+
+/// An action which plays an animation once on the entity who spawned it, regardless of its states.
+/// Ignored when run without an entity, such as from a dialog, expiring effect, or world event
+#[derive(Debug, Clone)]
+pub struct PlayAnimation {
+    /// State name of the animation to play
+    pub state: String
+}
+
+impl Actionable for PlayAnimation {
+    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, commands: &mut Commands) {
+        match commands.entity {
+            Some(id) => commands.animations.push((id, AnimationRequest::Play(self.state.clone()))),
+            None => warn!("Cannot play animation '{}' without an entity", self.state)
+        }
+    }
+}
+impl Action for PlayAnimation {}
+
+/// An action which stops a played animation, returning to animations selected by state.
+/// Ignored when run without an entity
+#[derive(Debug, Clone)]
+pub struct StopAnimation;
+
+impl Actionable for StopAnimation {
+    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, commands: &mut Commands) {
+        match commands.entity {
+            Some(id) => commands.animations.push((id, AnimationRequest::Stop)),
+            None => warn!("Cannot stop an animation without an entity")
+        }
+    }
+}
+impl Action for StopAnimation {}
+
+/// An action which runs several actions together in a single tick
+#[derive(Debug, Clone)]
+pub struct Composite {
+    /// Actions to run, in order
+    pub actions: Vec<Box<dyn Action>>
+}
+
+impl Actionable for Composite {
+    fn tick(&mut self, states: &mut HashSet<String>, effects: &mut Vec<Effect>, dialog: &mut Option<String>, commands: &mut Commands) {
+        for action in self.actions.iter_mut() {
+            action.tick(states, effects, dialog, commands);
+        }
+    }
+
+    fn dialogs(&self) -> Vec<String> {
+        self.actions.iter().flat_map(|action| action.dialogs()).collect()
+    }
+}
+impl Action for Composite {}
+
+/// An action which briefly flashes the whole screen a color, fading to transparent
+#[derive(Debug, Clone)]
+pub struct Flash {
+    /// Red component of the flash
+    pub r: u8,
+    /// Green component of the flash
+    pub g: u8,
+    /// Blue component of the flash
+    pub b: u8,
+    /// Seconds the flash takes to fade out
+    pub duration: f32
+}
+
+impl Actionable for Flash {
+    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, commands: &mut Commands) {
+        commands.flashes.push((Color::RGB(self.r, self.g, self.b), self.duration));
+    }
+}
+impl Action for Flash {}
+
+/// An action which tints the world with a color until changed
+#[derive(Debug, Clone)]
+pub struct SetOverlay {
+    /// Red component of the tint
+    pub r: u8,
+    /// Green component of the tint
+    pub g: u8,
+    /// Blue component of the tint
+    pub b: u8,
+    /// Opacity of the tint, 0 removes it
+    pub a: u8
+}
+
+impl Actionable for SetOverlay {
+    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, commands: &mut Commands) {
+        commands.overlays.push(Color::RGBA(self.r, self.g, self.b, self.a));
+    }
 }
+impl Action for SetOverlay {}
+
+/// An action which shakes every camera, the shake fading out over its duration
+#[derive(Debug, Clone)]
+pub struct Shake {
+    /// Furthest distance in world units the camera is moved from its position
+    pub magnitude: f32,
+    /// Seconds the shake takes to settle
+    pub duration: f32
+}
+
+impl Actionable for Shake {
+    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, commands: &mut Commands) {
+        commands.shakes.push((self.magnitude, self.duration));
+    }
+}
+impl Action for Shake {}
+
+/// An action which removes the entity who spawned it.
+/// Removal is deferred until the frame's systems have run, so the rest of the frame still sees the entity.
+/// Ignored when run without an entity, such as from a dialog, expiring effect, or world event
+#[derive(Debug, Clone)]
+pub struct Despawn {}
+
+impl Actionable for Despawn {
+    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, commands: &mut Commands) {
+        match commands.entity {
+            Some(id) => commands.despawns.push(id),
+            None => warn!("Cannot despawn without an entity")
+        }
+    }
+}
+impl Action for Despawn {}
+
+/// An action which plays a sound once
+#[derive(Debug, Clone)]
+pub struct PlaySound {
+    /// Path of the sound to play
+    pub path: String
+}
+
+impl Actionable for PlaySound {
+    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, commands: &mut Commands) {
+        commands.sounds.push(self.path.clone());
+    }
+}
+impl Action for PlaySound {}
+
+/// Entity waiting to be added to the world once the current systems have run
+#[derive(Debug, Clone)]
+pub struct PendingSpawn {
+    /// Entity definition, in the same form as an entity in a world file
+    pub template: Yaml,
+    /// Position of the entity whose action spawned this one, none if the action had no entity
+    pub origin: Option<PositionComponent>
+}
+
+/// Set the origin of every spawn queued by an entity's actions
+pub fn set_origin(spawns: &mut [PendingSpawn], origin: Option<PositionComponent>) {
+    for spawn in spawns.iter_mut() {
+        spawn.origin = origin.clone();
+    }
+}
+
+/// An action which adds a new entity to the world.
+/// The entity's position is an offset from the spawning entity's position,
+/// or from the world's origin for actions without an entity such as world events
+#[derive(Debug, Clone)]
+pub struct SpawnEntity {
+    /// Entity definition, in the same form as an entity in a world file
+    pub template: Yaml
+}
+
+impl Actionable for SpawnEntity {
+    fn tick(&mut self, _: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>, commands: &mut Commands) {
+        commands.spawns.push(PendingSpawn { template: self.template.clone(), origin: None });
+    }
+}
+impl Action for SpawnEntity {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Rect;
+
+    #[test]
+    fn dialog_variants_show_once_their_states_are_met() {
+        let mut show = ShowDialog {
+            dialog: "guard_intro".into(),
+            variants: vec![(vec!["met_guard".into()], "guard_again".into())]
+        };
+        let mut states = HashSet::new();
+        let mut dialog = None;
+
+        show.tick(&mut states, &mut Vec::new(), &mut dialog, &mut Commands::default());
+        assert_eq!(dialog.as_deref(), Some("guard_intro"));
+
+        states.insert("met_guard".to_string());
+        show.tick(&mut states, &mut Vec::new(), &mut dialog, &mut Commands::default());
+        assert_eq!(dialog.as_deref(), Some("guard_again"));
+    }
+
+    #[test]
+    fn composites_run_every_action_in_one_tick() {
+        let mut composite = Composite {
+            actions: vec![
+                Box::new(AddState { state: "open".into() }),
+                Box::new(RemoveState { state: "locked".into() }),
+                Box::new(AddEffect { effect: EffectSpawner::new(vec!["lit".into()], Vec::new(), Rect::new(0.0, 0.0, 4, 4), None, None) }),
+                Box::new(ShowDialog { dialog: "creak".into(), variants: Vec::new() }),
+                Box::new(PlaySound { path: "door.wav".into() })
+            ]
+        };
+        let mut states = HashSet::from(["locked".to_string()]);
+        let mut effects = Vec::new();
+        let mut dialog = None;
+        let mut commands = Commands::default();
+
+        composite.tick(&mut states, &mut effects, &mut dialog, &mut commands);
 
-impl Actionable for Goto {
-    fn tick(&mut self, states: &mut HashSet<String>, _: &mut Vec<Effect>, _: &mut Option<String>) {
-        states.insert(format!("__MOVE_TO__={}", self.location));
+        assert_eq!(states, HashSet::from(["open".to_string()]));
+        assert_eq!(effects.len(), 1);
+        assert_eq!(dialog.as_deref(), Some("creak"));
+        assert_eq!(commands.sounds, vec!["door.wav".to_string()]);
+        assert_eq!(composite.dialogs(), vec!["creak".to_string()]);
     }
 }
-impl Action for Goto {}
@@ -1,242 +1,375 @@
 use std::ops::Mul;
-use std::time::Instant;
-use std::collections::HashSet;
+
+use log::debug;
 
 use crate::world::World;
-use crate::physics::PhysicsComponent;
-use crate::geometry::PositionComponent;
+use crate::geometry::{PositionComponent, Rect};
+use crate::pathfinding::shortest_path_segment;
+
+/// Spacing in pixels of the grid searched when routing around obstacles
+const PATH_STEP: i32 = 4;
+/// Seconds between searches for a new route to the player
+const ROUTE_INTERVAL: f32 = 0.25;
+
+/// Tunable behavior of an AI controlled entity
+#[derive(Debug, Clone)]
+pub struct AIConfig {
+    /// Points walked between while idle, as (x, y, seconds taken to reach the point)
+    pub idle_path: Vec<(f32, f32, f32)>,
+    /// Distance within which a visible player is chased
+    pub aggro_distance: f32,
+    /// Seconds spent lost before returning to idle
+    pub lost_delay: f32,
+    /// Average speed while chasing the player in pixels/second
+    pub chase_speed: f32,
+    /// How far the chase speed swings above and below its average, so the chase feels less mechanical
+    pub chase_wobble: f32,
+    /// Speed while walking the idle path in pixels/second
+    pub idle_speed: f32,
+    /// Seconds before an entity chasing the player appears in the world they moved to
+    pub teleport_delay: f32,
+    /// World a global entity lives in, following the player out of it while chasing them.
+    /// None for an entity which stays in the world it was loaded with
+    pub home_world: Option<String>
+}
 
-static PID: usize = 0;
-static MID: usize = 1;
+impl Default for AIConfig {
+    fn default() -> AIConfig {
+        AIConfig {
+            idle_path: Vec::new(),
+            aggro_distance: 1000.0,
+            lost_delay: 5.0,
+            chase_speed: 54.0,
+            chase_wobble: 20.0,
+            idle_speed: 60.0,
+            teleport_delay: 5.0,
+            home_world: None
+        }
+    }
+}
 
-pub struct AISystem {
-    last_aggro: Instant,
-    idle_path: Vec<(f32, f32, f32)>,
+/// Component for an entity which wanders an idle path and chases the player once it sees them
+#[derive(Debug, Clone)]
+pub struct AIComponent {
+    /// Behavior of the entity
+    pub config: AIConfig,
+    /// Seconds since the player was lost
+    since_aggro: f32,
     next_idle: usize,
-    last_idle_time: Instant,
-    aggro_distance: f32,
-    lost_delay: f32,
-    last_pathfind: Instant,
-    monster_world: String,
-    teleport_timer: Instant,
+    /// Seconds since the last idle point was reached
+    since_idle: f32,
+    /// Seconds the entity has been simulated, used to wobble its chase speed
+    since_pathfind: f32,
+    /// Seconds since the route to the player was last searched
+    since_route: f32,
+    /// Next point on the route to the player, if one was found
+    waypoint: Option<(f32, f32)>,
+    /// World the entity is currently in, only tracked for entities with a home world
+    current_world: String,
+    /// Seconds spent waiting to teleport after the player
+    teleport_wait: f32,
     awaiting_teleport: bool,
     teleport_location: (f32, f32),
-    monster_lake_pos: (f32, f32)
+    /// Position in the home world, kept while the entity is elsewhere
+    home_pos: (f32, f32)
 }
 
-
-impl AISystem {
-    pub fn new(idle_path: Vec<(f32, f32, f32)>, aggro_distance: f32, lost_delay: f32) -> Self {
-        Self {
-            last_aggro: Instant::now(),
-            idle_path,
+impl AIComponent {
+    /// Create a new AIComponent
+    pub fn new(config: AIConfig) -> AIComponent {
+        AIComponent {
+            current_world: config.home_world.clone().unwrap_or_default(),
+            config,
+            since_aggro: 0.0,
             next_idle: 0,
-            last_idle_time: Instant::now(),
-            aggro_distance,
-            lost_delay,
-            last_pathfind: Instant::now(),
-            monster_world: "lake".into(),
-            teleport_timer: Instant::now(),
+            since_idle: 0.0,
+            since_pathfind: 0.0,
+            since_route: 0.0,
+            waypoint: None,
+            teleport_wait: 0.0,
             awaiting_teleport: false,
             teleport_location: (0.0, 0.0),
-            monster_lake_pos: (0.0, 0.0)
+            home_pos: (0.0, 0.0)
         }
     }
 
-    pub fn run(&mut self, world: &mut World) {
-        // Check if monster needs to be loaded back into lake world
-        if self.monster_world == "lake" && world.current_world == "lake" && world.positions[MID].is_none() {
-            world.positions[MID] = Some(PositionComponent::new(self.monster_lake_pos.0, self.monster_lake_pos.1));
-        }
+    /// Move every timer forward by dt seconds
+    fn advance(&mut self, dt: f32) {
+        self.since_aggro += dt;
+        self.since_idle += dt;
+        self.since_pathfind += dt;
+        self.since_route += dt;
+        self.teleport_wait += dt;
+    }
 
-        // Check if player has moved to new world
-        if self.monster_world != world.current_world {
-            // If the new world is the lake, restore the monsters position
-            if world.current_world == "lake" {
-                self.monster_world = "lake".into();
-                world.positions[MID] = Some(PositionComponent::new(self.monster_lake_pos.0, self.monster_lake_pos.1));
-            } else if world.positions[MID].is_some() {
-                // Save monster lake position
-                self.monster_lake_pos = {
-                    let pos = world.positions[MID].as_ref().unwrap();
-                    (pos.x, pos.y)
-                };
+    /// Index of the idle point closest to a position, none if there is no idle path
+    fn nearest_idle(&self, x: f32, y: f32) -> Option<usize> {
+        self.config.idle_path.iter()
+            .enumerate()
+            .map(|(i, (px, py, _))| (i, ((py-y).powi(2) + (px-x).powi(2)).sqrt()))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(i, _)| i)
+    }
 
-                // If we are aggroed then we teleport after them,
-                // else teleport to nearest idle location
-                if world.states[MID].contains("aggro") {
-                    self.awaiting_teleport = true;
-                    self.teleport_location = {
-                        let rect = world.physics[PID].as_ref().unwrap().hitbox
-                            .after_position(world.positions[PID].as_ref().unwrap())
-                            .after_depth(world.physics[PID].as_ref().unwrap().depth);
-                        (rect.x, rect.y)
-                    };
-                    self.teleport_timer = Instant::now();
-                    self.monster_world = world.current_world.clone();
-                } else {
-                    let mindex = self.idle_path.iter()
-                        .enumerate()
-                        .map(|(i, (x, y, _))| {
-                            (i, ((y-self.monster_lake_pos.1).powi(2) + (x-self.monster_lake_pos.0).powi(2)).sqrt())
-                        })
-                    .min_by(|a, b| {
-                        a.1.partial_cmp(&b.1).unwrap()
-                    }).unwrap().0;
-
-                    self.monster_lake_pos.0 = self.idle_path[mindex].0;
-                    self.monster_lake_pos.1 = self.idle_path[mindex].1;
-                    self.next_idle = (mindex + 1) % self.idle_path.len();
-                    self.last_idle_time = Instant::now();
-                }
+    /// Distance from the simulated home position to the next idle point
+    fn sim_dist(&self) -> f32 {
+        let x0 = self.config.idle_path[self.next_idle].0;
+        let y0 = self.config.idle_path[self.next_idle].1;
+        let x1 = self.home_pos.0;
+        let y1 = self.home_pos.1;
 
-                // Remove monster from the world (temporarily)
-                world.positions[MID] = None;
-                println!("Remove position");
-            }
+        ((y1-y0).powi(2) + (x1-x0).powi(2)).sqrt()
+    }
+}
+
+/// System which moves every entity with the ai state
+pub struct AISystem {
+    /// Behavior given to entities with the ai state but no AIComponent of their own
+    pub config: AIConfig
+}
+
+impl AISystem {
+    pub fn new(config: AIConfig) -> Self {
+        Self {
+            config
         }
+    }
 
-        // If we are awaiting a teleport skip ahead,
-        // else teleport the monster to the teleport location
-        if self.awaiting_teleport && self.teleport_timer.elapsed().as_secs_f32() < 5.0 {
+    /// Move every AI controlled entity, advancing their timers by dt seconds
+    pub fn run(&mut self, world: &mut World, dt: f32) {
+        if world.frozen() {
             return;
-        } else if self.awaiting_teleport {
-            self.awaiting_teleport = false;
+        }
+
+        let ids: Vec<usize> = (0..world.ais.len())
+            .filter(|&id| id != world.player_id && world.states[id].contains(&world.state_names.ai))
+            .collect();
+
+        for id in ids {
+            let mut ai = world.ais[id].take().unwrap_or_else(|| AIComponent::new(self.config.clone()));
+            ai.advance(dt);
+            self.run_entity(world, id, &mut ai);
+            world.ais[id] = Some(ai);
+        }
+    }
+
+    /// Move a single AI controlled entity
+    fn run_entity(&mut self, world: &mut World, id: usize, ai: &mut AIComponent) {
+        let pid = world.player_id;
+
+        if let Some(home) = ai.config.home_world.clone() {
+            // Check if the entity needs to be loaded back into its home world
+            if ai.current_world == home && world.current_world == home && world.positions[id].is_none() {
+                world.positions[id] = Some(PositionComponent::new(ai.home_pos.0, ai.home_pos.1));
+            }
+
+            // Check if player has moved to new world
+            if ai.current_world != world.current_world {
+                // If the new world is the home world, restore the entity's position
+                if world.current_world == home {
+                    ai.current_world = home.clone();
+                    world.positions[id] = Some(PositionComponent::new(ai.home_pos.0, ai.home_pos.1));
+                } else if let Some(pos) = world.positions[id].as_ref() {
+                    // Save home position
+                    ai.home_pos = (pos.x, pos.y);
+
+                    // If we are aggroed then we teleport after them,
+                    // else teleport to nearest idle location
+                    if world.states[id].contains(&world.state_names.aggro) {
+                        ai.awaiting_teleport = true;
+                        ai.teleport_location = footprint(world, pid).map(|r| (r.x, r.y)).unwrap_or(ai.home_pos);
+                        ai.teleport_wait = 0.0;
+                        ai.current_world = world.current_world.clone();
+                        debug!("AI: entity {} following player to {}", id, ai.current_world);
+                    } else if let Some(mindex) = ai.nearest_idle(ai.home_pos.0, ai.home_pos.1) {
+                        ai.home_pos.0 = ai.config.idle_path[mindex].0;
+                        ai.home_pos.1 = ai.config.idle_path[mindex].1;
+                        ai.next_idle = (mindex + 1) % ai.config.idle_path.len();
+                        ai.since_idle = 0.0;
+                    }
+
+                    // Remove entity from the world (temporarily)
+                    world.positions[id] = None;
+                    debug!("AI: entity {} left {}", id, home);
+                }
+            }
+
+            // If we are awaiting a teleport skip ahead,
+            // else teleport the entity to the teleport location
+            if ai.awaiting_teleport && ai.teleport_wait < ai.config.teleport_delay {
+                return;
+            } else if ai.awaiting_teleport {
+                ai.awaiting_teleport = false;
 
-            let height = world.physics[MID].as_ref().unwrap().hitbox.h;
+                let height = world.physics[id].as_ref().map(|p| p.hitbox.h).unwrap_or(0);
 
-            // Add monster back into the world at the correct location
-            world.positions[MID] = Some(PositionComponent::new(self.teleport_location.0, self.teleport_location.1 - height as f32));
+                // Add entity back into the world at the correct location
+                world.positions[id] = Some(PositionComponent::new(ai.teleport_location.0, ai.teleport_location.1 - height as f32));
+                debug!("AI: entity {} teleported to ({}, {})", id, ai.teleport_location.0, ai.teleport_location.1);
+            }
         }
 
+        // An entity away from the player's world, or without a body, only has its idle movement simulated
+        let present = world.positions[id].is_some() && world.physics[id].is_some();
+        let in_world = ai.config.home_world.is_none() || world.current_world == ai.current_world;
+
         // Check if can see player, if so set aggro to true, if aggro, then lost
-        if world.current_world == self.monster_world {
-            if self.player_visible(world) {
+        if in_world && present {
+            if self.player_visible(world, id) {
                 let (x, y) = {
-                    let pos = world.positions[PID].as_ref().unwrap();
+                    let pos = world.positions[pid].as_ref().unwrap();
                     (pos.x, pos.y)
                 };
 
-                if self.dist(world, x, y) < self.aggro_distance {
-                    world.states[MID].remove("lost");
-                    world.states[MID].remove("idle");
-                    world.states[MID].insert("aggro".into());
+                if self.dist(world, id, x, y) < ai.config.aggro_distance {
+                    if !world.states[id].contains(&world.state_names.aggro) {
+                        debug!("AI: entity {} spotted player -> {}", id, world.state_names.aggro);
+                    }
+
+                    world.states[id].remove(&world.state_names.lost);
+                    world.states[id].remove(&world.state_names.idle);
+                    world.states[id].insert(world.state_names.aggro.clone());
                 }
-            } else if world.states[MID].contains("aggro") {
-                self.last_aggro = Instant::now();
-                world.states[MID].remove("aggro");
-                world.states[MID].insert("lost".into());
+            } else if world.states[id].contains(&world.state_names.aggro) {
+                ai.since_aggro = 0.0;
+                world.states[id].remove(&world.state_names.aggro);
+                world.states[id].insert(world.state_names.lost.clone());
+                debug!("AI: entity {} {} -> {}", id, world.state_names.aggro, world.state_names.lost);
             }
         }
 
+        if world.states[id].contains(&world.state_names.idle) {
+            let at_home = match ai.config.home_world.as_ref() {
+                Some(home) => (world.current_world == *home, ai.current_world == *home),
+                None => (true, true)
+            };
 
-        if world.states[MID].contains("idle") {
-            if world.current_world == "lake" && self.monster_world == "lake" {
-                // Normal idle movement in the lake world
-                let (dest_x, dest_y, _) = self.idle_path[self.next_idle];
-                if self.dist(world, dest_x, dest_y) < 2.0 {
-                    self.next_idle += 1;
-                    self.next_idle %= self.idle_path.len();
-                    self.last_idle_time = Instant::now();
-                    return;
-                }
-
-                self.goto(world, dest_x, dest_y, 60.0);
-            } else if world.current_world != "lake" && self.monster_world != "lake" {
-                // Monster move back to teleport point, then deload
-                let dist = self.dist(world, self.teleport_location.0, self.teleport_location.1);
-
-                if dist < 2.0 {
-                    world.positions[MID] = None;
-                    self.monster_world = "lake".into();
-                } else {
-                    self.goto(world, self.teleport_location.0, self.teleport_location.1, 60.0);
+            match at_home {
+                (true, true) => {
+                    // Normal idle movement in the entity's own world
+                    if ai.config.idle_path.is_empty() || !present {
+                        if present {
+                            self.stop(world, id);
+                        }
+                        return;
+                    }
+
+                    let (dest_x, dest_y, _) = ai.config.idle_path[ai.next_idle];
+                    if self.dist(world, id, dest_x, dest_y) < 2.0 {
+                        ai.next_idle += 1;
+                        ai.next_idle %= ai.config.idle_path.len();
+                        ai.since_idle = 0.0;
+                        return;
+                    }
+
+                    self.goto(world, id, dest_x, dest_y, ai.config.idle_speed);
                 }
-            } else {
-                // Monster is in lake world while player is in room, simulate idle movement
-                if self.sim_dist() < 2.0 {
-                    self.next_idle += 1;
-                    self.next_idle %= self.idle_path.len();
-                    self.last_idle_time = Instant::now();
+                (false, false) => {
+                    // Entity moves back to teleport point, then deloads
+                    if !present {
+                        return;
+                    }
+
+                    let dist = self.dist(world, id, ai.teleport_location.0, ai.teleport_location.1);
+
+                    if dist < 2.0 {
+                        world.positions[id] = None;
+                        ai.current_world = ai.config.home_world.clone().unwrap();
+                    } else {
+                        self.goto(world, id, ai.teleport_location.0, ai.teleport_location.1, ai.config.idle_speed);
+                    }
                 }
+                _ => {
+                    // Entity is in its home world while player is elsewhere, simulate idle movement
+                    if ai.config.idle_path.is_empty() {
+                        return;
+                    }
 
-                // Linear interpolation between idle points based on idle time
-                let t = self.last_idle_time.elapsed().as_secs_f32() / self.idle_path[self.next_idle].2;
+                    if ai.sim_dist() < 2.0 {
+                        ai.next_idle += 1;
+                        ai.next_idle %= ai.config.idle_path.len();
+                        ai.since_idle = 0.0;
+                    }
 
-                let last_index = (self.next_idle - 1 + self.idle_path.len()) % self.idle_path.len();
+                    // Linear interpolation between idle points based on idle time
+                    let t = ai.since_idle / ai.config.idle_path[ai.next_idle].2;
 
-                let last_x = self.idle_path[last_index].0;
-                let last_y = self.idle_path[last_index].1;
-                let x = self.idle_path[self.next_idle].0;
-                let y = self.idle_path[self.next_idle].1;
+                    let last_index = (ai.next_idle + ai.config.idle_path.len() - 1) % ai.config.idle_path.len();
 
-                let delta_x = x-last_x;
-                let delta_y = y-last_y;
+                    let last_x = ai.config.idle_path[last_index].0;
+                    let last_y = ai.config.idle_path[last_index].1;
+                    let x = ai.config.idle_path[ai.next_idle].0;
+                    let y = ai.config.idle_path[ai.next_idle].1;
 
-                self.monster_lake_pos.0 = last_x + delta_x*t;
-                self.monster_lake_pos.1 = last_y + delta_y*t;
+                    let delta_x = x-last_x;
+                    let delta_y = y-last_y;
+
+                    ai.home_pos.0 = last_x + delta_x*t;
+                    ai.home_pos.1 = last_y + delta_y*t;
+                }
             }
-        } else if world.states[MID].contains("aggro") {
-            let (x, y) = {
-                let rect = world.physics[PID].as_ref().unwrap().hitbox
-                    .after_position(world.positions[PID].as_ref().unwrap())
-                    .after_depth(world.physics[PID].as_ref().unwrap().depth);
-                (rect.x, rect.y)
+        } else if world.states[id].contains(&world.state_names.aggro) && present {
+            let (x, y) = match footprint(world, pid) {
+                Some(rect) => (rect.x, rect.y),
+                None => return
             };
 
-            let speed = 54.0 + 20.0 * self.last_pathfind.elapsed().as_secs_f32().mul(5.0).sin();
+            let speed = ai.config.chase_speed + ai.config.chase_wobble * ai.since_pathfind.mul(5.0).sin();
 
-            self.goto(world, x, y, speed);
-        } else if world.states[MID].contains("lost") {
-            // Wait, and then return to idle
-            self.stop(world);
-            if self.last_aggro.elapsed().as_secs_f32() > self.lost_delay {
-                world.states[MID].remove("lost");
-                world.states[MID].insert("idle".into());
-
-                let mindex = self.idle_path.iter()
-                    .enumerate()
-                    .map(|(i, (x, y, _))| {
-                        (i, ((y-self.monster_lake_pos.1).powi(2) + (x-self.monster_lake_pos.0).powi(2)).sqrt())
-                    })
-                .min_by(|a, b| {
-                    a.1.partial_cmp(&b.1).unwrap()
-                }).unwrap().0;
-
-                self.next_idle = (mindex + 1) % self.idle_path.len();
-                self.last_idle_time = Instant::now();
+            // Route around walls, heading straight for the player if no route is found
+            let reached = ai.waypoint.is_some_and(|(wx, wy)| self.dist(world, id, wx, wy) < 1.0);
+            if ai.waypoint.is_none() || reached || ai.since_route > ROUTE_INTERVAL {
+                ai.waypoint = self.route(world, id, x, y);
+                ai.since_route = 0.0;
             }
-        }
-    }
 
-    fn sim_dist(&self) -> f32 {
-        let x0 = self.idle_path[self.next_idle].0;
-        let y0 = self.idle_path[self.next_idle].1;
-        let x1 = self.monster_lake_pos.0;
-        let y1 = self.monster_lake_pos.1;
+            let (x, y) = ai.waypoint.unwrap_or((x, y));
+            self.goto(world, id, x, y, speed);
+        } else if world.states[id].contains(&world.state_names.lost) && present {
+            // Wait, and then return to idle
+            self.stop(world, id);
+            if ai.since_aggro > ai.config.lost_delay {
+                world.states[id].remove(&world.state_names.lost);
+                world.states[id].insert(world.state_names.idle.clone());
+                debug!("AI: entity {} {} -> {}", id, world.state_names.lost, world.state_names.idle);
+
+                // Entities with a home world pick up their path from where they were last at home
+                let (x, y) = if ai.config.home_world.is_some() {
+                    ai.home_pos
+                } else {
+                    let pos = world.positions[id].as_ref().unwrap();
+                    (pos.x, pos.y)
+                };
 
-        ((y1-y0).powi(2) + (x1-x0).powi(2)).sqrt()
+                if let Some(mindex) = ai.nearest_idle(x, y) {
+                    ai.next_idle = (mindex + 1) % ai.config.idle_path.len();
+                    ai.since_idle = 0.0;
+                }
+            }
+        }
     }
 
-    fn player_visible(&mut self, world: &mut World) -> bool {
-        let entities: Vec<(usize, (&mut HashSet<String>, &mut PositionComponent, &mut PhysicsComponent))> = world.physics_mut().0.collect();
-        let m_rect = entities[MID].1.2.hitbox
-            .after_position(entities[MID].1.1)
-            .after_depth(entities[MID].1.2.depth);
+    fn player_visible(&mut self, world: &World, id: usize) -> bool {
+        let pid = world.player_id;
 
-        let p_rect = entities[PID].1.2.hitbox
-            .after_position(entities[PID].1.1)
-            .after_depth(entities[PID].1.2.depth);
+        let (m_rect, p_rect) = match (footprint(world, id), footprint(world, pid)) {
+            (Some(m_rect), Some(p_rect)) => (m_rect, p_rect),
+            _ => return false
+        };
 
         let my = m_rect.y + m_rect.h as f32/2.0;
         let mx = m_rect.x + m_rect.w as f32/2.0;
         let py = p_rect.y + p_rect.h as f32/2.0;
         let px = p_rect.x + p_rect.w as f32/2.0;
 
-        for i in 2..entities.len() {
-            let mut footprint = entities[i].1.2.hitbox
-                .after_position(entities[i].1.1)
-                .after_depth(entities[i].1.2.depth);
+        for (i, (states, pos, phys)) in world.physics().0 {
+            if i == id || i == pid || !phys.occludes(states) {
+                continue;
+            }
+
+            let mut footprint = phys.hitbox
+                .after_position(pos)
+                .after_depth(phys.depth);
 
             if footprint.intersects_line(mx, my, px, py) {
                 return false;
@@ -246,43 +379,120 @@ impl AISystem {
         true
     }
 
-    fn goto(&mut self, world: &mut World, x: f32, y: f32, speed: f32) {
+    /// Next point on a route from an entity to a target around every blocking entity but itself and the player
+    fn route(&mut self, world: &World, id: usize, x: f32, y: f32) -> Option<(f32, f32)> {
+        let pid = world.player_id;
+        let rect = footprint(world, id)?;
+
+        let blocked: Vec<Rect> = world.physics().0
+            .filter(|(i, (_, _, phys))| *i != id && *i != pid && phys.blocks())
+            .map(|(_, (_, pos, phys))| phys.hitbox.after_position(pos).after_depth(phys.depth))
+            .collect();
+
+        shortest_path_segment(
+            (rect.x as i32, rect.y as i32),
+            (x as i32, y as i32),
+            PATH_STEP,
+            (rect.w, rect.h),
+            &blocked
+        ).map(|(x, y)| (x as f32, y as f32))
+    }
+
+    fn goto(&mut self, world: &mut World, id: usize, x: f32, y: f32, speed: f32) {
         let (curr_x, curr_y) = {
-            let rect = world.physics[MID].as_ref().unwrap().hitbox
-                .after_position(world.positions[MID].as_ref().unwrap())
-                .after_depth(world.physics[MID].as_ref().unwrap().depth);
+            let rect = footprint(world, id).unwrap();
             (rect.x, rect.y)
         };
 
-        let angle = (y-curr_y).atan2(x-curr_x);
+        let mut angle = (y-curr_y).atan2(x-curr_x);
+
+        // Slide perpendicular to the target until free of whatever we are wedged against
+        if world.states[id].contains(&world.state_names.stuck) {
+            angle += std::f32::consts::FRAC_PI_2;
+        }
         let mag = speed;
 
-        world.physics[MID].as_mut().unwrap().velocity.dir = angle;
-        world.physics[MID].as_mut().unwrap().velocity.mag = mag;
+        world.physics[id].as_mut().unwrap().velocity.dir = angle;
+        world.physics[id].as_mut().unwrap().velocity.mag = mag;
 
-        world.states[MID].insert("walking".into());
+        world.states[id].insert(world.state_names.walking.clone());
 
-        if world.physics[MID].as_mut().unwrap().velocity.x() > 0.1 {
-            world.graphics[MID].as_mut().unwrap().flipped = false;
-        } else {
-            world.graphics[MID].as_mut().unwrap().flipped = true;
+        if let Some(graphics) = world.graphics[id].as_mut() {
+            graphics.flipped = world.physics[id].as_ref().unwrap().velocity.x() <= 0.1;
         }
     }
 
-    fn dist(&mut self, world: &mut World, x: f32, y: f32) -> f32 {
+    fn dist(&mut self, world: &World, id: usize, x: f32, y: f32) -> f32 {
         let (curr_x, curr_y) = {
-            let rect = world.physics[MID].as_ref().unwrap().hitbox
-                .after_position(world.positions[MID].as_ref().unwrap())
-                .after_depth(world.physics[MID].as_ref().unwrap().depth);
+            let rect = footprint(world, id).unwrap();
             (rect.x, rect.y)
         };
 
         ((curr_y-y).powi(2) + (curr_x-x).powi(2)).sqrt()
     }
 
-    fn stop(&mut self, world: &mut World) {
-        world.physics[MID].as_mut().unwrap().velocity.mag = 0.0;
-        world.states[MID].remove("walking");
+    fn stop(&mut self, world: &mut World, id: usize) {
+        world.physics[id].as_mut().unwrap().velocity.mag = 0.0;
+        world.states[id].remove(&world.state_names.walking);
+    }
+}
+
+/// Footprint of an entity on the ground, none if it has no position or physics
+fn footprint(world: &World, id: usize) -> Option<Rect> {
+    match world.get_entity_physics(id) {
+        (Some(pos), Some(phys)) => Some(phys.hitbox.after_position(pos).after_depth(phys.depth)),
+        _ => None
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::with_texture_manager;
+    use crate::logger;
+    use crate::physics::PhysicsComponent;
+    use crate::world::WorldBuilder;
+
+    #[test]
+    fn spotting_the_player_is_logged() {
+        with_texture_manager(|texture_manager| {
+            let body = || PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 10, true);
+            let mut world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .entity(Some(PositionComponent::new(100.0, 0.0)), Some(body()), None, None, None, Vec::new())
+                .entity(Some(PositionComponent::new(0.0, 0.0)), Some(body()), None, None, None, vec!["ai".into(), "idle".into()])
+                .build();
+            let mut system = AISystem::new(AIConfig::default());
+
+            let logs = logger::capture(|| system.run(&mut world, 0.1));
+
+            assert!(world.states[1].contains("aggro"));
+            assert!(logs.contains(&"[DEBUG] AI: entity 1 spotted player -> aggro".to_string()), "logged {:?}", logs);
+        });
+    }
+
+    #[test]
+    fn doors_block_sight_until_opened() {
+        with_texture_manager(|texture_manager| {
+            let body = |h| PhysicsComponent::new(Rect::new(0.0, 0.0, 10, h), h, true);
+            let mut door = body(30);
+            door.see_through_states = vec!["open".into()];
+            let mut world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .entity(Some(PositionComponent::new(100.0, 0.0)), Some(body(10)), None, None, None, Vec::new())
+                .entity(Some(PositionComponent::new(0.0, 0.0)), Some(body(10)), None, None, None, vec!["ai".into()])
+                .entity(Some(PositionComponent::new(50.0, -10.0)), Some(door), None, None, None, Vec::new())
+                .build();
+            let mut system = AISystem::new(AIConfig::default());
+
+            assert!(!system.player_visible(&world, 1));
+
+            world.states[2].insert("open".into());
+            assert!(system.player_visible(&world, 1));
+
+            world.states[2].remove("open");
+            world.physics[2].as_mut().unwrap().occludes_sight = false;
+            assert!(system.player_visible(&world, 1));
+        });
+    }
+}
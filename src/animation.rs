@@ -1,7 +1,9 @@
 use crate::world::World;
-use std::time::Instant;
 use std::collections::HashMap;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
 use crate::state::Sequence;
+use crate::actions::set_origin;
+use log::warn;
 
 /// A Graphical Animation across multiple textures
 #[derive(Debug)]
@@ -12,10 +14,21 @@ pub struct Animation {
     period: f32,
     /// Current state index
     curr_tex_index: usize,
-    /// Time that the state last changed
-    last_switch: Instant,
+    /// Steps taken through the current cycle, the same as the state index unless ping-ponging
+    position: usize,
+    /// Seconds since the state last changed
+    elapsed: f32,
     /// Actions to run after animation completes
-    after: Option<Sequence>
+    after: Option<Sequence>,
+    /// Whether the animation starts over after its last state, otherwise it holds the last state
+    pub looping: bool,
+    /// Whether the animation plays its states forwards then backwards instead of only forwards
+    pub ping_pong: bool,
+    /// Whether a looping animation runs its after actions at the end of every loop,
+    /// otherwise only non looping animations run them
+    pub after_each_loop: bool,
+    /// Whether a non looping animation has played through
+    finished: bool
 }
 
 impl Animation {
@@ -25,25 +38,105 @@ impl Animation {
             states,
             period,
             curr_tex_index: 0,
-            last_switch: Instant::now(),
-            after
+            position: 0,
+            elapsed: 0.0,
+            after,
+            looping: true,
+            ping_pong: false,
+            after_each_loop: false,
+            finished: false
         }
     }
 
-    /// Check if the time since the last switch has exceeded the period
-    /// and switch to the next state if so
-    fn tick(&mut self) {
-        if self.last_switch.elapsed().as_secs_f32() > self.period {
-            if self.curr_tex_index == self.states.len()-1 {
-                self.curr_tex_index = 0;
+    /// Advance the animation by dt seconds, checking if the time since the last switch
+    /// has exceeded the period and advancing as many states as the elapsed time warrants if so.
+    /// Returns whether the animation wrapped back around to the start,
+    /// or for a non looping animation whether it finished during this tick
+    fn tick(&mut self, dt: f32) -> bool {
+        if self.finished {
+            return false;
+        }
+
+        self.elapsed += dt;
+
+        if self.elapsed > self.period {
+            let frames = if self.period <= 0.0 {
+                self.elapsed = 0.0;
+                1
             } else {
-                self.curr_tex_index += 1;
+                let frames = (self.elapsed / self.period) as usize;
+
+                // Keep the leftover time so the animation does not drift when frames are skipped
+                self.elapsed -= frames as f32 * self.period;
+                frames
+            };
+
+            let cycle = self.cycle_len();
+            let next = self.position + frames;
+
+            if !self.looping && next >= cycle {
+                // Hold the state the cycle ends on, the first when ping-ponging and the last otherwise
+                self.position = if self.ping_pong { 0 } else { cycle - 1 };
+                self.curr_tex_index = self.state_at(self.position);
+                self.finished = true;
+                return true;
             }
 
-            self.last_switch = Instant::now();
+            self.position = next % cycle;
+            self.curr_tex_index = self.state_at(self.position);
+
+            return next >= cycle;
+        }
+
+        false
+    }
+
+    /// Number of steps before the animation starts over.
+    /// Ping-ponging does not repeat the end states, so it takes 2(n-1) steps, or 1 for a single state
+    fn cycle_len(&self) -> usize {
+        let len = self.states.len();
+
+        if self.ping_pong && len > 1 {
+            2 * (len - 1)
+        } else {
+            len
+        }
+    }
+
+    /// State index shown at a step of the cycle, counting back down past the last state when ping-ponging
+    fn state_at(&self, position: usize) -> usize {
+        let len = self.states.len();
+
+        if position < len {
+            position
+        } else {
+            self.cycle_len() - position
         }
     }
 
+    /// Restart the animation from its first state
+    fn reset(&mut self) {
+        self.curr_tex_index = 0;
+        self.position = 0;
+        self.elapsed = 0.0;
+        self.finished = false;
+    }
+
+    /// Check if the after actions run when the animation completes a cycle
+    fn runs_after(&self) -> bool {
+        !self.looping || self.after_each_loop
+    }
+
+    /// Check if a non looping animation has played through and is holding its last state
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Current step through the cycle and seconds since it was switched to
+    pub fn progress(&self) -> (usize, f32) {
+        (self.position, self.elapsed)
+    }
+
     /// The current texture id
     fn current_texture(&self) -> usize {
         self.states[self.curr_tex_index].0
@@ -55,6 +148,26 @@ impl Animation {
     }
 }
 
+/// Find the animation for a state, trying `state_<dir>` for the 8-way direction of facing,
+/// then `state_<dir>` for the nearest cardinal direction, either as a compass point or as
+/// up, down, left, or right, then the plain state
+fn directional_key(animations: &HashMap<String, Animation>, state: &str, facing: Option<f32>) -> Option<String> {
+    let mut keys = Vec::new();
+
+    if let Some(facing) = facing {
+        // Screen coordinates grow downwards, so a positive angle points south
+        let octant = ((facing / FRAC_PI_4).round() as i32).rem_euclid(8) as usize;
+        let quadrant = ((facing / FRAC_PI_2).round() as i32).rem_euclid(4) as usize;
+
+        keys.push(format!("{}_{}", state, ["e", "se", "s", "sw", "w", "nw", "n", "ne"][octant]));
+        keys.push(format!("{}_{}", state, ["e", "s", "w", "n"][quadrant]));
+        keys.push(format!("{}_{}", state, ["right", "down", "left", "up"][quadrant]));
+    }
+
+    keys.push(state.to_string());
+    keys.into_iter().find(|key| animations.contains_key(key))
+}
+
 /// Animation state for a single Entity
 #[derive(Debug)]
 pub struct AnimationComponent {
@@ -63,7 +176,18 @@ pub struct AnimationComponent {
     /// based on the states of the entity
     animations: HashMap<String, Animation>,
     /// Currently selected animation's key
-    curr_key: Option<String>
+    curr_key: Option<String>,
+    /// Animation played once regardless of the entity's states
+    forced: Option<String>
+}
+
+/// Request from an action to change which animation an entity plays
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnimationRequest {
+    /// Play the animation of a state once regardless of the entity's states
+    Play(String),
+    /// Stop a played animation, returning to animations selected by state
+    Stop
 }
 
 impl AnimationComponent {
@@ -71,10 +195,33 @@ impl AnimationComponent {
     pub fn new(animations: HashMap<String, Animation>) -> AnimationComponent {
         AnimationComponent {
             animations,
-            curr_key: None
+            curr_key: None,
+            forced: None
         }
     }
 
+    /// Start or stop a played animation. Playing an animation the entity does not have stops the current one
+    pub fn request(&mut self, request: AnimationRequest) {
+        match request {
+            AnimationRequest::Play(key) => match self.animations.get_mut(&key) {
+                Some(animation) => {
+                    animation.reset();
+                    self.forced = Some(key);
+                }
+                None => self.forced = None
+            },
+            AnimationRequest::Stop => self.forced = None
+        }
+    }
+
+    /// Names of every dialog shown by the actions run after any of the animations
+    pub fn dialogs(&self) -> Vec<String> {
+        self.animations.values()
+            .filter_map(|a| a.after.as_ref())
+            .flat_map(|s| s.dialogs())
+            .collect()
+    }
+
     /// Get an animation by its state
     pub fn get(&self, key: &String) -> Option<&Animation> {
         self.animations.get(key)
@@ -109,9 +256,25 @@ impl AnimationSystem {
         AnimationSystem {}
     }
 
-    /// Play the most relevant animations based on state
-    pub fn run(&mut self, world: &mut World) {
+    /// Play the most relevant animations based on state, advancing them by dt seconds
+    pub fn run(&mut self, world: &mut World, dt: f32) {
+        if world.frozen() {
+            return;
+        }
+
+        // Start or stop animations requested by actions, in the order they were requested
+        for (id, request) in std::mem::take(&mut world.commands.animations) {
+            match world.animations.get_mut(id).and_then(|a| a.as_mut()) {
+                Some(animations) => animations.request(request),
+                None => warn!("Entity {} has no animations to play {:?}", id, request)
+            }
+        }
+
         for i in 0..world.states.len() {
+            if world.statics[i] {continue;}
+
+            let facing = world.get_entity_facing(i);
+
             let states = &mut world.states[i];
             let graphics = &mut world.graphics[i];
             let animations = &mut world.animations[i];
@@ -120,20 +283,58 @@ impl AnimationSystem {
             let graphics = graphics.as_mut().unwrap();
             let animations = animations.as_mut().unwrap();
 
-            // Find the state which determines the animation
+            // Play a forced animation through once before returning to state selection
+            if let Some(key) = animations.forced.clone() {
+                let animation = animations.animations.get_mut(&key).unwrap();
+
+                if animation.tick(dt) {
+                    // Run the actions once as the animation completes
+                    let runs_after = animation.runs_after();
+                    if let (Some(after), true) = (animation.after.as_mut(), runs_after) {
+                        let queued = world.commands.spawns.len();
+                        after.run_all(Some(i), states, &mut world.effects, &mut world.curr_dialog, &mut world.commands);
+                        set_origin(&mut world.commands.spawns[queued..], world.positions[i].clone());
+                    }
+
+                    animations.forced = None;
+                } else {
+                    graphics.texture_id = animation.current_texture();
+                    graphics.srcbox = animation.current_srcbox();
+                    animations.curr_key = Some(key);
+
+                    continue;
+                }
+            }
+
+            // Find the state which determines the animation,
+            // preferring a variant for the direction the entity faces
             for state in states.iter() {
-                let animation = animations.animations.get_mut(state);
+                let key = match directional_key(&animations.animations, state, facing) {
+                    Some(key) => key,
+                    None => continue
+                };
+
+                let animation = animations.animations.get_mut(&key);
 
                 if animation.is_some() {
                     let animation = animation.unwrap();
-                    animation.tick();
+
+                    // Play a finished non looping animation again once it is selected anew
+                    if animation.finished() && animations.curr_key.as_ref() != Some(&key) {
+                        animation.reset();
+                    }
+
+                    let completed = animation.tick(dt);
 
                     graphics.texture_id = animation.current_texture();
                     graphics.srcbox = animation.current_srcbox();
-                    animations.curr_key = Some(state.clone());
+                    animations.curr_key = Some(key);
 
-                    if animation.after.is_some() && animation.curr_tex_index == animation.states.len()-1 {
-                        animation.after.as_mut().unwrap().run_all(states, &mut world.effects, &mut world.curr_dialog);
+                    // Run the actions once each time the animation completes a cycle
+                    if animation.after.is_some() && completed && animation.runs_after() {
+                        let queued = world.commands.spawns.len();
+                        animation.after.as_mut().unwrap().run_all(Some(i), states, &mut world.effects, &mut world.curr_dialog, &mut world.commands);
+                        set_origin(&mut world.commands.spawns[queued..], world.positions[i].clone());
                     }
 
                     break;
@@ -143,3 +344,104 @@ impl AnimationSystem {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{PositionComponent, Rect};
+    use crate::graphics::{with_texture_manager, GraphicsComponent};
+    use crate::physics::PhysicsComponent;
+    use crate::vector::Vector;
+    use crate::world::WorldBuilder;
+
+    /// A looping animation of count frames, each showing the texture with its frame number as id
+    fn frames(count: usize, period: f32) -> Animation {
+        Animation::new((0..count).map(|i| (i, None)).collect(), period, None)
+    }
+
+    #[test]
+    fn a_long_frame_lands_where_continuous_playback_would() {
+        let mut lagged = frames(4, 0.1);
+        let mut smooth = frames(4, 0.1);
+
+        lagged.tick(0.55);
+        for _ in 0..11 {
+            smooth.tick(0.05);
+        }
+
+        // 5.5 periods in, a 4 frame loop is on its second frame with half a period spent there
+        assert_eq!(lagged.current_texture(), 1);
+        assert_eq!(lagged.current_texture(), smooth.current_texture());
+        assert!((lagged.progress().1 - 0.05).abs() < 1e-4);
+    }
+
+    #[test]
+    fn a_played_animation_finishes_before_states_choose_again() {
+        with_texture_manager(|texture_manager| {
+            let idle = Animation::new(vec![(10, None)], 0.1, None);
+            let open = Animation::new(vec![(20, None), (21, None)], 0.1, None);
+
+            let animations = AnimationComponent::new(HashMap::from([("idle".to_string(), idle), ("open".to_string(), open)]));
+            let mut world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .entity(
+                    Some(PositionComponent::new(0.0, 0.0)), None,
+                    Some(GraphicsComponent::new(0, Rect::new(0.0, 0.0, 8, 8), None)),
+                    Some(animations), None, vec!["idle".into()]
+                )
+                .build();
+            let mut system = AnimationSystem::new();
+            let texture = |world: &World| world.graphics[0].as_ref().unwrap().texture_id;
+
+            system.run(&mut world, 0.05);
+            assert_eq!(texture(&world), 10);
+
+            world.commands.animations.push((0, AnimationRequest::Play("open".into())));
+            system.run(&mut world, 0.05);
+            assert_eq!(texture(&world), 20);
+            system.run(&mut world, 0.1);
+            assert_eq!(texture(&world), 21);
+
+            // Once through, the entity's idle state picks its animation again
+            system.run(&mut world, 0.1);
+            assert_eq!(texture(&world), 10);
+            system.run(&mut world, 0.1);
+            assert_eq!(texture(&world), 10);
+        });
+    }
+
+    #[test]
+    fn walking_picks_the_animation_for_each_cardinal() {
+        with_texture_manager(|texture_manager| {
+            let animations = AnimationComponent::new(HashMap::from([
+                ("walk".to_string(), frames(1, 0.1)),
+                ("walk_e".to_string(), Animation::new(vec![(1, None)], 0.1, None)),
+                ("walk_s".to_string(), Animation::new(vec![(2, None)], 0.1, None)),
+                ("walk_w".to_string(), Animation::new(vec![(3, None)], 0.1, None)),
+                ("walk_n".to_string(), Animation::new(vec![(4, None)], 0.1, None)),
+            ]));
+            let mut world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .entity(
+                    Some(PositionComponent::new(0.0, 0.0)),
+                    Some(PhysicsComponent::new(Rect::new(0.0, 0.0, 8, 8), 0, false)),
+                    Some(GraphicsComponent::new(9, Rect::new(0.0, 0.0, 8, 8), None)),
+                    Some(animations), None, vec!["walk".into()]
+                )
+                .build();
+            let mut system = AnimationSystem::new();
+            let texture = |world: &World| world.graphics[0].as_ref().unwrap().texture_id;
+
+            // Without ever having moved there is no facing, so the plain animation plays
+            system.run(&mut world, 0.05);
+            assert_eq!(texture(&world), 0);
+
+            // Screen coordinates grow downwards, so a quarter turn clockwise faces south
+            for (quarter, expected) in [(0.0, 1), (1.0, 2), (2.0, 3), (3.0, 4), (-1.0, 4)] {
+                world.physics[0].as_mut().unwrap().velocity = Vector::new(quarter * FRAC_PI_2, 10.0);
+                system.run(&mut world, 0.05);
+                assert_eq!(texture(&world), expected, "facing {} quarter turns", quarter);
+            }
+        });
+    }
+}
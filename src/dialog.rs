@@ -1,51 +1,180 @@
 use std::collections::HashSet;
+use crate::actions::Commands;
 use crate::effect::Effect;
 
 use crate::state::Sequence;
 
+/// An option the player can pick while a message is shown
+#[derive(Debug)]
+pub struct Choice {
+    /// Text of the option
+    pub text: String,
+    /// Index of the message shown once picked, closing the dialog if none
+    pub goto: Option<usize>,
+    /// Actions to run once picked
+    pub actions: Option<Sequence>
+}
+
 /// Represents a Dialog interaction with the player
 #[derive(Debug)]
 pub struct Dialog {
     /// Messages to display, in order that they will be displayed
     messages: Vec<String>,
+    /// Options offered by each message, empty for a message which simply advances
+    choices: Vec<Vec<Choice>>,
+    /// Name of who says each message and the texture id of their portrait, if any
+    speakers: Vec<(Option<String>, Option<usize>)>,
     /// Index of the current message to display
     curr_msg: usize,
+    /// Index of the highlighted choice of the current message
+    selected: usize,
+    /// Number of characters of the current message shown so far, fractional between frames
+    shown: f32,
     /// Actions to run after
     /// Note: state changes are nonsensical and have no effect when run after a dialog
     /// Use an effect instead
-    after: Option<Sequence>
+    after: Option<Sequence>,
+    /// Whether physics, animations, and AI pause while this dialog is shown
+    pub freeze_world: bool
 }
 
 impl Dialog {
     /// Create a new Dialog
-    pub fn new(messages: Vec<String>, after: Option<Sequence>) -> Dialog {
+    pub fn new(messages: Vec<String>, after: Option<Sequence>, freeze_world: bool) -> Dialog {
         Dialog {
+            choices: messages.iter().map(|_| Vec::new()).collect(),
+            speakers: messages.iter().map(|_| (None, None)).collect(),
             messages,
             curr_msg: 0,
-            after
+            selected: 0,
+            shown: 0.0,
+            after,
+            freeze_world
         }
     }
 
     /// Switch the dialog to the next message
     pub fn next(&mut self) -> String {
+        if self.messages.is_empty() {
+            return String::new();
+        }
+
         let msg = self.messages[self.curr_msg].clone();
         self.curr_msg = (self.curr_msg + 1) % self.messages.len();
+        self.selected = 0;
+        self.shown = 0.0;
         msg
     }
 
-    /// Check if the dialog box has shown all messages
+    /// Return to the first message, as when the dialog is closed early
+    pub fn reset(&mut self) {
+        self.curr_msg = 0;
+        self.selected = 0;
+        self.shown = 0.0;
+    }
+
+    /// Show more characters of the current message, as the typewriter effect moves along
+    pub fn reveal(&mut self, chars: f32) {
+        self.shown += chars;
+    }
+
+    /// Show the whole current message at once
+    pub fn reveal_all(&mut self) {
+        self.shown = f32::INFINITY;
+    }
+
+    /// Check if the whole current message is shown
+    pub fn revealed(&self) -> bool {
+        self.shown >= self.msg().chars().count() as f32
+    }
+
+    /// Part of the current message shown so far
+    pub fn visible_msg(&self) -> String {
+        self.msg().chars().take(self.shown as usize).collect()
+    }
+
+    /// Set the options offered by a message
+    pub fn set_choices(&mut self, message: usize, choices: Vec<Choice>) {
+        self.choices[message] = choices;
+    }
+
+    /// Set who says a message and the texture id of their portrait
+    pub fn set_speaker(&mut self, message: usize, speaker: Option<String>, portrait: Option<usize>) {
+        self.speakers[message] = (speaker, portrait);
+    }
+
+    /// Name of who says the current message, if anyone
+    pub fn speaker(&self) -> Option<&String> {
+        self.speakers.get(self.curr_msg).and_then(|s| s.0.as_ref())
+    }
+
+    /// Texture id of the portrait shown beside the current message, if any
+    pub fn portrait(&self) -> Option<usize> {
+        self.speakers.get(self.curr_msg).and_then(|s| s.1)
+    }
+
+    /// Options offered by the current message, empty if it simply advances
+    pub fn choices(&self) -> &[Choice] {
+        self.choices.get(self.curr_msg).map(|c| c.as_slice()).unwrap_or(&[])
+    }
+
+    /// Index of the highlighted choice
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Move the highlight by offset choices, wrapping around at either end
+    pub fn select(&mut self, offset: i32) {
+        let count = self.choices().len() as i32;
+        if count > 0 {
+            self.selected = (self.selected as i32 + offset).rem_euclid(count) as usize;
+        }
+    }
+
+    /// Pick the highlighted choice, running its actions and moving to its message.
+    /// Returns false if the choice ends the dialog
+    pub fn choose(&mut self, effects: &mut Vec<Effect>, curr_dialog: &mut Option<String>, commands: &mut Commands) -> bool {
+        let choice = match self.choices.get_mut(self.curr_msg).and_then(|c| c.get_mut(self.selected)) {
+            Some(choice) => choice,
+            None => return false
+        };
+
+        if let Some(sequence) = &mut choice.actions {
+            sequence.run_all(None, &mut HashSet::new(), effects, curr_dialog, commands);
+        }
+
+        match choice.goto {
+            Some(message) => {
+                self.curr_msg = message;
+                self.selected = 0;
+                self.shown = 0.0;
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Check if the dialog box has shown all messages, including the whole of the last one
     pub fn finished(&self) -> bool {
-        self.messages.len()-1 == self.curr_msg
+        self.curr_msg + 1 >= self.messages.len() && self.revealed()
     }
 
-    /// Get current message
+    /// Get current message, empty if the dialog has no messages
     pub fn msg(&self) -> String {
-        self.messages[self.curr_msg].clone()
+        self.messages.get(self.curr_msg).cloned().unwrap_or_default()
+    }
+
+    /// Names of every dialog shown by the actions run after this one
+    pub fn dialogs(&self) -> Vec<String> {
+        self.after.iter()
+            .chain(self.choices.iter().flatten().filter_map(|c| c.actions.as_ref()))
+            .flat_map(|s| s.dialogs())
+            .collect()
     }
 
-    pub fn run_after(&mut self, effects: &mut Vec<Effect>, curr_dialog: &mut Option<String>) {
+    pub fn run_after(&mut self, effects: &mut Vec<Effect>, curr_dialog: &mut Option<String>, commands: &mut Commands) {
         if let Some(sequence) = &mut self.after {
-            sequence.run_all(&mut HashSet::new(), effects, curr_dialog);
+            sequence.run_all(None, &mut HashSet::new(), effects, curr_dialog, commands);
         }
     }
 }
@@ -1,26 +1,93 @@
-use std::time::Instant;
-use crate::{geometry::Rect, world::World};
+use std::collections::{HashMap, HashSet};
+use crate::{geometry::{Circle, Rect}, world::World, state::Sequence};
+
+/// How much of an entity must be inside an effect for the effect to apply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlapMode {
+    /// Any overlap at all
+    Intersect,
+    /// The entity's footprint lies entirely inside the effect
+    Contains,
+    /// The center of the entity's footprint lies inside the effect
+    CenterInside
+}
+
+impl OverlapMode {
+    /// Parse an overlap mode from its name
+    pub fn from_name(name: &str) -> Option<OverlapMode> {
+        match name {
+            "intersect" => Some(OverlapMode::Intersect),
+            "contains" => Some(OverlapMode::Contains),
+            "center_inside" => Some(OverlapMode::CenterInside),
+            _ => None
+        }
+    }
+
+    /// Check if a footprint is inside an effect's rectangle according to this mode
+    pub fn applies(&self, rect: Rect, footprint: Rect) -> bool {
+        match self {
+            OverlapMode::Intersect => footprint.has_intersection(rect),
+            OverlapMode::Contains => rect.contains(footprint),
+            OverlapMode::CenterInside => {
+                let (x, y) = footprint.center();
+                rect.contains_point(x, y)
+            }
+        }
+    }
+
+    /// Check if a footprint is inside an effect's circle according to this mode
+    pub fn applies_circle(&self, circle: Circle, footprint: Rect) -> bool {
+        match self {
+            OverlapMode::Intersect => circle.intersects_rect(&footprint),
+            OverlapMode::Contains => circle.contains_rect(&footprint),
+            OverlapMode::CenterInside => {
+                let (x, y) = footprint.center();
+                circle.contains_point(x, y)
+            }
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct EffectSpawner {
     adds: Vec<String>,
     removes: Vec<String>,
     ttl: Option<f32>,
-    rect: Rect
+    rect: Rect,
+    on_expire: Option<Sequence>,
+    /// Circle the spawned effect covers instead of its rectangle, if any
+    pub circle: Option<Circle>,
+    /// How much of an entity must be inside the spawned effect
+    pub overlap_mode: OverlapMode,
+    /// Change per second to the stats of entities inside the spawned effect
+    pub stat_deltas: HashMap<String, f32>
 }
 
 impl EffectSpawner {
-    pub fn new(adds: Vec<String>, removes: Vec<String>, rect: Rect, ttl: Option<f32>) -> EffectSpawner {
+    pub fn new(adds: Vec<String>, removes: Vec<String>, rect: Rect, ttl: Option<f32>, on_expire: Option<Sequence>) -> EffectSpawner {
         EffectSpawner {
             adds,
             removes,
             ttl,
-            rect
+            rect,
+            on_expire,
+            circle: None,
+            overlap_mode: OverlapMode::Intersect,
+            stat_deltas: HashMap::new()
         }
     }
 
     pub fn spawn(&self) -> Effect {
-        Effect::new(self.adds.clone(), self.removes.clone(), self.rect, self.ttl)
+        let mut effect = Effect::new(self.adds.clone(), self.removes.clone(), self.rect, self.ttl, self.on_expire.clone());
+        effect.circle = self.circle;
+        effect.overlap_mode = self.overlap_mode;
+        effect.stat_deltas = self.stat_deltas.clone();
+        effect
+    }
+
+    /// Names of every dialog the spawned effect can show once it expires
+    pub fn dialogs(&self) -> Vec<String> {
+        self.on_expire.as_ref().map(|s| s.dialogs()).unwrap_or_default()
     }
 }
 
@@ -33,30 +100,85 @@ pub struct Effect {
     pub adds: Vec<String>,
     /// Name of the states this effect removes
     pub removes: Vec<String>,
-    /// Time effect was created
-    created: Instant,
+    /// Seconds the effect has existed
+    age: f32,
     /// Time that the effect lasts, in seconds
     ttl: Option<f32>,
     /// Rectangle for which the effect is affective
-    pub rect: Rect
+    pub rect: Rect,
+    /// Circle for which the effect is affective instead of its rectangle, if any
+    pub circle: Option<Circle>,
+    /// How much of an entity must be inside the rectangle or circle for the effect to apply
+    pub overlap_mode: OverlapMode,
+    /// Change per second to the stats of entities inside the effect
+    pub stat_deltas: HashMap<String, f32>,
+    /// World and entrance the player is moved to once inside the effect, such as an exit
+    pub change_world: Option<(String, String)>,
+    /// Actions to run once the effect expires
+    /// Note: like dialogs, state changes have no effect when run after an effect expires
+    on_expire: Option<Sequence>
 }
 
 impl Effect {
     /// Create a new Effect
-    pub fn new(adds: Vec<String>, removes: Vec<String>, rect: Rect, ttl: Option<f32>) -> Effect {
+    pub fn new(adds: Vec<String>, removes: Vec<String>, rect: Rect, ttl: Option<f32>, on_expire: Option<Sequence>) -> Effect {
         Effect {
             adds,
             removes,
             ttl,
             rect,
-            created: Instant::now()
+            circle: None,
+            on_expire,
+            overlap_mode: OverlapMode::Intersect,
+            stat_deltas: HashMap::new(),
+            change_world: None,
+            age: 0.0
         }
     }
 
+    /// Check if an entity's footprint is inside this effect
+    pub fn applies_to(&self, footprint: Rect) -> bool {
+        match self.circle {
+            Some(circle) => self.overlap_mode.applies_circle(circle, footprint),
+            None => self.overlap_mode.applies(self.rect, footprint)
+        }
+    }
+
+    /// Smallest rectangle containing the area of this effect
+    pub fn bounds(&self) -> Rect {
+        self.circle.map(|c| c.bounds()).unwrap_or(self.rect)
+    }
+
+    /// Move an effect defined relative to an entity onto that entity's footprint.
+    /// A rectangle is offset from the footprint's corner and grown by its size,
+    /// while a circle is offset from the footprint's center
+    pub fn place_around(&mut self, footprint: Rect) {
+        self.rect.x += footprint.x;
+        self.rect.y += footprint.y;
+        self.rect.w += footprint.w;
+        self.rect.h += footprint.h;
+
+        if let Some(circle) = self.circle.as_mut() {
+            let (x, y) = footprint.center();
+            circle.x += x;
+            circle.y += y;
+        }
+    }
+
+    /// Names of every dialog this effect can show once it expires
+    pub fn dialogs(&self) -> Vec<String> {
+        self.on_expire.as_ref().map(|s| s.dialogs()).unwrap_or_default()
+    }
+
+    /// Seconds left before the effect finishes, none if it lasts forever
+    pub fn remaining(&self) -> Option<f32> {
+        self.ttl.map(|ttl| (ttl - self.age).max(0.0))
+    }
+
     /// Check if the effect has finished
     pub fn finished(&self) -> bool {
         if self.ttl.is_none() { return false; }
-        self.created.elapsed().as_secs_f32() > self.ttl.unwrap()
+        self.age > self.ttl.unwrap()
     }
 }
 
@@ -68,15 +190,141 @@ impl EffectSystem {
         EffectSystem {}
     }
 
-    /// Remove any effects in the world which
+    /// Age every effect by dt seconds and remove any effects in the world which
     /// have finished, then apply the appropriate states
-    /// to every entity inside each effect
-    pub fn run(&mut self, world: &mut World) {
-        world.apply_effects();
-
-        world.effects = world.effects.iter()
-            .filter(|e| !e.finished())
-            .map(|e| e.clone())
-            .collect();
+    /// and dt seconds of stat changes to every entity inside each effect.
+    /// Effects which finish run their on expire actions once as they are removed
+    pub fn run(&mut self, world: &mut World, dt: f32) {
+        for effect in world.effects.iter_mut() {
+            effect.age += dt;
+        }
+
+        world.apply_effects(dt);
+
+        let (finished, effects): (Vec<Effect>, Vec<Effect>) = world.effects.drain(..)
+            .partition(|e| e.finished());
+
+        world.effects = effects;
+
+        for mut effect in finished {
+            if let Some(sequence) = &mut effect.on_expire {
+                sequence.run_all(None, &mut HashSet::new(), &mut world.effects, &mut world.curr_dialog, &mut world.commands);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::{Action, PlaySound};
+    use crate::geometry::PositionComponent;
+    use crate::graphics::with_texture_manager;
+    use crate::physics::PhysicsComponent;
+    use crate::stats::StatsComponent;
+    use crate::world::WorldBuilder;
+
+    fn timed_effect(ttl: Option<f32>) -> Effect {
+        Effect::new(vec!["burning".to_string()], Vec::new(), Rect::new(0.0, 0.0, 10, 10), ttl, None)
+    }
+
+    #[test]
+    fn remaining_ttl_follows_the_game_clock() {
+        with_texture_manager(|texture_manager| {
+            let mut world = WorldBuilder::new(texture_manager).current_world("test").effect(timed_effect(Some(1.0))).build();
+            let mut system = EffectSystem::new();
+
+            // Only the dt given to the system counts, however long the frames really took
+            system.run(&mut world, 0.25);
+            assert_eq!(world.effects[0].remaining(), Some(0.75));
+            system.run(&mut world, 0.5);
+            assert_eq!(world.effects[0].remaining(), Some(0.25));
+
+            system.run(&mut world, 0.5);
+            assert!(world.effects.is_empty());
+        });
+    }
+
+    #[test]
+    fn effects_without_a_ttl_have_no_remaining_time() {
+        let effect = timed_effect(None);
+
+        assert_eq!(effect.remaining(), None);
+        assert!(!effect.finished());
+    }
+
+    #[test]
+    fn expired_effects_run_their_expire_actions_once() {
+        with_texture_manager(|texture_manager| {
+            let on_expire = Sequence::new(vec![(0.0, Box::new(PlaySound { path: "pop.wav".into() }) as Box<dyn Action>)]);
+            let effect = Effect::new(Vec::new(), Vec::new(), Rect::new(0.0, 0.0, 10, 10), Some(0.25), Some(on_expire));
+            let mut world = WorldBuilder::new(texture_manager).current_world("test").effect(effect).build();
+            let mut system = EffectSystem::new();
+
+            for _ in 0..10 {
+                system.run(&mut world, 0.1);
+            }
+
+            assert!(world.effects.is_empty());
+            assert_eq!(world.commands.sounds, vec!["pop.wav".to_string()]);
+        });
+    }
+
+    #[test]
+    fn ambient_effects_reach_every_entity() {
+        with_texture_manager(|texture_manager| {
+            let body = || PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 10, true);
+            let mut world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .entity(Some(PositionComponent::new(0.0, 0.0)), Some(body()), None, None, None, Vec::new())
+                .entity(Some(PositionComponent::new(5000.0, 3000.0)), Some(body()), None, None, None, Vec::new())
+                .entity(Some(PositionComponent::new(-800.0, 40.0)), None, None, None, None, vec!["lit".into()])
+                .build();
+            world.ambient.push(Effect::new(vec!["dark".into()], vec!["lit".into()], Rect::new(0.0, 0.0, 0, 0), None, None));
+
+            world.apply_effects(0.1);
+
+            assert!(world.states.iter().all(|states| states.contains("dark") && !states.contains("lit")));
+        });
+    }
+
+    #[test]
+    fn overlap_modes_at_a_straddled_boundary() {
+        let tile = Rect::new(0.0, 0.0, 20, 20);
+        let barely_in = Rect::new(18.0, 5.0, 10, 10);
+        let mostly_in = Rect::new(12.0, 5.0, 10, 10);
+        let inside = Rect::new(5.0, 5.0, 10, 10);
+
+        let applies = |mode: OverlapMode| [barely_in, mostly_in, inside].map(|footprint| mode.applies(tile, footprint));
+
+        assert_eq!(applies(OverlapMode::Intersect), [true, true, true]);
+        assert_eq!(applies(OverlapMode::CenterInside), [false, true, true]);
+        assert_eq!(applies(OverlapMode::Contains), [false, false, true]);
+    }
+
+    #[test]
+    fn overlapping_stat_deltas_add_together() {
+        with_texture_manager(|texture_manager| {
+            let poison = || {
+                let mut cloud = Effect::new(Vec::new(), Vec::new(), Rect::new(0.0, 0.0, 40, 40), None, None);
+                cloud.stat_deltas.insert("hp".into(), -1.0);
+                cloud
+            };
+            let mut world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .entity(
+                    Some(PositionComponent::new(10.0, 10.0)),
+                    Some(PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 10, true)),
+                    None, None, None, Vec::new()
+                )
+                .effect(poison())
+                .effect(poison())
+                .build();
+            world.stats[0] = Some(StatsComponent::new(HashMap::from([("hp".to_string(), 10.0)])));
+
+            world.apply_effects(0.5);
+
+            assert_eq!(world.stats[0].as_ref().unwrap().get("hp"), Some(9.0));
+        });
     }
 }
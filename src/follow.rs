@@ -0,0 +1,201 @@
+use crate::geometry::Rect;
+use crate::world::World;
+
+/// Makes an entity follow another entity, such as a companion following the player
+#[derive(Debug, Clone)]
+pub struct FollowComponent {
+    /// Name of the entity to follow
+    pub target: String,
+    /// Distance from the target at which the entity stops
+    pub distance: f32,
+    /// Speed to move towards the target in pixels/second
+    pub speed: f32,
+    /// Number of frames between updates while far from the player, 1 to update every frame
+    pub update_interval: u32,
+    /// Distance from the player beyond which updates are throttled
+    pub throttle_distance: f32,
+    /// Frames since the last update
+    skipped: u32,
+    /// Seconds since the last update, made up for by the next one
+    elapsed: f32
+}
+
+impl FollowComponent {
+    /// Create a new FollowComponent
+    pub fn new(target: String, distance: f32, speed: f32) -> FollowComponent {
+        FollowComponent {
+            target,
+            distance,
+            speed,
+            update_interval: 1,
+            throttle_distance: 0.0,
+            skipped: 0,
+            elapsed: 0.0
+        }
+    }
+}
+
+/// System which steers following entities towards their targets
+#[derive(Default)]
+pub struct FollowSystem;
+
+impl FollowSystem {
+    /// Create a new FollowSystem
+    pub fn new() -> FollowSystem {
+        FollowSystem {}
+    }
+
+    /// Move every following entity towards its target, stopping once within the leash distance.
+    /// A throttled entity holds still between updates, then covers the ground of every frame since its last
+    pub fn run(&mut self, world: &mut World, dt: f32) {
+        if world.frozen() {
+            return;
+        }
+
+        for i in 0..world.states.len() {
+            let follow = match world.follows[i].as_ref() {
+                Some(follow) => follow.clone(),
+                None => continue
+            };
+
+            let target = match world.get_entity_by_name(&follow.target) {
+                Some(target) if target != i => target,
+                _ => continue
+            };
+
+            let (from, to) = match (world.get_entity_physics(i), world.get_entity_physics(target)) {
+                ((Some(pos), Some(phys)), (Some(target_pos), Some(target_phys))) => (
+                    phys.hitbox.after_position(pos).after_depth(phys.depth),
+                    target_phys.hitbox.after_position(target_pos).after_depth(target_phys.depth)
+                ),
+                _ => continue
+            };
+
+            // Update less often while far from the player
+            let throttled = follow.update_interval > 1 && player_distance(world, from).is_some_and(|d| d > follow.throttle_distance);
+            let counter = world.follows[i].as_mut().unwrap();
+            counter.skipped += 1;
+            counter.elapsed += dt;
+
+            if throttled && counter.skipped < counter.update_interval {
+                if let Some(physics) = world.physics[i].as_mut() {
+                    physics.velocity.mag = 0.0;
+                }
+                continue;
+            }
+
+            let elapsed = counter.elapsed;
+            counter.skipped = 0;
+            counter.elapsed = 0.0;
+
+            let dx = to.x - from.x;
+            let dy = to.y - from.y;
+            let dist = (dx.powi(2) + dy.powi(2)).sqrt();
+
+            let walking = world.state_names.walking.clone();
+            let physics = world.physics[i].as_mut().unwrap();
+
+            if dist > follow.distance {
+                // Move this frame as far as the time since the last update allows, without passing the leash
+                let travel = (follow.speed * elapsed).min(dist - follow.distance);
+                physics.velocity.dir = dy.atan2(dx);
+                physics.velocity.mag = if dt > 0.0 { travel / dt } else { 0.0 };
+                world.states[i].insert(walking);
+
+                if let Some(graphics) = world.graphics[i].as_mut() {
+                    graphics.flipped = dx < 0.0;
+                }
+            } else {
+                physics.velocity.mag = 0.0;
+                world.states[i].remove(&walking);
+            }
+        }
+    }
+}
+
+/// Distance from a footprint to the player's footprint, if the player has one
+fn player_distance(world: &World, from: Rect) -> Option<f32> {
+    let to = world.bounding_box(&[world.player_id])?;
+
+    Some(((to.x - from.x).powi(2) + (to.y - from.y).powi(2)).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::PositionComponent;
+    use crate::graphics::{with_texture_manager, TextureManager};
+    use crate::physics::{PhysicsComponent, PhysicsSystem};
+    use crate::world::WorldBuilder;
+
+    /// A world with the player at x and a follower at the origin, leashed to the player
+    fn leashed(texture_manager: TextureManager, player_x: f32, follow: FollowComponent) -> World {
+        let body = || PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 10, false);
+        let mut world = WorldBuilder::new(texture_manager)
+            .current_world("test")
+            .entity(Some(PositionComponent::new(player_x, 0.0)), Some(body()), None, None, None, Vec::new())
+            .entity(Some(PositionComponent::new(0.0, 0.0)), Some(body()), None, None, None, Vec::new())
+            .build();
+
+        world.set_entity_name(0, "player".into());
+        world.follows[1] = Some(follow);
+        world
+    }
+
+    fn x(world: &World, id: usize) -> f32 {
+        world.positions[id].as_ref().unwrap().x
+    }
+
+    #[test]
+    fn followers_close_in_and_stop_at_the_leash() {
+        with_texture_manager(|texture_manager| {
+            let mut world = leashed(texture_manager, 100.0, FollowComponent::new("player".into(), 30.0, 50.0));
+            let (mut follow, mut physics) = (FollowSystem::new(), PhysicsSystem::new());
+
+            follow.run(&mut world, 0.1);
+            physics.step(&mut world, 0.1);
+            assert!((x(&world, 1) - 5.0).abs() < 1e-3);
+            assert!(world.states[1].contains("walking"));
+
+            for _ in 0..40 {
+                follow.run(&mut world, 0.1);
+                physics.step(&mut world, 0.1);
+            }
+
+            assert!((x(&world, 1) - 70.0).abs() < 1e-3, "stopped at {}", x(&world, 1));
+            assert!(!world.states[1].contains("walking"));
+        });
+    }
+
+    #[test]
+    fn throttled_followers_cover_the_same_ground() {
+        // Where a follower far from the player is after each of 12 frames, updating every interval frames
+        let positions = |interval: u32| {
+            let mut positions = Vec::new();
+
+            with_texture_manager(|texture_manager| {
+                let mut follow = FollowComponent::new("player".into(), 30.0, 50.0);
+                follow.update_interval = interval;
+                let mut world = leashed(texture_manager, 1000.0, follow);
+                let (mut follow, mut physics) = (FollowSystem::new(), PhysicsSystem::new());
+
+                for _ in 0..12 {
+                    follow.run(&mut world, 0.1);
+                    physics.step(&mut world, 0.1);
+                    positions.push(x(&world, 1));
+                }
+            });
+
+            positions
+        };
+
+        let (every, throttled) = (positions(1), positions(4));
+
+        // Between updates the throttled follower holds still, then catches up
+        assert_eq!(throttled[..3], [0.0; 3]);
+        for frame in [3, 7, 11] {
+            assert!((every[frame] - throttled[frame]).abs() < 1e-3, "frame {}: {} vs {}", frame, every[frame], throttled[frame]);
+        }
+        assert!((throttled[11] - 60.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,333 @@
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use log::{error, info};
+
+use crate::ai::AISystem;
+use crate::animation::AnimationSystem;
+use crate::effect::EffectSystem;
+use crate::follow::FollowSystem;
+use crate::graphics::GraphicsSystem;
+use crate::input::InputSystem;
+use crate::physics::PhysicsSystem;
+use crate::projectile::ProjectileSystem;
+use crate::sound::SoundSystem;
+use crate::state::StateSystem;
+use crate::world::World;
+
+/// Splits the time between frames into whole steps of a fixed length,
+/// carrying whatever is left over to the next frame
+#[derive(Debug, Clone, Default)]
+pub struct FixedStep {
+    /// Time not yet simulated
+    accumulator: f32
+}
+
+impl FixedStep {
+    /// Create a new FixedStep with no time carried over
+    pub fn new() -> FixedStep {
+        FixedStep::default()
+    }
+
+    /// Lengths of the steps to run for dt more seconds at hz steps per second.
+    /// A single step over all of dt is run if hz is not positive.
+    /// At most max_steps are run, dropping any time beyond them
+    /// so a long stall does not leave the game ever further behind
+    pub fn steps(&mut self, dt: f32, hz: f32, max_steps: u32) -> Vec<f32> {
+        if hz <= 0.0 {
+            return vec![dt];
+        }
+
+        let tick = 1.0 / hz;
+        self.accumulator += dt;
+
+        let mut steps = Vec::new();
+
+        // Allow a little float error so a frame of exactly n ticks runs all n
+        while self.accumulator >= tick - tick * 1e-3 {
+            if steps.len() as u32 >= max_steps {
+                self.accumulator = 0.0;
+                break;
+            }
+
+            steps.push(tick);
+            self.accumulator -= tick;
+        }
+
+        self.accumulator = self.accumulator.max(0.0);
+        steps
+    }
+}
+
+/// The world and every system, run together once per frame
+pub struct Game<'a> {
+    /// All game data and current state
+    pub world: World<'a>,
+    pub input_system: InputSystem,
+    pub physics_system: PhysicsSystem,
+    pub graphics_system: GraphicsSystem<'a>,
+    pub animation_system: AnimationSystem,
+    pub effects_system: EffectSystem,
+    pub state_system: StateSystem,
+    pub follow_system: FollowSystem,
+    pub projectile_system: ProjectileSystem,
+    pub sound_system: SoundSystem,
+    pub ai_system: AISystem,
+    /// Fixed steps physics, projectiles and states run in
+    fixed_step: FixedStep
+}
+
+impl<'a> Game<'a> {
+    /// Create a new Game, creating any systems which need no configuration
+    pub fn new(world: World<'a>, input_system: InputSystem, graphics_system: GraphicsSystem<'a>, sound_system: SoundSystem, ai_system: AISystem) -> Game<'a> {
+        Game {
+            world,
+            input_system,
+            physics_system: PhysicsSystem::new(),
+            graphics_system,
+            animation_system: AnimationSystem::new(),
+            effects_system: EffectSystem::new(),
+            state_system: StateSystem::new(),
+            follow_system: FollowSystem::new(),
+            projectile_system: ProjectileSystem::new(),
+            sound_system,
+            ai_system,
+            fixed_step: FixedStep::new()
+        }
+    }
+
+    /// Reload the current world from its file, logging why if it fails.
+    /// A failed reload leaves the world as it was
+    pub fn reload(&mut self) {
+        match self.world.reload() {
+            Ok(()) => info!("Reloaded {}", self.world.current_world),
+            Err(e) => error!("Could not reload {}: {}", self.world.current_world, e)
+        }
+    }
+
+    /// Reload the game file or the current world's file after it has been edited, logging why if it fails.
+    /// Reloading the game file also gives every system its new config
+    pub fn reload_from_file(&mut self, path: &str) {
+        match self.world.reload_from_file(path) {
+            Ok(Some((input_config, graphics_config, ai_system, sound_config))) => {
+                self.input_system.apply_config(input_config);
+                self.graphics_system.apply_config(graphics_config);
+                self.sound_system.apply_config(sound_config);
+                self.ai_system = ai_system;
+                self.graphics_system.snap_cameras(&self.world);
+                info!("Reloaded {}", path);
+            }
+            Ok(None) => info!("Reloaded {}", path),
+            Err(e) => error!("Could not reload {}: {}", path, e)
+        }
+    }
+
+    /// Type out more of the current dialog's message, or all of it if text appears at once
+    fn reveal_dialog(&mut self, dt: f32) {
+        let text_speed = self.graphics_system.text_speed();
+
+        if let Some(dialog) = self.world.curr_dialog.as_ref().and_then(|name| self.world.dialogs.get_mut(name)) {
+            match text_speed {
+                Some(speed) => dialog.reveal(speed * dt),
+                None => dialog.reveal_all()
+            }
+        }
+    }
+
+    /// Handle events and run every system once, moving the game forward by dt seconds.
+    /// Physics, projectiles and states run in fixed steps of the world's physics rate.
+    /// Returns false once the game has been asked to quit
+    pub fn step(&mut self, dt: f32, events: Vec<Event>) -> bool {
+        // Handle events
+        for event in events {
+            match event {
+                Event::Quit {..} |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    return false;
+                },
+                Event::Window { win_event: WindowEvent::Resized(_, _), .. } => {
+                    self.graphics_system.refresh();
+                }
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => {
+                    self.reload();
+                }
+                _ => {self.input_system.handle_event(event)}
+            }
+        }
+
+        // The world is paused while fading between worlds, so input cannot start another move
+        let transitioning = self.graphics_system.transitioning();
+
+        // Advance the game clock unless a dialog or transition has paused the world
+        if !self.world.frozen() && !transitioning {
+            self.world.elapsed += dt;
+        }
+
+        // Run all subsystems
+        if !transitioning {
+            self.input_system.run(&mut self.world, dt);
+            self.follow_system.run(&mut self.world, dt);
+
+            for tick in self.fixed_step.steps(dt, self.world.physics_hz, self.world.max_physics_steps) {
+                self.physics_system.step(&mut self.world, tick);
+                self.projectile_system.run(&mut self.world, tick);
+                self.state_system.run(&mut self.world, tick);
+            }
+
+            self.animation_system.run(&mut self.world, dt);
+            self.world.spawn_pending();
+            self.world.despawn_pending();
+        }
+        self.reveal_dialog(dt);
+        self.graphics_system.run(&mut self.world, dt);
+        if !transitioning {
+            self.effects_system.run(&mut self.world, dt);
+            self.sound_system.run(&mut self.world);
+        }
+
+        // Check if the player is being moved to another world, fading out before the swap
+        if let Some(destination) = self.world.commands.world_change.take() {
+            self.graphics_system.begin_transition(destination);
+        }
+
+        // Swap worlds once the screen is fully black
+        if let Some((name, entrance)) = self.graphics_system.advance_transition(dt) {
+            self.world.deload();
+
+            // The previous world is already gone, so a world which fails to load ends the game
+            if let Err(e) = self.world.load(&name, &entrance) {
+                error!("Could not load {}/{}: {}", name, entrance, e);
+                return false;
+            }
+
+            self.graphics_system.snap_cameras(&self.world);
+        }
+
+        if !transitioning {
+            self.ai_system.run(&mut self.world, dt);
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use sdl2::keyboard::Mod;
+    use crate::ai::AIConfig;
+    use crate::dialog::Dialog;
+    use crate::geometry::{PositionComponent, Rect};
+    use crate::graphics::{test_camera, test_config, with_graphics_system, with_texture_manager};
+    use crate::input::InputConfig;
+    use crate::physics::PhysicsComponent;
+    use crate::sound::SoundConfig;
+    use crate::vector::Vector;
+    use crate::world::WorldBuilder;
+
+    #[test]
+    fn fixed_step_carries_the_remainder_and_caps_catch_up() {
+        let mut fixed = FixedStep::new();
+
+        // Half a tick runs nothing until the next frame completes it
+        assert!(fixed.steps(0.005, 100.0, 8).is_empty());
+        assert_eq!(fixed.steps(0.025, 100.0, 8).len(), 3);
+
+        // A long stall only runs the most steps allowed and drops the rest
+        assert_eq!(fixed.steps(10.0, 100.0, 8).len(), 8);
+        assert!(fixed.steps(0.0, 100.0, 8).is_empty());
+
+        // Without a rate the whole frame is one step
+        assert_eq!(fixed.steps(0.3, 0.0, 8), vec![0.3]);
+    }
+
+    /// Run a test with a game of a single player in an empty world
+    fn with_game<F: FnOnce(&mut Game)>(test: F) {
+        with_graphics_system(test_config(vec![test_camera(64, 64, 1)]), |sdl, graphics_system, texture_manager| {
+            let world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .bounds(1000, 1000)
+                .entity(
+                    Some(PositionComponent::new(0.0, 0.0)),
+                    Some(PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 10, true)),
+                    None, None, None, Vec::new()
+                )
+                .build();
+            let input_system = InputSystem::new(InputConfig::new(), sdl.game_controller().unwrap());
+            let sound_system = SoundSystem::new(SoundConfig { sounds: HashMap::new() }, None);
+
+            test(&mut Game::new(world, input_system, graphics_system, sound_system, AISystem::new(AIConfig::default())));
+        });
+    }
+
+    #[test]
+    fn stepping_a_game_moves_the_player_until_it_quits() {
+        with_game(|game| {
+            let press = Event::KeyDown { timestamp: 0, window_id: 0, keycode: Some(Keycode::D), scancode: None, keymod: Mod::NOMOD, repeat: false };
+            assert!(game.step(0.1, vec![press]));
+            for _ in 0..3 {
+                assert!(game.step(0.1, Vec::new()));
+            }
+
+            let x = game.world.positions[0].as_ref().unwrap().x;
+            assert!(x > 0.0, "player stayed at {}", x);
+            assert!((game.world.elapsed - 0.4).abs() < 1e-4);
+            assert!(game.world.states[0].contains("walking"));
+
+            assert!(!game.step(0.1, vec![Event::Quit { timestamp: 0 }]));
+        });
+    }
+
+    #[test]
+    fn elapsed_time_follows_dt_and_pauses_with_the_world() {
+        with_game(|game| {
+            game.world.add_dialog("pause".into(), Dialog::new(vec!["Paused".into()], None, true));
+
+            for dt in [0.25, 0.5, 0.125] {
+                game.step(dt, Vec::new());
+            }
+            assert_eq!(game.world.elapsed, 0.875);
+
+            game.world.curr_dialog = Some("pause".into());
+            game.step(1.0, Vec::new());
+            assert_eq!(game.world.elapsed, 0.875);
+
+            game.world.curr_dialog = None;
+            game.step(0.125, Vec::new());
+            assert_eq!(game.world.elapsed, 1.0);
+        });
+    }
+
+    #[test]
+    fn physics_substeps_cover_the_same_distance_as_one_frame() {
+        with_texture_manager(|texture_manager| {
+            let mut moving = PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 10, true);
+            moving.velocity = Vector::new(0.3, 90.0);
+            let mut world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .bounds(1000, 1000)
+                .entity(Some(PositionComponent::new(100.0, 100.0)), Some(moving), None, None, None, Vec::new())
+                .build();
+            let mut physics = PhysicsSystem::new();
+            let frame = 1.0 / 60.0;
+
+            // Distance moved over a 60 Hz frame run as the given steps, starting from the same spot
+            let mut distance = |steps: Vec<f32>| {
+                world.positions[0] = Some(PositionComponent::new(100.0, 100.0));
+                for tick in steps {
+                    physics.step(&mut world, tick);
+                }
+                let end = world.positions[0].as_ref().unwrap();
+                ((end.x - 100.0).powi(2) + (end.y - 100.0).powi(2)).sqrt()
+            };
+
+            let ticks = FixedStep::new().steps(frame, 120.0, 8);
+            assert_eq!(ticks.len(), 2);
+
+            let substepped = distance(ticks);
+            let single = distance(vec![frame]);
+            assert!((substepped - 1.5).abs() < 1e-3, "moved {}", substepped);
+            assert!((substepped - single).abs() < 1e-3, "sub-steps moved {} but one step moved {}", substepped, single);
+        });
+    }
+}
@@ -45,6 +45,34 @@ impl Rect {
         return true;
     }
 
+    /// Check if another rectangle lies entirely inside this rectangle
+    pub fn contains(&self, other: Rect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.x + other.w as f32 <= self.x + self.w as f32
+            && other.y + other.h as f32 <= self.y + self.h as f32
+    }
+
+    /// Check if a point lies inside this rectangle
+    pub fn contains_point(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.w as f32 && y >= self.y && y < self.y + self.h as f32
+    }
+
+    /// Center point of this rectangle
+    pub fn center(&self) -> (f32, f32) {
+        (self.x + self.w as f32 / 2.0, self.y + self.h as f32 / 2.0)
+    }
+
+    /// Smallest rectangle containing both this rectangle and another
+    pub fn union(&self, other: Rect) -> Rect {
+        let left = self.x.min(other.x);
+        let top = self.y.min(other.y);
+        let right = (self.x + self.w as f32).max(other.x + other.w as f32);
+        let bottom = (self.y + self.h as f32).max(other.y + other.h as f32);
+
+        Rect::new(left, top, (right - left) as u32, (bottom - top) as u32)
+    }
+
     pub fn intersects_line(&mut self, x0: f32, y0: f32, x1: f32, y1: f32) -> bool {
         self.sdl2().intersect_line(
             Point::new(x0 as i32, y0 as i32),
@@ -75,6 +103,56 @@ impl Rect {
     }
 }
 
+/// Circle which exists inside the game world, such as the area of an explosion
+#[derive(Debug, Copy, Clone)]
+pub struct Circle {
+    pub x: f32,
+    pub y: f32,
+    pub r: f32
+}
+
+impl Circle {
+    /// Create a new circle from its center and radius
+    pub fn new(x: f32, y: f32, r: f32) -> Circle {
+        Circle {x, y, r}
+    }
+
+    /// Check if a point lies inside this circle
+    pub fn contains_point(&self, x: f32, y: f32) -> bool {
+        (x - self.x).powi(2) + (y - self.y).powi(2) <= self.r.powi(2)
+    }
+
+    /// Check if a rectangle lies entirely inside this circle
+    pub fn contains_rect(&self, rect: &Rect) -> bool {
+        let right = rect.x + rect.w as f32;
+        let bottom = rect.y + rect.h as f32;
+
+        [(rect.x, rect.y), (right, rect.y), (rect.x, bottom), (right, bottom)]
+            .iter()
+            .all(|(x, y)| self.contains_point(*x, *y))
+    }
+
+    /// Check if this circle intersects in any way with a rectangle
+    pub fn intersects_rect(&self, rect: &Rect) -> bool {
+        // Closest point of the rectangle to the center
+        let x = self.x.clamp(rect.x, rect.x + rect.w as f32);
+        let y = self.y.clamp(rect.y, rect.y + rect.h as f32);
+
+        self.contains_point(x, y)
+    }
+
+    /// Check if this circle intersects in any way with another circle
+    pub fn intersects_circle(&self, other: &Circle) -> bool {
+        (other.x - self.x).powi(2) + (other.y - self.y).powi(2) <= (self.r + other.r).powi(2)
+    }
+
+    /// Smallest rectangle containing this circle
+    pub fn bounds(&self) -> Rect {
+        let size = (self.r * 2.0).max(0.0) as u32;
+        Rect::new(self.x - self.r, self.y - self.r, size, size)
+    }
+}
+
 impl std::ops::Add<Rect> for Rect {
     type Output = Rect;
 
@@ -104,3 +182,29 @@ impl PositionComponent {
         self.y += vec.y();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(rect: Rect) -> (f32, f32, u32, u32) {
+        (rect.x, rect.y, rect.w, rect.h)
+    }
+
+    #[test]
+    fn union_covers_disjoint_rects() {
+        let a = Rect::new(0.0, 0.0, 10, 10);
+        let b = Rect::new(30.0, 20.0, 5, 5);
+
+        assert_eq!(parts(a.union(b)), (0.0, 0.0, 35, 25));
+        assert_eq!(parts(b.union(a)), (0.0, 0.0, 35, 25));
+    }
+
+    #[test]
+    fn union_of_overlapping_rects() {
+        let a = Rect::new(0.0, 0.0, 10, 10);
+
+        assert_eq!(parts(a.union(Rect::new(5.0, -5.0, 10, 10))), (0.0, -5.0, 15, 15));
+        assert_eq!(parts(a.union(Rect::new(2.0, 2.0, 4, 4))), (0.0, 0.0, 10, 10));
+    }
+}
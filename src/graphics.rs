@@ -1,9 +1,14 @@
 use std::collections::{HashSet, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
 use sdl2::pixels::Color;
-use sdl2::render::Canvas;
+use sdl2::render::{BlendMode, Canvas};
 use sdl2::render::TextureQuery;
 use sdl2::ttf::Font;
 use sdl2::ttf::Sdl2TtfContext;
+use sdl2::rwops::RWops;
+use log::warn;
 use sdl2::video::{Window, WindowContext};
 use sdl2::image::LoadTexture;
 use sdl2::render::Texture;
@@ -24,7 +29,13 @@ pub struct GraphicsComponent {
     /// Coordinates to render inside the game world
     pub renderbox: Rect,
     /// Whether to flip the texture
-    pub flipped: bool
+    pub flipped: bool,
+    /// Opacity of the texture, 255 is fully opaque
+    pub alpha: u8,
+    /// Color the texture is multiplied by, such as red for a damage flash
+    pub tint: Option<Color>,
+    /// Layer to draw in, higher layers are drawn over lower ones regardless of depth
+    pub z: i32
 }
 
 impl GraphicsComponent {
@@ -33,6 +44,9 @@ impl GraphicsComponent {
         GraphicsComponent {
             texture_id: tex_id,
             flipped: false,
+            alpha: 255,
+            tint: None,
+            z: 0,
             renderbox,
             srcbox
         }
@@ -47,41 +61,220 @@ pub struct Camera {
     /// Box that the player must reside in and the camera will move with the player
     pub player_box: Rect,
     /// Pixel scaling factor, ie conversion factor between world units and screen pixels
-    pub zoom: u32
+    pub zoom: u32,
+    /// Area of the window the camera renders into, the whole window if none
+    pub viewport: Option<sdl2::rect::Rect>,
+    /// Id of the entity the camera follows
+    pub target: usize,
+    /// How world coordinates map onto the screen
+    pub projection: Projection
+}
+
+/// Mapping of world coordinates onto the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projection {
+    /// World axes line up with the screen
+    TopDown,
+    /// World axes run diagonally with the standard 2:1 isometric ratio
+    Iso
 }
 
 impl Camera {
+    /// Area of the window the camera renders into
+    fn area(&self, (width, height): (u32, u32)) -> sdl2::rect::Rect {
+        self.viewport.unwrap_or_else(|| sdl2::rect::Rect::new(0, 0, width, height))
+    }
+
+    /// Screen coordinates of the top left corner of the camera's view
+    fn origin(&self, size: (u32, u32)) -> (i32, i32) {
+        let area = self.area(size);
+
+        (
+            area.x() + (area.width() as i32 - self.rect.w as i32) / 2,
+            area.y() + (area.height() as i32 - self.rect.h as i32) / 2
+        )
+    }
+
+    /// Screen rectangle of a background layer which scrolls by parallax times the camera's movement,
+    /// 0 staying still on screen and 1 moving with the world
+    fn layer_rect(&self, renderbox: Rect, parallax: f32, size: (u32, u32)) -> Rect {
+        let (origin_x, origin_y) = self.origin(size);
+        let left = origin_x as f32 - self.rect.x * parallax * self.zoom as f32;
+        let top = origin_y as f32 - self.rect.y * parallax * self.zoom as f32;
+
+        renderbox.after_position(&PositionComponent::new(left, top))
+    }
+
+    /// Screen offset of a world offset from the camera, before zoom
+    fn project(&self, x: f32, y: f32) -> (f32, f32) {
+        match self.projection {
+            Projection::TopDown => (x, y),
+            Projection::Iso => (x - y, (x + y) / 2.0)
+        }
+    }
+
+    /// Key to draw entities in order of, those with smaller keys drawn first
+    fn depth(&self, rect: Rect) -> i32 {
+        let bottom = rect.y + rect.h as f32;
+
+        match self.projection {
+            Projection::TopDown => bottom as i32,
+            Projection::Iso => (rect.x + bottom) as i32
+        }
+    }
+
+    /// Ids of the drawable entities in the order they are drawn, sorted by layer then the bottom of their rects,
+    /// breaking ties by id so equal baselines never swap
+    fn draw_order(&self, world: &World) -> Vec<usize> {
+        let mut drawables: Vec<(i32, i32, usize)> = world.graphics()
+            .map(|(id, (_, pos, graphics))| (graphics.z, self.depth(graphics.renderbox.after_position(pos)), id))
+            .collect();
+
+        drawables.sort();
+        drawables.into_iter().map(|(_, _, id)| id).collect()
+    }
+
     /// Find the new rectangle with respect to the view of the camera
-    fn view(&self, rect: Rect, (width, height): (u32, u32)) -> Rect {
-        let screen_x = (width - self.rect.w) / 2;
-        let screen_y = (height - self.rect.h) / 2;
+    fn view(&self, rect: Rect, size: (u32, u32)) -> Rect {
+        let (screen_x, screen_y) = self.origin(size);
+        let (x, y) = self.project(rect.x-self.rect.x, rect.y-self.rect.y);
 
         Rect::new(
-            (rect.x-self.rect.x) * self.zoom as f32 + screen_x as f32,
-            (rect.y-self.rect.y) * self.zoom as f32 + screen_y as f32,
+            x * self.zoom as f32 + screen_x as f32,
+            y * self.zoom as f32 + screen_y as f32,
             rect.w * self.zoom,
             rect.h * self.zoom
         )
     }
 
-    /// Cover the world outside the camera's view with black bars
+    /// World coordinates of the grid lines inside the camera's view, every spacing world units,
+    /// paired with their screen coordinates as (vertical lines, horizontal lines)
+    fn grid_lines(&self, spacing: u32, size: (u32, u32)) -> (Vec<(f32, i32)>, Vec<(f32, i32)>) {
+        if spacing == 0 {
+            return (Vec::new(), Vec::new());
+        }
+
+        let spacing = spacing as f32;
+        let (screen_x, screen_y) = self.origin(size);
+        let view_w = self.rect.w as f32 / self.zoom as f32;
+        let view_h = self.rect.h as f32 / self.zoom as f32;
+
+        let lines = |start: f32, len: f32, screen: i32| {
+            let mut lines = Vec::new();
+            let mut world = (start / spacing).ceil() * spacing;
+
+            while world <= start + len {
+                lines.push((world, ((world - start) * self.zoom as f32) as i32 + screen));
+                world += spacing;
+            }
+
+            lines
+        };
+
+        (lines(self.rect.x, view_w, screen_x), lines(self.rect.y, view_h, screen_y))
+    }
+
+    /// Make the Camera follow a given rectangle
+    fn follow(&mut self, rect: Rect, world_width: u32, world_height: u32) {
+        // Bounding box
+        let box_x_offset = self.player_box.x / self.zoom as f32;
+        let box_y_offset = self.player_box.y / self.zoom as f32;
+        let box_width = self.player_box.w as f32 / self.zoom as f32;
+        let box_height = self.player_box.h as f32 / self.zoom as f32;
+
+        let box_left = self.rect.x + box_x_offset;
+        let box_right = box_left + box_width;
+        let box_top = self.rect.y + box_y_offset;
+        let box_bottom = box_top + box_height;
+
+        let rect_left = rect.x;
+        let rect_right = rect.x + rect.w as f32;
+        let rect_top = rect.y;
+        let rect_bottom = rect.y + rect.h as f32;
+
+        if rect_left < box_left {
+            self.rect.x = rect_left - box_x_offset;
+        }
+
+        if rect_right > box_right {
+            self.rect.x = rect_right - box_width - box_x_offset;
+        }
+
+        if rect_top < box_top {
+            self.rect.y = rect_top - box_y_offset;
+        }
+
+        if rect_bottom > box_bottom {
+            self.rect.y = rect_bottom - box_height - box_y_offset;
+        }
+
+        self.clamp(world_width, world_height);
+    }
+
+    /// Center the camera on a given rectangle at once, rather than waiting for it to leave the player box
+    pub fn snap_to(&mut self, rect: Rect, world_width: u32, world_height: u32) {
+        let (center_x, center_y) = rect.center();
+        self.rect.x = center_x - self.rect.w as f32 / self.zoom as f32 / 2.0;
+        self.rect.y = center_y - self.rect.h as f32 / self.zoom as f32 / 2.0;
+
+        self.clamp(world_width, world_height);
+    }
+
+    /// Clamp camera to world bounds
+    fn clamp(&mut self, world_width: u32, world_height: u32) {
+        self.rect.x = self.rect.x.max(0.0);
+        self.rect.y = self.rect.y.max(0.0);
+        self.rect.x = (self.rect.x + self.rect.w as f32 / self.zoom as f32).min(world_width as f32) - (self.rect.w as f32 / self.zoom as f32);
+        self.rect.y = (self.rect.y + self.rect.h as f32 / self.zoom as f32).min(world_height as f32) - (self.rect.h as f32 / self.zoom as f32);
+    }
+
+    /// Cover the part of the camera's area outside its view with black bars
     fn render(&self, canvas: &mut Canvas<Window>) {
-        let (width, height) = canvas.output_size().unwrap();
-        let left_offset = (width - self.rect.w) / 2;
-        let top_offset = (height - self.rect.h) / 2;
-        let right_offset = width - left_offset;
-        let bottom_offset = height - top_offset;
+        let size = canvas.output_size().unwrap();
+        let area = self.area(size);
+        let (left, top) = self.origin(size);
+        let right = left + self.rect.w as i32;
+        let bottom = top + self.rect.h as i32;
+
+        let bars = [
+            (area.x(), area.y(), area.width() as i32, top - area.y()),
+            (area.x(), area.y(), left - area.x(), area.height() as i32),
+            (area.x(), bottom, area.width() as i32, area.bottom() - bottom),
+            (right, area.y(), area.right() - right, area.height() as i32)
+        ];
 
         let old_color = canvas.draw_color();
         canvas.set_draw_color((0, 0, 0));
-        canvas.fill_rect(sdl2::rect::Rect::new(0, 0, width, top_offset as u32)).unwrap();
-        canvas.fill_rect(sdl2::rect::Rect::new(0, 0, left_offset as u32, height)).unwrap();
-        canvas.fill_rect(sdl2::rect::Rect::new(0, bottom_offset as i32, width, top_offset as u32)).unwrap();
-        canvas.fill_rect(sdl2::rect::Rect::new(right_offset as i32, 0, left_offset as u32, height)).unwrap();
+        for (x, y, w, h) in bars {
+            if w > 0 && h > 0 {
+                canvas.fill_rect(sdl2::rect::Rect::new(x, y, w as u32, h as u32)).unwrap();
+            }
+        }
         canvas.set_draw_color(old_color);
     }
 }
 
+/// Parts of an area not covered by another rectangle, as up to four bars
+/// above, below, left, and right of the covered rectangle
+fn uncovered(area: sdl2::rect::Rect, covered: sdl2::rect::Rect) -> Vec<sdl2::rect::Rect> {
+    let covered = match area.intersection(covered) {
+        Some(covered) => covered,
+        None => return vec![area]
+    };
+
+    let bars = [
+        (area.x(), area.y(), area.width() as i32, covered.y() - area.y()),
+        (area.x(), covered.bottom(), area.width() as i32, area.bottom() - covered.bottom()),
+        (area.x(), covered.y(), covered.x() - area.x(), covered.height() as i32),
+        (covered.right(), covered.y(), area.right() - covered.right(), covered.height() as i32)
+    ];
+
+    bars.iter()
+        .filter(|(_, _, w, h)| *w > 0 && *h > 0)
+        .map(|(x, y, w, h)| sdl2::rect::Rect::new(*x, *y, *w as u32, *h as u32))
+        .collect()
+}
+
 /// Manages loading and keeping track of textures
 pub struct TextureManager<'a> {
     /// Index to give a newly created texture
@@ -90,7 +283,13 @@ pub struct TextureManager<'a> {
     textures: HashMap<usize, Texture<'a>>,
     /// Sdl texture creation struct
     texture_creator: &'a TextureCreator<WindowContext>,
-    texture_paths: HashMap<String, usize>
+    texture_paths: HashMap<String, usize>,
+    /// Hashes of the contents of textures loaded from memory
+    texture_hashes: HashMap<u64, usize>,
+    /// Directory relative asset paths are resolved against, the working directory if empty
+    pub asset_root: PathBuf,
+    /// First texture id loaded for the current world, textures before it are kept across worlds
+    scope_start: usize
 }
 
 impl<'a> TextureManager<'a> {
@@ -100,43 +299,261 @@ impl<'a> TextureManager<'a> {
             next_texture_id: 0,
             textures: HashMap::new(),
             texture_creator,
-            texture_paths: HashMap::new()
+            texture_paths: HashMap::new(),
+            texture_hashes: HashMap::new(),
+            asset_root: PathBuf::new(),
+            scope_start: 0
+        }
+    }
+
+    /// Texture creator new textures are made with, such as for another manager drawing to the same canvas
+    pub fn texture_creator(&self) -> &'a TextureCreator<WindowContext> {
+        self.texture_creator
+    }
+
+    /// Resolve an asset path against the asset root, leaving absolute paths untouched
+    pub fn resolve(&self, path: &str) -> String {
+        if Path::new(path).is_absolute() {
+            path.to_string()
+        } else {
+            self.asset_root.join(path).to_string_lossy().into_owned()
         }
     }
 
     /// Read a texture from disk into memory and returns its index to reference later
-    pub fn load_texture(&mut self, path: &str) -> usize {
-        if let Some(id) = self.texture_paths.get(path) {
-            return *id;
+    pub fn load_texture(&mut self, path: &str) -> Result<usize, String> {
+        let path = self.resolve(path);
+
+        if let Some(id) = self.texture_paths.get(&path) {
+            return Ok(*id);
         }
 
+        let tex = self.texture_creator.load_texture(&path)?;
+
         let id = self.next_texture_id;
         self.next_texture_id += 1;
 
-        let tex = self.texture_creator.load_texture(path).unwrap();
         self.textures.insert(id, tex);
-        self.texture_paths.insert(path.to_string(), id);
+        self.texture_paths.insert(path, id);
 
-        id
+        Ok(id)
+    }
+
+    /// Read a texture from an encoded image in memory, such as one from `include_bytes!`,
+    /// and return its index to reference later
+    pub fn load_texture_bytes(&mut self, bytes: &[u8]) -> Result<usize, String> {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if let Some(id) = self.texture_hashes.get(&hash) {
+            return Ok(*id);
+        }
+
+        let tex = self.texture_creator.load_texture_bytes(bytes)?;
+
+        let id = self.next_texture_id;
+        self.next_texture_id += 1;
+
+        self.textures.insert(id, tex);
+        self.texture_hashes.insert(hash, id);
+
+        Ok(id)
+    }
+
+    /// Start a new world's scope, textures loaded from now on are unloaded with `unload_scope`
+    pub fn begin_scope(&mut self) {
+        self.scope_start = self.next_texture_id;
+    }
+
+    /// Unload every texture loaded since the scope began, such as those unique to a world being deloaded.
+    /// Textures loaded before, like those of global entities, stay resident.
+    /// The ids of unloaded textures are handed out again, so reloading a world does not grow them forever
+    pub fn unload_scope(&mut self) {
+        let start = self.scope_start;
+
+        self.textures.retain(|id, _| *id < start);
+        self.texture_paths.retain(|_, id| *id < start);
+        self.texture_hashes.retain(|_, id| *id < start);
+        self.next_texture_id = start;
+    }
+
+    /// Number of textures currently resident
+    pub fn texture_count(&self) -> usize {
+        self.textures.len()
     }
 
     /// Get a texture from its index
     pub fn get_texture(&self, id: usize) -> Option<&Texture<'a>> {
         self.textures.get(&id)
     }
+
+    /// Get a mutable texture from its index, such as to change how it is blended
+    pub fn get_texture_mut(&mut self, id: usize) -> Option<&mut Texture<'a>> {
+        self.textures.get_mut(&id)
+    }
+}
+
+/// Overlay showing a scaled down view of entity footprints
+#[derive(Debug)]
+pub struct Minimap {
+    /// Area of the window to draw the minimap into
+    pub rect: sdl2::rect::Rect,
+    /// Scaling factor from world units to minimap pixels
+    pub scale: f32
+}
+
+impl Minimap {
+    /// Draw the footprint of every physical entity, coloring the player differently.
+    /// If any entity is flagged for the minimap only flagged entities are drawn
+    fn render(&self, canvas: &mut Canvas<Window>, world: &World) {
+        let old_color = canvas.draw_color();
+        canvas.set_clip_rect(self.rect);
+
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 255));
+        canvas.fill_rect(self.rect).unwrap();
+
+        let flagged_only = world.on_minimap.contains(&true);
+
+        for (id, (_, pos, phys)) in world.physics().0 {
+            if flagged_only && !world.on_minimap[id] {
+                continue;
+            }
+
+            let footprint = phys.hitbox.after_position(pos).after_depth(phys.depth);
+
+            let rect = sdl2::rect::Rect::new(
+                self.rect.x() + (footprint.x * self.scale) as i32,
+                self.rect.y() + (footprint.y * self.scale) as i32,
+                ((footprint.w as f32 * self.scale) as u32).max(1),
+                ((footprint.h as f32 * self.scale) as u32).max(1)
+            );
+
+            canvas.set_draw_color(if id == world.player_id { Color::GREEN } else { Color::WHITE });
+            canvas.fill_rect(rect).unwrap();
+        }
+
+        canvas.set_draw_color(Color::WHITE);
+        canvas.draw_rect(self.rect).unwrap();
+
+        canvas.set_clip_rect(None);
+        canvas.set_draw_color(old_color);
+    }
 }
 
 /// Configuration for the graphics system,
 /// created by parsing yaml file
 #[derive(Debug)]
 pub struct GraphicsConfig {
-    pub camera: Camera,
+    pub cameras: Vec<Camera>,
+    pub minimap: Option<Minimap>,
     pub debug: bool,
+    pub grid_spacing: u32,
+    pub overlay: Option<Color>,
+    pub fade_duration: f32,
     pub dialog_tex_id: Option<usize>,
     pub dialog_font_path: Option<String>,
     pub dialog_font_size: Option<u16>,
     pub dialog_textbox: Option<sdl2::rect::Rect>,
-    pub dialog_renderbox: Option<sdl2::rect::Rect>
+    pub dialog_renderbox: Option<sdl2::rect::Rect>,
+    pub dialog_insets: Option<Insets>,
+    pub dialog_text_speed: Option<f32>,
+    pub dialog_portrait_box: Option<sdl2::rect::Rect>
+}
+
+/// Strength of an effect which fades linearly from full to nothing over a duration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decay {
+    /// Seconds the effect takes to fade out
+    duration: f32,
+    /// Seconds since the effect began
+    elapsed: f32
+}
+
+impl Decay {
+    /// Create a new Decay at full strength
+    pub fn new(duration: f32) -> Decay {
+        Decay {
+            duration,
+            elapsed: 0.0
+        }
+    }
+
+    /// Move the fade forward by dt seconds
+    pub fn advance(&mut self, dt: f32) {
+        self.elapsed += dt;
+    }
+
+    /// Strength left, from 1 when the effect begins to 0 once it has faded out.
+    /// Effects without a positive duration have no strength
+    pub fn strength(&self) -> f32 {
+        if self.duration <= 0.0 {
+            return 0.0;
+        }
+
+        (1.0 - self.elapsed / self.duration).clamp(0.0, 1.0)
+    }
+
+    /// Check if the effect has faded out completely
+    pub fn finished(&self) -> bool {
+        self.strength() <= 0.0
+    }
+}
+
+/// Fade to black and back in, covering the move between two worlds
+#[derive(Debug, Clone)]
+pub struct Transition {
+    /// World and entrance to load once the screen is black, none once handed out
+    destination: Option<(String, String)>,
+    /// Seconds since the transition began
+    elapsed: f32
+}
+
+/// Widths of the borders of a nine-slice texture, which are drawn at native size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Insets {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32
+}
+
+/// Split a source and destination rectangle into nine (source, destination) pairs:
+/// corners at native size, edges stretched along one axis, and the center stretched along both.
+/// Pieces with no area are left out
+fn nine_slice(src: sdl2::rect::Rect, dst: sdl2::rect::Rect, insets: Insets) -> Vec<(sdl2::rect::Rect, sdl2::rect::Rect)> {
+    // Column and row boundaries as (start, size) for the source and destination
+    let split = |start: i32, len: u32, first: u32, last: u32| {
+        let first = first.min(len);
+        let last = last.min(len - first);
+        [
+            (start, first),
+            (start + first as i32, len - first - last),
+            (start + (len - last) as i32, last)
+        ]
+    };
+
+    let src_cols = split(src.x(), src.width(), insets.left, insets.right);
+    let src_rows = split(src.y(), src.height(), insets.top, insets.bottom);
+    let dst_cols = split(dst.x(), dst.width(), insets.left, insets.right);
+    let dst_rows = split(dst.y(), dst.height(), insets.top, insets.bottom);
+
+    let mut pieces = Vec::with_capacity(9);
+
+    for row in 0..3 {
+        for col in 0..3 {
+            let (sx, sw) = src_cols[col];
+            let (sy, sh) = src_rows[row];
+            let (dx, dw) = dst_cols[col];
+            let (dy, dh) = dst_rows[row];
+
+            if sw > 0 && sh > 0 && dw > 0 && dh > 0 {
+                pieces.push((sdl2::rect::Rect::new(sx, sy, sw, sh), sdl2::rect::Rect::new(dx, dy, dw, dh)));
+            }
+        }
+    }
+
+    pieces
 }
 
 /// Configuration for rendering the Dialog
@@ -144,6 +561,9 @@ pub struct DialogConfig<'a> {
     tex_id: usize,
     renderbox: sdl2::rect::Rect,
     textbox: sdl2::rect::Rect,
+    insets: Option<Insets>,
+    text_speed: Option<f32>,
+    portrait_box: Option<sdl2::rect::Rect>,
     font: Font<'a, 'a>
 }
 
@@ -154,27 +574,62 @@ impl<'a> DialogConfig<'a> {
             None
         } else {
             let tex_id = gc.dialog_tex_id.unwrap();
-            let font = ttf_context.load_font(gc.dialog_font_path.as_ref().unwrap(), gc.dialog_font_size.unwrap()).unwrap();
+            let font = load_font_or_default(ttf_context, gc.dialog_font_path.as_ref().unwrap(), gc.dialog_font_size.unwrap());
 
             Some(DialogConfig {
                 tex_id,
                 font,
                 renderbox: gc.dialog_renderbox.unwrap(),
-                textbox: gc.dialog_textbox.unwrap()
+                textbox: gc.dialog_textbox.unwrap(),
+                insets: gc.dialog_insets,
+                text_speed: gc.dialog_text_speed,
+                portrait_box: gc.dialog_portrait_box
             })
         }
     }
 }
 
 
+/// Font used when a configured font cannot be loaded
+static DEFAULT_FONT: &[u8] = include_bytes!("../assets/PublicPixel-0W6DP.ttf");
+
+/// Load a font from disk, falling back to the bundled default font if it cannot be read
+fn load_font_or_default<'a>(ttf_context: &'a Sdl2TtfContext, path: &str, size: u16) -> Font<'a, 'static> {
+    ttf_context.load_font(path, size).unwrap_or_else(|e| {
+        warn!("Could not load font '{}', using the default font: {}", path, e);
+        let rwops = RWops::from_bytes(DEFAULT_FONT).unwrap();
+        ttf_context.load_font_from_rwops(rwops, size).unwrap()
+    })
+}
+
 /// The actual rendering system, uses GraphicsState
 pub struct GraphicsSystem<'a> {
     /// Rendering surface, does all drawing
     canvas: &'a mut Canvas<Window>,
-    /// Camera to view the world through
-    pub camera: Camera,
+    /// Font context used to load the dialog font
+    ttf_context: &'a Sdl2TtfContext,
+    /// Cameras to view the world through, each rendering into its own viewport
+    pub cameras: Vec<Camera>,
+    /// Minimap overlay, if any
+    pub minimap: Option<Minimap>,
     /// Display debug information such as hitboxes
     pub debug: bool,
+    /// Spacing in world units of the debug grid, no grid if 0
+    pub grid_spacing: u32,
+    /// Number of entities skipped in the last frame because they were offscreen
+    pub culled: usize,
+    /// Color and fade of the current screen flash
+    flash: Option<(Color, Decay)>,
+    /// Magnitude and fade of the current camera shake
+    shake: Option<(f32, Decay)>,
+    /// State of the generator picking each frame's shake offset
+    shake_seed: u32,
+    /// Tint drawn over the world until changed, such as darkness in a cave
+    pub overlay: Option<Color>,
+    /// Seconds the screen takes to fade to black, and again to fade back in, when moving between worlds
+    pub fade_duration: f32,
+    /// Move between worlds in progress, if any
+    transition: Option<Transition>,
     /// Dialog Settings
     /// (texture id, renderbox, textbox, Font)
     pub dialog: Option<DialogConfig<'a>>,
@@ -187,121 +642,364 @@ impl<'a> GraphicsSystem<'a> {
 
         GraphicsSystem {
             canvas,
-            camera: config.camera,
+            ttf_context,
+            cameras: config.cameras,
+            minimap: config.minimap,
             debug: config.debug,
+            grid_spacing: config.grid_spacing,
+            culled: 0,
+            flash: None,
+            shake: None,
+            shake_seed: 0x9E37_79B9,
+            overlay: config.overlay,
+            fade_duration: config.fade_duration,
+            transition: None,
             dialog: dialog_config
         }
     }
 
-    /// Fit the sdl window to the newly resized window
-    pub fn refresh(&mut self) {
+    /// Tint the world with a color until changed, an alpha of 0 removes the tint
+    pub fn set_overlay(&mut self, color: Color, alpha: u8) {
+        self.overlay = if alpha == 0 {
+            None
+        } else {
+            Some(Color::RGBA(color.r, color.g, color.b, alpha))
+        };
     }
 
-    /// Make the Camera follow a given rectangle
-    fn follow(&mut self, rect: Rect, world_width: u32, world_height: u32) {
-        // Bounding box
-        let box_x_offset = self.camera.player_box.x / self.camera.zoom as f32;
-        let box_y_offset = self.camera.player_box.y / self.camera.zoom as f32;
-        let box_width = self.camera.player_box.w as f32 / self.camera.zoom as f32;
-        let box_height = self.camera.player_box.h as f32 / self.camera.zoom as f32;
+    /// Overlay the whole screen with a color which fades to transparent over duration seconds
+    pub fn flash(&mut self, color: Color, duration: f32) {
+        self.flash = Some((color, Decay::new(duration)));
+    }
 
-        let box_left = self.camera.rect.x + box_x_offset;
-        let box_right = box_left + box_width;
-        let box_top = self.camera.rect.y + box_y_offset;
-        let box_bottom = box_top + box_height;
+    /// Opacity of the current flash, decaying linearly from the color's alpha to zero
+    pub fn flash_alpha(&self) -> u8 {
+        match self.flash {
+            Some((color, decay)) => (color.a as f32 * decay.strength()) as u8,
+            None => 0
+        }
+    }
 
-        let rect_left = rect.x;
-        let rect_right = rect.x + rect.w as f32;
-        let rect_top = rect.y;
-        let rect_bottom = rect.y + rect.h as f32;
+    /// Shake every camera by up to magnitude world units, settling linearly over duration seconds
+    pub fn shake(&mut self, magnitude: f32, duration: f32) {
+        self.shake = Some((magnitude, Decay::new(duration)));
+    }
 
-        if rect_left < box_left {
-            self.camera.rect.x = rect_left - box_x_offset;
+    /// Distance the cameras may currently be moved by the shake, decaying linearly from its magnitude to zero
+    pub fn shake_magnitude(&self) -> f32 {
+        match self.shake {
+            Some((magnitude, decay)) => magnitude * decay.strength(),
+            None => 0.0
         }
+    }
 
-        if rect_right > box_right {
-            self.camera.rect.x = rect_right - box_width - box_x_offset;
+    /// Fade the flash and shake by dt seconds, dropping them once they have settled
+    pub fn advance_effects(&mut self, dt: f32) {
+        if let Some((_, decay)) = self.flash.as_mut() {
+            decay.advance(dt);
         }
 
-        if rect_top < box_top {
-            self.camera.rect.y = rect_top - box_y_offset;
+        if let Some((_, decay)) = self.shake.as_mut() {
+            decay.advance(dt);
         }
 
-        if rect_bottom > box_bottom {
-            self.camera.rect.y = rect_bottom - box_height - box_y_offset;
+        if self.flash.is_some_and(|(_, decay)| decay.finished()) {
+            self.flash = None;
+        }
+
+        if self.shake.is_some_and(|(_, decay)| decay.finished()) {
+            self.shake = None;
+        }
+    }
+
+    /// Random offset in [-magnitude, magnitude] on both axes, from a xorshift generator
+    fn shake_offset(&mut self, magnitude: f32) -> (f32, f32) {
+        let mut next = || {
+            self.shake_seed ^= self.shake_seed << 13;
+            self.shake_seed ^= self.shake_seed >> 17;
+            self.shake_seed ^= self.shake_seed << 5;
+            (self.shake_seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+        };
+
+        (next() * magnitude, next() * magnitude)
+    }
+
+    /// Start fading to black before moving to a world and entrance.
+    /// Ignored while another transition is in progress
+    pub fn begin_transition(&mut self, destination: (String, String)) {
+        if self.transition.is_none() {
+            self.transition = Some(Transition { destination: Some(destination), elapsed: 0.0 });
+        }
+    }
+
+    /// Whether a move between worlds is in progress, during which the world should be paused
+    pub fn transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Move the transition forward by dt seconds.
+    /// Returns the destination once, when the screen is fully black and the world should be swapped
+    pub fn advance_transition(&mut self, dt: f32) -> Option<(String, String)> {
+        let fade = self.fade_duration.max(0.0);
+        let transition = self.transition.as_mut()?;
+        transition.elapsed += dt;
+
+        let destination = if transition.elapsed >= fade {
+            transition.destination.take()
+        } else {
+            None
+        };
+
+        if transition.elapsed >= fade * 2.0 {
+            self.transition = None;
         }
 
-        // Clamp camera to world bounds
-        self.camera.rect.x = self.camera.rect.x.max(0.0);
-        self.camera.rect.y = self.camera.rect.y.max(0.0);
-        self.camera.rect.x = (self.camera.rect.x + self.camera.rect.w as f32 / self.camera.zoom as f32).min(world_width as f32) - (self.camera.rect.w as f32 / self.camera.zoom as f32);
-        self.camera.rect.y = (self.camera.rect.y + self.camera.rect.h as f32 / self.camera.zoom as f32).min(world_height as f32) - (self.camera.rect.h as f32 / self.camera.zoom as f32);
+        destination
     }
 
-    /// Draw an entity based on its position and texture
-    pub fn draw_entity(&mut self, texture_manager: &TextureManager, entity: (&HashSet<String>, &PositionComponent, &GraphicsComponent), _physics: Option<&PhysicsComponent>) {
+    /// Opacity of the black covering the screen, rising to full while fading out then falling while fading in
+    pub fn transition_alpha(&self) -> u8 {
+        match self.transition.as_ref() {
+            Some(_) if self.fade_duration <= 0.0 => 0,
+            Some(transition) => {
+                let progress = transition.elapsed / self.fade_duration;
+                let coverage = if progress < 1.0 { progress } else { 2.0 - progress };
+                (255.0 * coverage.clamp(0.0, 1.0)) as u8
+            }
+            None => 0
+        }
+    }
+
+    /// Characters per second dialog text is revealed at, none if it appears at once
+    pub fn text_speed(&self) -> Option<f32> {
+        self.dialog.as_ref().and_then(|d| d.text_speed)
+    }
+
+    /// Center every camera on its target, such as right after a world load so the first frame is not a jump
+    pub fn snap_cameras(&mut self, world: &World) {
+        for camera in self.cameras.iter_mut() {
+            if camera.target < world.states.len() {
+                if let (Some(pos), Some(phys)) = world.get_entity_physics(camera.target) {
+                    camera.snap_to(phys.hitbox.after_position(pos), world.world_width, world.world_height);
+                }
+            }
+        }
+    }
+
+    /// Fit the sdl window to the newly resized window
+    pub fn refresh(&mut self) {
+    }
+
+    /// Replace the camera, debug, and dialog settings with those of a new GraphicsConfig
+    pub fn apply_config(&mut self, config: GraphicsConfig) {
+        self.dialog = DialogConfig::from_graphics_config(&config, self.ttf_context);
+        self.cameras = config.cameras;
+        self.minimap = config.minimap;
+        self.debug = config.debug;
+        self.grid_spacing = config.grid_spacing;
+        self.overlay = config.overlay;
+        self.fade_duration = config.fade_duration;
+    }
+
+    /// Draw an entity based on its position and texture through a single camera
+    pub fn draw_entity(&mut self, cam: usize, texture_manager: &mut TextureManager, entity: (&HashSet<String>, &PositionComponent, &GraphicsComponent), _physics: Option<&PhysicsComponent>) {
         let tex_id = entity.2.texture_id;
         let flipped = entity.2.flipped;
-        let texture = texture_manager.get_texture(tex_id).unwrap();
+        let alpha = entity.2.alpha;
+        let tint = entity.2.tint;
+        let texture = texture_manager.get_texture_mut(tex_id).unwrap();
 
-        let entity_rect = self.camera.view(entity.2.renderbox.after_position(entity.1), self.canvas.output_size().unwrap());
+        let size = self.canvas.output_size().unwrap();
+        let entity_rect = self.cameras[cam].view(entity.2.renderbox.after_position(entity.1), size);
+
+        // Skip entities which lie entirely outside the camera's area
+        if !entity_rect.sdl2().has_intersection(self.cameras[cam].area(size)) {
+            self.culled += 1;
+            return;
+        }
 
-        self.canvas.copy_ex(texture, entity.2.srcbox, entity_rect.sdl2(), 0.0, None, flipped, false).unwrap();
+        if alpha == 255 && tint.is_none() {
+            self.canvas.copy_ex(texture, entity.2.srcbox, entity_rect.sdl2(), 0.0, None, flipped, false).unwrap();
+        } else {
+            // Textures are shared between entities, so only fade and tint this copy
+            let blend_mode = texture.blend_mode();
+            texture.set_blend_mode(BlendMode::Blend);
+            texture.set_alpha_mod(alpha);
+
+            if let Some(tint) = tint {
+                texture.set_color_mod(tint.r, tint.g, tint.b);
+            }
+
+            self.canvas.copy_ex(texture, entity.2.srcbox, entity_rect.sdl2(), 0.0, None, flipped, false).unwrap();
+
+            texture.set_color_mod(255, 255, 255);
+            texture.set_alpha_mod(255);
+            texture.set_blend_mode(blend_mode);
+        }
     }
 
-    /// Draw all renderable entities
-    pub fn run(&mut self, world: &mut World) {
+    /// Draw all renderable entities, fading the flash and shake by dt seconds
+    pub fn run(&mut self, world: &mut World, dt: f32) {
         if let (0, 0) = self.canvas.output_size().unwrap() {
             return;
         }
 
+        // Settle the current flash and shake, then start any requested by actions along with overlay changes
+        self.advance_effects(dt);
+
+        for (magnitude, duration) in std::mem::take(&mut world.commands.shakes) {
+            self.shake(magnitude, duration);
+        }
+
+        for (color, duration) in std::mem::take(&mut world.commands.flashes) {
+            self.flash(color, duration);
+        }
+
+        for color in std::mem::take(&mut world.commands.overlays) {
+            self.set_overlay(color, color.a);
+        }
+
         // Set background color
         self.canvas.set_draw_color(world.background_color);
 
         self.canvas.clear();
+        self.culled = 0;
+
+        let magnitude = self.shake_magnitude();
+
+        // Draw the world once per camera, inside its viewport
+        for cam in 0..self.cameras.len() {
+            let target = self.cameras[cam].target;
+            if target < world.states.len() {
+                if let (Some(pos), Some(phys)) = world.get_entity_physics(target) {
+                    self.cameras[cam].follow(phys.hitbox.after_position(pos), world.world_width, world.world_height);
+                }
+            }
+
+            // Offset the camera only while drawing so following keeps working from its real position
+            let (base_x, base_y) = (self.cameras[cam].rect.x, self.cameras[cam].rect.y);
+            if magnitude > 0.0 {
+                let (dx, dy) = self.shake_offset(magnitude);
+                self.cameras[cam].rect.x += dx;
+                self.cameras[cam].rect.y += dy;
+            }
+
+            self.canvas.set_clip_rect(self.cameras[cam].viewport);
+            self.render_camera(cam, world);
 
-        let player_id = 0;
-        if let (Some(pos), Some(phys)) = world.get_entity_physics(player_id) {
-            self.follow(phys.hitbox.after_position(pos), world.world_width, world.world_height);
+            self.cameras[cam].rect.x = base_x;
+            self.cameras[cam].rect.y = base_y;
         }
 
-        // Draw background if exists
-        if let Some(background) = world.background.as_ref() {
-            let (width, height) = self.canvas.output_size().unwrap();
-            let left = (width as f32 - self.camera.rect.w as f32) / 2.0 - self.camera.rect.x * self.camera.zoom as f32;
-            let top = (height - self.camera.rect.h) as f32 / 2.0 - self.camera.rect.y * self.camera.zoom as f32;
-            let renderbox = background.renderbox.after_position(&PositionComponent::new(left, top)).sdl2();
-            let tex = world.texture_manager.get_texture(background.texture_id).unwrap();
-            self.canvas.copy(tex, None, renderbox).unwrap();
+        self.canvas.set_clip_rect(None);
+
+        // Draw Minimap If Exists
+        if let Some(minimap) = self.minimap.as_ref() {
+            minimap.render(self.canvas, world);
         }
 
-        let mut drawables: Vec<(usize, (_, &PositionComponent, &GraphicsComponent))> = world.graphics().collect();
+        // Draw Dialog If Exists
+        if self.dialog.is_some() {
+            if world.curr_dialog.is_some() {
+                let dialog_name = world.curr_dialog.as_ref().unwrap();
+                let dialog = &world.dialogs[dialog_name];
+                self.render_dialog(&world.texture_manager, dialog);
+            }
+        }
 
-        // Sort entities by the bottom of their rects
-        drawables.sort_by_key(|e| {
-            let r = e.1.2.renderbox.after_position(e.1.1);
-            r.y as i32+r.h as i32
-        });
+        // Draw the flash over everything else
+        let alpha = self.flash_alpha();
+        if let (Some((color, _)), true) = (self.flash, alpha > 0) {
+            let size = self.canvas.output_size().unwrap();
+            self.canvas.set_blend_mode(BlendMode::Blend);
+            self.canvas.set_draw_color(Color::RGBA(color.r, color.g, color.b, alpha));
+            self.canvas.fill_rect(sdl2::rect::Rect::new(0, 0, size.0, size.1)).unwrap();
+            self.canvas.set_blend_mode(BlendMode::None);
+        }
+
+        // Cover everything while moving between worlds
+        let alpha = self.transition_alpha();
+        if alpha > 0 {
+            let size = self.canvas.output_size().unwrap();
+            self.canvas.set_blend_mode(BlendMode::Blend);
+            self.canvas.set_draw_color(Color::RGBA(0, 0, 0, alpha));
+            self.canvas.fill_rect(sdl2::rect::Rect::new(0, 0, size.0, size.1)).unwrap();
+            self.canvas.set_blend_mode(BlendMode::None);
+        }
+
+        self.canvas.present();
+    }
+
+    /// Draw the world as seen through a single camera
+    fn render_camera(&mut self, cam: usize, world: &mut World) {
+        let size = self.canvas.output_size().unwrap();
+
+        // Layering: clear color, out of bounds fill, background layers, tilemaps, entities, then letterbox bars
+        let camera = &self.cameras[cam];
+        let layers: Vec<Rect> = world.backgrounds.iter()
+            .map(|(layer, parallax)| camera.layer_rect(layer.renderbox, *parallax, size))
+            .collect();
+
+        // Fill the parts of the view the layers moving with the world do not cover, behind every layer
+        if let Some(color) = world.out_of_bounds_color {
+            let (origin_x, origin_y) = camera.origin(size);
+            let view = sdl2::rect::Rect::new(origin_x, origin_y, camera.rect.w, camera.rect.h);
+            let covered = world.backgrounds.iter().zip(&layers)
+                .filter(|((_, parallax), _)| *parallax == 1.0)
+                .map(|(_, rect)| *rect)
+                .reduce(|a, b| a.union(b));
+
+            if let Some(covered) = covered {
+                self.canvas.set_draw_color(color);
+                for region in uncovered(view, covered.sdl2()) {
+                    self.canvas.fill_rect(region).unwrap();
+                }
+            }
+        }
+
+        // Draw the layers in order, each scrolled by its fraction of the camera's position
+        for ((layer, _), rect) in world.backgrounds.iter().zip(layers) {
+            let tex = world.texture_manager.get_texture(layer.texture_id).unwrap();
+            self.canvas.copy(tex, layer.srcbox, rect.sdl2()).unwrap();
+        }
+
+        self.render_tilemaps(cam, world);
 
         // Draw Entities
-        drawables.iter().for_each(|e| {
-            if !e.1.0.contains(&"invisible".to_string()) {
-                let physics = world.get_entity_physics(e.0);
-                self.draw_entity(&world.texture_manager, e.1, physics.1);
+        for id in self.cameras[cam].draw_order(world) {
+            if !world.states[id].contains(&world.state_names.invisible) {
+                let entity = (&world.states[id], world.positions[id].as_ref().unwrap(), world.graphics[id].as_ref().unwrap());
+                self.draw_entity(cam, &mut world.texture_manager, entity, world.physics[id].as_ref());
             }
-        });
+        }
+
+        // Tint the world, leaving the letterbox bars untouched
+        if let Some(overlay) = self.overlay {
+            let camera = &self.cameras[cam];
+            let (x, y) = camera.origin(size);
+
+            self.canvas.set_blend_mode(BlendMode::Blend);
+            self.canvas.set_draw_color(overlay);
+            self.canvas.fill_rect(sdl2::rect::Rect::new(x, y, camera.rect.w, camera.rect.h)).unwrap();
+            self.canvas.set_blend_mode(BlendMode::None);
+        }
+
+        // Draw the world grid if we are in debug mode
+        if self.debug {
+            self.render_grid(cam, world);
+        }
 
         // Draw hitboxes if we are in debug mode
         if self.debug {
             self.canvas.set_draw_color(Color::RED);
             for i in 0..world.states.len() {
                 if world.physics[i].is_some() && world.positions[i].is_some() {
-                    let rect = self.camera.view(
+                    let rect = self.cameras[cam].view(
                         world.physics[i].as_ref().unwrap().hitbox
                             .after_position(
                                 world.positions[i].as_ref().unwrap()
                             ),
-                        self.canvas.output_size().unwrap()
+                        size
                     );
 
                     self.canvas.draw_rect(rect.sdl2()).unwrap();
@@ -309,68 +1007,610 @@ impl<'a> GraphicsSystem<'a> {
             }
         }
 
-        // Draw Dialog If Exists
-        if self.dialog.is_some() {
-            if world.curr_dialog.is_some() {
-                let dialog_name = world.curr_dialog.as_ref().unwrap();
-                let dialog = &world.dialogs[dialog_name];
-                self.render_dialog(&world.texture_manager, dialog);
-            }
-        }
-
         // Draw effects if we are in debug mode
         if self.debug {
             self.canvas.set_draw_color(Color::MAGENTA);
             for effect in world.effects.iter() {
-                let rect = self.camera.view(effect.rect, self.canvas.output_size().unwrap());
+                let rect = self.cameras[cam].view(effect.bounds(), size);
                 self.canvas.draw_rect(rect.sdl2()).unwrap();
             }
         }
 
         // Draw Camera Borders
-        self.camera.render(self.canvas);
-        self.canvas.present();
+        self.cameras[cam].render(self.canvas);
+    }
+
+    /// Draw the visible tiles of every tilemap through a single camera, beneath all entities
+    fn render_tilemaps(&mut self, cam: usize, world: &World) {
+        let size = self.canvas.output_size().unwrap();
+        let camera = &self.cameras[cam];
+        let area = camera.area(size);
+
+        // Only the tiles under the camera's view need checking when world and screen axes line up
+        let view = Rect::new(
+            camera.rect.x,
+            camera.rect.y,
+            camera.rect.w / camera.zoom.max(1),
+            camera.rect.h / camera.zoom.max(1)
+        );
+
+        for (pos, tilemap) in world.positions.iter().zip(world.tilemaps.iter()) {
+            let (pos, tilemap) = match (pos, tilemap) {
+                (Some(pos), Some(tilemap)) => (pos, tilemap),
+                _ => continue
+            };
+
+            let texture = world.texture_manager.get_texture(tilemap.texture_id).unwrap();
+            let columns = texture.query().width / tilemap.tile_width.max(1);
+
+            let (first_row, end_row, first_col, end_col) = match camera.projection {
+                Projection::TopDown => tilemap.visible(pos, view),
+                Projection::Iso => (0, tilemap.tiles.len(), 0, usize::MAX)
+            };
+
+            for row in first_row..end_row {
+                let tiles = &tilemap.tiles[row];
+
+                for (col, tile) in tiles.iter().enumerate().take(end_col).skip(first_col) {
+                    let index = match tile {
+                        Some(index) => *index,
+                        None => continue
+                    };
+
+                    let rect = camera.view(tilemap.tile_rect(pos, row, col), size).sdl2();
+                    if !rect.has_intersection(area) {
+                        continue;
+                    }
+
+                    self.canvas.copy(texture, tilemap.srcbox(index, columns), rect).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Draw grid lines through a single camera, labelled with their world coordinates if there is a font
+    fn render_grid(&mut self, cam: usize, world: &World) {
+        let size = self.canvas.output_size().unwrap();
+        let area = self.cameras[cam].area(size);
+        let (vertical, horizontal) = self.cameras[cam].grid_lines(self.grid_spacing, size);
+
+        self.canvas.set_draw_color(Color::GRAY);
+        for (_, x) in vertical.iter() {
+            self.canvas.draw_line((*x, area.top()), (*x, area.bottom())).unwrap();
+        }
+
+        for (_, y) in horizontal.iter() {
+            self.canvas.draw_line((area.left(), *y), (area.right(), *y)).unwrap();
+        }
+
+        // Labels reuse the dialog font
+        let font = match self.dialog.as_ref() {
+            Some(d) => &d.font,
+            None => return
+        };
+
+        let labels = vertical.iter().map(|(w, x)| (*w, *x, area.top()))
+            .chain(horizontal.iter().map(|(w, y)| (*w, area.left(), *y)));
+
+        for (world_coord, x, y) in labels {
+            let surface = font.render(&format!("{}", world_coord)).blended(Color::GRAY).unwrap();
+            let tex = world.texture_manager.texture_creator.create_texture_from_surface(&surface).unwrap();
+            let TextureQuery { width, height, .. } = tex.query();
+
+            self.canvas.copy(&tex, None, sdl2::rect::Rect::new(x + 2, y + 2, width, height)).unwrap();
+        }
     }
 
     /// Render a dialog window
     fn render_dialog(&mut self, texture_manager: &TextureManager, dialog: &Dialog) {
-        let (screen_width, screen_height) = self.canvas.output_size().unwrap();
-        let left_offset = ((screen_width - self.camera.rect.w) / 2) as i32;
-        let top_offset = ((screen_height - self.camera.rect.h) / 2) as i32;
+        let (left_offset, top_offset) = self.cameras[0].origin(self.canvas.output_size().unwrap());
 
-        // Draw Box
+        // Draw Box, keeping its borders at native size if it is nine-sliced
         let d = self.dialog.as_ref().unwrap();
         let tex = texture_manager.get_texture(d.tex_id).unwrap();
-        self.canvas.copy(
-            tex,
-            None,
-            sdl2::rect::Rect::new(
-                left_offset+d.renderbox.x,
-                top_offset+d.renderbox.y,
-                d.renderbox.width(),
-                d.renderbox.height()
-            )
-        ).unwrap();
-
-        // Draw Text
-        let msg = dialog.msg();
-        let surface = d.font.render(&msg).blended_wrapped((255, 255, 255), d.textbox.width()).unwrap();
-        let tex = texture_manager.texture_creator.create_texture_from_surface(&surface).unwrap();
-
-        let TextureQuery { width, height, .. } = tex.query();
-
-        self.canvas.copy(
-            &tex,
-            None,
-            sdl2::rect::Rect::new(
-                left_offset+d.renderbox.x+d.textbox.x,
-                top_offset+d.renderbox.y+d.textbox.y,
-                width,
-                height
-            )
-        ).unwrap();
+        let dst = sdl2::rect::Rect::new(
+            left_offset+d.renderbox.x,
+            top_offset+d.renderbox.y,
+            d.renderbox.width(),
+            d.renderbox.height()
+        );
+
+        match d.insets {
+            Some(insets) => {
+                let TextureQuery { width, height, .. } = tex.query();
+
+                for (src, dst) in nine_slice(sdl2::rect::Rect::new(0, 0, width, height), dst, insets) {
+                    self.canvas.copy(tex, src, dst).unwrap();
+                }
+            }
+            None => self.canvas.copy(tex, None, dst).unwrap()
+        }
+
+        // Draw the speaker's portrait
+        if let (Some(portrait), Some(portrait_box)) = (dialog.portrait(), d.portrait_box) {
+            if let Some(tex) = texture_manager.get_texture(portrait) {
+                self.canvas.copy(
+                    tex,
+                    None,
+                    sdl2::rect::Rect::new(
+                        left_offset+d.renderbox.x+portrait_box.x,
+                        top_offset+d.renderbox.y+portrait_box.y,
+                        portrait_box.width(),
+                        portrait_box.height()
+                    )
+                ).unwrap();
+            }
+        }
+
+        // Draw the speaker's name at the top of the textbox, moving the text below it
+        let mut text_y = top_offset+d.renderbox.y+d.textbox.y;
+
+        if let Some(speaker) = dialog.speaker().filter(|s| !s.is_empty()) {
+            let surface = d.font.render(speaker).blended((255, 220, 0)).unwrap();
+            let tex = texture_manager.texture_creator.create_texture_from_surface(&surface).unwrap();
+            let TextureQuery { width, height, .. } = tex.query();
+
+            self.canvas.copy(
+                &tex,
+                None,
+                sdl2::rect::Rect::new(left_offset+d.renderbox.x+d.textbox.x, text_y, width, height)
+            ).unwrap();
+
+            text_y += height as i32;
+        }
+
+        // Draw the revealed part of the text, nothing can be rendered before the first character
+        let msg = dialog.visible_msg();
+        let mut height = 0;
+
+        if !msg.is_empty() {
+            let surface = d.font.render(&msg).blended_wrapped((255, 255, 255), d.textbox.width()).unwrap();
+            let tex = texture_manager.texture_creator.create_texture_from_surface(&surface).unwrap();
+
+            let query = tex.query();
+            height = query.height;
+
+            self.canvas.copy(
+                &tex,
+                None,
+                sdl2::rect::Rect::new(
+                    left_offset+d.renderbox.x+d.textbox.x,
+                    text_y,
+                    query.width,
+                    query.height
+                )
+            ).unwrap();
+        }
+
+        // Draw the choices below the message once it is fully shown, highlighting the selected one
+        if !dialog.revealed() {
+            return;
+        }
+
+        let mut y = text_y+height as i32;
+        for (i, choice) in dialog.choices().iter().enumerate() {
+            let (text, color) = if i == dialog.selected() {
+                (format!("> {}", choice.text), (255, 220, 0))
+            } else {
+                (format!("  {}", choice.text), (255, 255, 255))
+            };
+
+            let surface = d.font.render(&text).blended_wrapped(color, d.textbox.width()).unwrap();
+            let tex = texture_manager.texture_creator.create_texture_from_surface(&surface).unwrap();
+            let TextureQuery { width, height, .. } = tex.query();
+
+            self.canvas.copy(
+                &tex,
+                None,
+                sdl2::rect::Rect::new(left_offset+d.renderbox.x+d.textbox.x, y, width, height)
+            ).unwrap();
 
+            y += height as i32;
+        }
+    }
+}
+
+
+/// Run a test with a texture manager for a hidden window, so worlds can be built without a display.
+/// Only one SDL context may exist at a time, so tests which need one take turns
+#[cfg(test)]
+pub(crate) fn with_texture_manager<F: FnOnce(TextureManager)>(test: F) {
+    with_sdl(|_, texture_manager| test(texture_manager));
+}
+
+/// Run a test with the SDL context alongside a texture manager, for systems
+/// which need their own subsystems
+#[cfg(test)]
+pub(crate) fn with_sdl<F: FnOnce(&sdl2::Sdl, TextureManager)>(test: F) {
+    use std::sync::Mutex;
+
+    static SDL: Mutex<()> = Mutex::new(());
+    let _turn = SDL.lock().unwrap_or_else(|e| e.into_inner());
+
+    sdl2::hint::set("SDL_VIDEODRIVER", "dummy");
+    let sdl = sdl2::init().unwrap();
+    let video = sdl.video().unwrap();
+    let window = video.window("test", 64, 64).hidden().build().unwrap();
+    let canvas = window.into_canvas().software().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+
+    test(&sdl, TextureManager::new(&texture_creator));
+}
+
+/// A top down camera at the world origin showing w by h screen pixels
+#[cfg(test)]
+pub(crate) fn test_camera(w: u32, h: u32, zoom: u32) -> Camera {
+    Camera {
+        rect: Rect::new(0.0, 0.0, w, h),
+        player_box: Rect::new(0.0, 0.0, w, h),
+        zoom,
+        viewport: None,
+        target: 0,
+        projection: Projection::TopDown
     }
 }
 
+/// Graphics settings with the given cameras and nothing else
+#[cfg(test)]
+pub(crate) fn test_config(cameras: Vec<Camera>) -> GraphicsConfig {
+    GraphicsConfig {
+        cameras,
+        minimap: None,
+        debug: false,
+        grid_spacing: 0,
+        overlay: None,
+        fade_duration: 0.0,
+        dialog_tex_id: None,
+        dialog_font_path: None,
+        dialog_font_size: None,
+        dialog_textbox: None,
+        dialog_renderbox: None,
+        dialog_insets: None,
+        dialog_text_speed: None,
+        dialog_portrait_box: None
+    }
+}
+
+/// Run a test with a graphics system drawing into its own hidden window
+#[cfg(test)]
+pub(crate) fn with_graphics_system<F: FnOnce(&sdl2::Sdl, GraphicsSystem, TextureManager)>(config: GraphicsConfig, test: F) {
+    with_sdl(|sdl, texture_manager| {
+        let window = sdl.video().unwrap().window("graphics", 64, 64).hidden().build().unwrap();
+        let mut canvas = window.into_canvas().software().build().unwrap();
+        let ttf_context = sdl2::ttf::init().unwrap();
+
+        test(sdl, GraphicsSystem::new(config, &ttf_context, &mut canvas), texture_manager);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::{Actionable, Commands, SetOverlay};
+    use crate::logger;
+    use crate::world::WorldBuilder;
+
+    /// A 1x1 PNG of a single red pixel
+    const RED_PIXEL_PNG: &[u8] = &[
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+        0x44, 0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00,
+        0x00, 0x1f, 0x15, 0xc4, 0x89, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x44, 0x41, 0x54, 0x78,
+        0x9c, 0x63, 0xf8, 0xcf, 0xc0, 0xf0, 0x1f, 0x00, 0x05, 0x00, 0x01, 0xff, 0x89, 0x99,
+        0x3d, 0x1d, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82
+    ];
+
+    fn parts(rect: Rect) -> (f32, f32, u32, u32) {
+        (rect.x, rect.y, rect.w, rect.h)
+    }
+
+    #[test]
+    fn applied_zoom_changes_the_view() {
+        with_graphics_system(test_config(vec![test_camera(100, 100, 1)]), |_, mut system, _| {
+            let rect = Rect::new(10.0, 20.0, 5, 5);
+            assert_eq!(parts(system.cameras[0].view(rect, (100, 100))), (10.0, 20.0, 5, 5));
+
+            system.apply_config(test_config(vec![test_camera(100, 100, 2)]));
+            assert_eq!(parts(system.cameras[0].view(rect, (100, 100))), (20.0, 40.0, 10, 10));
+        });
+    }
+
+    #[test]
+    fn each_camera_views_from_its_own_position() {
+        let mut left = test_camera(100, 100, 1);
+        left.viewport = Some(sdl2::rect::Rect::new(0, 0, 100, 100));
+        let mut right = test_camera(100, 100, 1);
+        right.rect.x = 40.0;
+        right.viewport = Some(sdl2::rect::Rect::new(100, 0, 100, 100));
+
+        let entity = Rect::new(60.0, 10.0, 8, 8);
+
+        assert_eq!(parts(left.view(entity, (200, 100))), (60.0, 10.0, 8, 8));
+        assert_eq!(parts(right.view(entity, (200, 100))), (120.0, 10.0, 8, 8));
+    }
+
+    #[test]
+    fn entities_outside_the_camera_are_culled() {
+        with_graphics_system(test_config(vec![test_camera(64, 64, 1)]), |_, mut system, mut texture_manager| {
+            let tex_id = texture_manager.load_texture_bytes(RED_PIXEL_PNG).unwrap();
+            let graphics = GraphicsComponent::new(tex_id, Rect::new(0.0, 0.0, 8, 8), None);
+            let states = HashSet::new();
+
+            system.draw_entity(0, &mut texture_manager, (&states, &PositionComponent::new(10.0, 10.0), &graphics), None);
+            assert_eq!(system.culled, 0);
+
+            system.draw_entity(0, &mut texture_manager, (&states, &PositionComponent::new(500.0, 10.0), &graphics), None);
+            system.draw_entity(0, &mut texture_manager, (&states, &PositionComponent::new(10.0, -100.0), &graphics), None);
+            assert_eq!(system.culled, 2);
+        });
+    }
+
+    #[test]
+    fn grid_lines_land_on_world_multiples() {
+        let mut camera = test_camera(64, 64, 2);
+        camera.rect.x = 8.0;
+        camera.rect.y = 4.0;
+
+        let (vertical, horizontal) = camera.grid_lines(16, (64, 64));
+
+        assert_eq!(vertical, vec![(16.0, 16), (32.0, 48)]);
+        assert_eq!(horizontal, vec![(16.0, 24), (32.0, 56)]);
+        assert_eq!(camera.grid_lines(0, (64, 64)), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn snapped_cameras_contain_the_player() {
+        with_graphics_system(test_config(vec![test_camera(64, 64, 2)]), |_, mut system, texture_manager| {
+            let mut world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .bounds(1000, 1000)
+                .entity(
+                    Some(PositionComponent::new(10.0, 10.0)),
+                    Some(PhysicsComponent::new(Rect::new(0.0, 0.0, 8, 8), 8, true)),
+                    None, None, None, Vec::new()
+                )
+                .build();
+
+            // Arriving far across the new world, such as through an entrance
+            world.positions[0] = Some(PositionComponent::new(600.0, 420.0));
+            system.snap_cameras(&world);
+
+            let view = &system.cameras[0].rect;
+            assert!(view.x <= 600.0 && 608.0 <= view.x + 32.0, "camera x at {}", view.x);
+            assert!(view.y <= 420.0 && 428.0 <= view.y + 32.0, "camera y at {}", view.y);
+        });
+    }
+
+    #[test]
+    fn fill_covers_what_an_undersized_background_leaves() {
+        use sdl2::rect::Rect as ScreenRect;
+        let area = ScreenRect::new(0, 0, 100, 80);
+
+        assert_eq!(uncovered(area, ScreenRect::new(20, 10, 50, 40)), vec![
+            ScreenRect::new(0, 0, 100, 10),
+            ScreenRect::new(0, 50, 100, 30),
+            ScreenRect::new(0, 10, 20, 40),
+            ScreenRect::new(70, 10, 30, 40)
+        ]);
+
+        // A background flush with three edges of the view leaves a single bar
+        assert_eq!(uncovered(area, ScreenRect::new(0, 0, 60, 80)), vec![ScreenRect::new(60, 0, 40, 80)]);
+        assert_eq!(uncovered(area, ScreenRect::new(-10, -10, 200, 200)), Vec::new());
+        assert_eq!(uncovered(area, ScreenRect::new(300, 300, 10, 10)), vec![area]);
+    }
+
+    #[test]
+    fn equal_baselines_keep_their_draw_order() {
+        with_texture_manager(|texture_manager| {
+            let sprite = |z: i32| {
+                let mut graphics = GraphicsComponent::new(0, Rect::new(0.0, 0.0, 8, 8), None);
+                graphics.z = z;
+                Some(graphics)
+            };
+            let world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .entity(Some(PositionComponent::new(20.0, 10.0)), None, sprite(0), None, None, Vec::new())
+                .entity(Some(PositionComponent::new(0.0, 10.0)), None, sprite(0), None, None, Vec::new())
+                .entity(Some(PositionComponent::new(0.0, 0.0)), None, sprite(0), None, None, Vec::new())
+                .entity(Some(PositionComponent::new(0.0, -50.0)), None, sprite(1), None, None, Vec::new())
+                .build();
+            let camera = test_camera(64, 64, 1);
+
+            for _ in 0..10 {
+                assert_eq!(camera.draw_order(&world), vec![2, 0, 1, 3]);
+            }
+        });
+    }
+
+    #[test]
+    fn png_bytes_load_into_a_shared_texture() {
+        with_texture_manager(|mut texture_manager| {
+            let id = texture_manager.load_texture_bytes(RED_PIXEL_PNG).unwrap();
 
+            assert!(texture_manager.get_texture(id).is_some());
+            assert_eq!(texture_manager.load_texture_bytes(RED_PIXEL_PNG), Ok(id));
+            assert_eq!(texture_manager.texture_count(), 1);
+
+            assert!(texture_manager.load_texture_bytes(b"not an image").is_err());
+            assert_eq!(texture_manager.texture_count(), 1);
+        });
+    }
+
+    #[test]
+    fn relative_paths_resolve_against_the_asset_root() {
+        with_texture_manager(|mut texture_manager| {
+            assert_eq!(texture_manager.resolve("box.png"), "box.png");
+            assert!(texture_manager.load_texture("box.png").is_err());
+
+            texture_manager.asset_root = PathBuf::from("assets");
+            assert_eq!(texture_manager.resolve("box.png"), Path::new("assets").join("box.png").to_string_lossy());
+            let absolute = std::env::current_dir().unwrap().join("box.png").to_string_lossy().into_owned();
+            assert_eq!(texture_manager.resolve(&absolute), absolute);
+            assert!(texture_manager.load_texture("box.png").is_ok());
+        });
+    }
+
+    #[test]
+    fn unreadable_fonts_fall_back_to_the_default() {
+        with_sdl(|_, _| {
+            let ttf_context = sdl2::ttf::init().unwrap();
+
+            let logs = logger::capture(|| {
+                load_font_or_default(&ttf_context, "assets/PressStart2P-vaV7.ttf", 12);
+            });
+            assert!(logs.is_empty(), "logged {:?}", logs);
+
+            let logs = logger::capture(|| {
+                load_font_or_default(&ttf_context, "assets/missing.ttf", 12);
+            });
+            assert_eq!(logs.len(), 1);
+            assert!(logs[0].starts_with("[WARN] Could not load font 'assets/missing.ttf', using the default font"), "logged {:?}", logs);
+        });
+    }
+
+    #[test]
+    fn flashes_fade_to_transparent_over_their_duration() {
+        with_graphics_system(test_config(vec![test_camera(64, 64, 1)]), |_, mut system, texture_manager| {
+            let mut world = WorldBuilder::new(texture_manager).current_world("test").build();
+            world.commands.flashes.push((Color::RGBA(255, 255, 255, 200), 0.5));
+
+            // A requested flash starts after the frame's fade, so it is drawn at full strength first
+            system.run(&mut world, 0.125);
+            assert_eq!(system.flash_alpha(), 200);
+
+            for alpha in [150, 100, 50, 0] {
+                system.run(&mut world, 0.125);
+                assert_eq!(system.flash_alpha(), alpha);
+            }
+            assert!(system.flash.is_none());
+        });
+    }
+
+    #[test]
+    fn deloading_worlds_drops_their_textures() {
+        with_texture_manager(|mut texture_manager| {
+            texture_manager.asset_root = PathBuf::from("assets");
+            let player = texture_manager.load_texture("timmy_walking.png").unwrap();
+            let mut world = WorldBuilder::new(texture_manager).current_world("test").build();
+
+            for background in ["forest.png", "lake.png", "cellar.png", "forest.png"] {
+                world.texture_manager.begin_scope();
+                world.texture_manager.load_texture(background).unwrap();
+                world.texture_manager.load_texture("key.png").unwrap();
+                assert_eq!(world.texture_manager.texture_count(), 3);
+
+                world.deload();
+                assert_eq!(world.texture_manager.texture_count(), 1);
+            }
+
+            // The player's texture was loaded by the game rather than a world, so it stays loaded
+            assert!(world.texture_manager.get_texture(player).is_some());
+            assert_eq!(world.texture_manager.load_texture("timmy_walking.png"), Ok(player));
+        });
+    }
+
+    #[test]
+    fn iso_cameras_project_onto_a_two_to_one_diamond() {
+        let mut camera = test_camera(64, 64, 2);
+        camera.projection = Projection::Iso;
+        let screen = |x: f32, y: f32| {
+            let rect = camera.view(Rect::new(x, y, 4, 4), (64, 64));
+            (rect.x, rect.y)
+        };
+
+        assert_eq!(screen(0.0, 0.0), (0.0, 0.0));
+        assert_eq!(screen(10.0, 0.0), (20.0, 10.0));
+        assert_eq!(screen(0.0, 10.0), (-20.0, 10.0));
+        assert_eq!(screen(10.0, 10.0), (0.0, 20.0));
+
+        with_texture_manager(|texture_manager| {
+            let sprite = || Some(GraphicsComponent::new(0, Rect::new(0.0, 0.0, 8, 8), None));
+            let world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .entity(Some(PositionComponent::new(0.0, 30.0)), None, sprite(), None, None, Vec::new())
+                .entity(Some(PositionComponent::new(25.0, 10.0)), None, sprite(), None, None, Vec::new())
+                .build();
+
+            // Top down the higher entity is drawn behind, while iso sorts by x + y and draws the one further right in front
+            assert_eq!(test_camera(64, 64, 1).draw_order(&world), vec![1, 0]);
+            assert_eq!(camera.draw_order(&world), vec![0, 1]);
+        });
+    }
+
+    #[test]
+    fn overlays_persist_until_an_action_changes_them() {
+        let mut config = test_config(vec![test_camera(64, 64, 1)]);
+        config.overlay = Some(Color::RGBA(0, 0, 40, 120));
+
+        with_graphics_system(config, |_, mut system, texture_manager| {
+            let mut world = WorldBuilder::new(texture_manager).current_world("test").build();
+            let set_overlay = |world: &mut World, r, g, b, a| {
+                let mut commands = Commands::default();
+                SetOverlay { r, g, b, a }.tick(&mut HashSet::new(), &mut Vec::new(), &mut None, &mut commands);
+                world.commands.overlays.append(&mut commands.overlays);
+            };
+
+            system.run(&mut world, 0.1);
+            assert_eq!(system.overlay, Some(Color::RGBA(0, 0, 40, 120)));
+
+            // Unlike a flash the tint holds for as long as the world runs
+            set_overlay(&mut world, 255, 140, 0, 60);
+            for _ in 0..5 {
+                system.run(&mut world, 1.0);
+                assert_eq!(system.overlay, Some(Color::RGBA(255, 140, 0, 60)));
+            }
+
+            set_overlay(&mut world, 255, 140, 0, 0);
+            system.run(&mut world, 0.1);
+            assert_eq!(system.overlay, None);
+
+            // The given alpha replaces the color's own
+            system.set_overlay(Color::RGBA(10, 20, 30, 255), 90);
+            assert_eq!(system.overlay, Some(Color::RGBA(10, 20, 30, 90)));
+        });
+    }
+
+    #[test]
+    fn nine_slices_keep_the_borders_at_native_size() {
+        let rect = |x, y, w, h| sdl2::rect::Rect::new(x, y, w, h);
+        let insets = Insets { left: 4, top: 6, right: 8, bottom: 2 };
+
+        let pieces = nine_slice(rect(0, 0, 24, 24), rect(10, 20, 100, 50), insets);
+
+        let (src_cols, src_rows) = ([(0, 4), (4, 12), (16, 8)], [(0, 6), (6, 16), (22, 2)]);
+        let (dst_cols, dst_rows) = ([(10, 4), (14, 88), (102, 8)], [(20, 6), (26, 42), (68, 2)]);
+        let mut expected = Vec::new();
+        for row in 0..3 {
+            for col in 0..3 {
+                expected.push((
+                    rect(src_cols[col].0, src_rows[row].0, src_cols[col].1, src_rows[row].1),
+                    rect(dst_cols[col].0, dst_rows[row].0, dst_cols[col].1, dst_rows[row].1)
+                ));
+            }
+        }
+        assert_eq!(pieces, expected);
+
+        // Without borders the whole texture is stretched as one piece
+        let none = Insets { left: 0, top: 0, right: 0, bottom: 0 };
+        assert_eq!(nine_slice(rect(0, 0, 24, 24), rect(10, 20, 100, 50), none), vec![(rect(0, 0, 24, 24), rect(10, 20, 100, 50))]);
+    }
+
+    #[test]
+    fn layers_scroll_by_their_parallax_factor() {
+        let mut camera = test_camera(64, 64, 2);
+        let layer = Rect::new(4.0, 0.0, 32, 32);
+        let offset = |camera: &Camera, parallax| {
+            let rect = camera.layer_rect(layer, parallax, (64, 64));
+            (rect.x, rect.y)
+        };
+
+        assert_eq!(offset(&camera, 0.0), (4.0, 0.0));
+        assert_eq!(offset(&camera, 0.5), (4.0, 0.0));
+
+        // Zoomed in twice, the world moves 80 pixels on screen as the camera moves 40 units
+        camera.rect.x = 40.0;
+        camera.rect.y = 20.0;
+        assert_eq!(offset(&camera, 0.0), (4.0, 0.0));
+        assert_eq!(offset(&camera, 0.5), (-36.0, -20.0));
+        assert_eq!(offset(&camera, 1.0), (-76.0, -40.0));
+    }
+}
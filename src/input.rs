@@ -16,7 +16,13 @@ use crate::effect::EffectSpawner;
 #[derive(Debug)]
 pub struct InputConfig {
     keymap: HashMap<Keycode, EffectSpawner>,
-    buttonmap: HashMap<Button, EffectSpawner>
+    buttonmap: HashMap<Button, EffectSpawner>,
+    /// Time in seconds for key and button movement to reach full speed, instant if none
+    pub smoothing: Option<f32>,
+    /// Key which closes the current dialog at once
+    pub skip_key: Option<Keycode>,
+    /// Button which closes the current dialog at once
+    pub skip_button: Option<Button>
 }
 
 impl InputConfig {
@@ -24,7 +30,10 @@ impl InputConfig {
     pub fn new() -> InputConfig {
         InputConfig {
             keymap: HashMap::new(),
-            buttonmap: HashMap::new()
+            buttonmap: HashMap::new(),
+            smoothing: None,
+            skip_key: None,
+            skip_button: None
         }
     }
 
@@ -45,6 +54,52 @@ impl InputConfig {
             self.buttonmap.insert(button, es);
         }
     }
+
+    /// Move the effect of one key to another, such as from a settings menu.
+    /// An effect already on the new key moves to the old key, so no binding is lost.
+    /// Returns false if the old key has no binding
+    pub fn rebind_key(&mut self, old: Keycode, new: Keycode) -> bool {
+        let es = match self.keymap.remove(&old) {
+            Some(es) => es,
+            None => return false
+        };
+
+        if let Some(replaced) = self.keymap.insert(new, es) {
+            self.keymap.insert(old, replaced);
+        }
+
+        true
+    }
+
+    /// Bind an effect to a key or button from its name, replacing any existing binding.
+    /// Returns false if the name is neither a key nor a button
+    pub fn set_binding(&mut self, name: &str, effect: EffectSpawner) -> bool {
+        if let Some(key) = Keycode::from_name(name) {
+            self.keymap.insert(key, effect);
+            true
+        } else if let Some(button) = Button::from_string(name) {
+            self.buttonmap.insert(button, effect);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Names of every bound key and button with their effects, keys first, each sorted by name
+    pub fn bindings(&self) -> Vec<(String, &EffectSpawner)> {
+        let mut keys: Vec<(String, &EffectSpawner)> = self.keymap.iter()
+            .map(|(key, es)| (key.name(), es))
+            .collect();
+        let mut buttons: Vec<(String, &EffectSpawner)> = self.buttonmap.iter()
+            .map(|(button, es)| (button.string(), es))
+            .collect();
+
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        buttons.sort_by(|a, b| a.0.cmp(&b.0));
+
+        keys.extend(buttons);
+        keys
+    }
 }
 
 
@@ -53,14 +108,20 @@ pub struct InputSystem {
     config: InputConfig,
     /// Set of all the currently pressed keys
     key_state: HashSet<Keycode>,
-    /// Set of all the currently pressed buttons
+    /// Set of all the currently held buttons
     button_state: HashSet<Button>,
+    /// Set of the buttons pressed since the last run
+    just_pressed_buttons: HashSet<Button>,
     /// Subsystem for enumerating, opening, and closing controllers
     controller_system: GameControllerSubsystem,
     /// Currently selected controller
     controller: Option<GameController>,
-    /// Currently selected controller id
-    controller_id: u32
+    /// Instance id of the currently selected controller
+    controller_id: u32,
+    /// Current magnitude of smoothed key and button movement, from 0 to 1
+    input_magnitude: f32,
+    /// Direction of the last key and button movement
+    input_dir: f32
 }
 
 impl InputSystem {
@@ -70,9 +131,40 @@ impl InputSystem {
             config,
             key_state: HashSet::new(),
             button_state: HashSet::new(),
+            just_pressed_buttons: HashSet::new(),
             controller_system: gs,
             controller: None,
-            controller_id: 0
+            controller_id: 0,
+            input_magnitude: 0.0,
+            input_dir: 0.0
+        }
+    }
+
+    /// Replace the key and button mappings with those of a new InputConfig
+    pub fn apply_config(&mut self, config: InputConfig) {
+        self.config = config;
+    }
+
+    /// Move the effect of one key to another, see `InputConfig::rebind_key`
+    pub fn rebind_key(&mut self, old: Keycode, new: Keycode) -> bool {
+        self.key_state.remove(&old);
+        self.config.rebind_key(old, new)
+    }
+
+    /// Bind an effect to a key or button from its name, see `InputConfig::set_binding`
+    pub fn set_binding(&mut self, name: &str, effect: EffectSpawner) -> bool {
+        self.config.set_binding(name, effect)
+    }
+
+    /// Every bound key and button with its effect, see `InputConfig::bindings`
+    pub fn bindings(&self) -> Vec<(String, &EffectSpawner)> {
+        self.config.bindings()
+    }
+
+    /// Rumble the active controller, doing nothing if there is no controller or it cannot rumble
+    pub fn rumble(&mut self, low: u16, high: u16, duration_ms: u32) {
+        if let Some(controller) = self.controller.as_mut() {
+            let _ = controller.set_rumble(low, high, duration_ms);
         }
     }
 
@@ -87,49 +179,105 @@ impl InputSystem {
             }
             Event::ControllerButtonDown { button, .. } => {
                 self.button_state.insert(button);
+                self.just_pressed_buttons.insert(button);
             }
             Event::ControllerButtonUp { button, .. } => {
                 self.button_state.remove(&button);
             }
-            Event::ControllerDeviceAdded { which, .. } => {
-                self.controller = Some(self.controller_system.open(which).unwrap());
+            // Keep using the active controller if there already is one
+            Event::ControllerDeviceAdded { which, .. } if self.controller.is_none() => {
+                self.open_controller(which);
             }
-            Event::ControllerDeviceRemoved { which, .. } => {
-                if which==self.controller_id {
-                    self.controller = None;
+            Event::ControllerDeviceRemoved { which, .. } if self.controller.is_some() && which==self.controller_id => {
+                self.controller = None;
+                self.button_state.clear();
+
+                // Fall back to any other connected controller
+                let count = self.controller_system.num_joysticks().unwrap_or(0);
+                for index in 0..count {
+                    if self.open_controller(index) {
+                        break;
+                    }
                 }
             }
             _ => {}
         }
     }
 
-    /// Based on current input modify the world state
-    pub fn run(&mut self, world: &mut World) {
+    /// Based on current input modify the world state, smoothing movement over dt seconds
+    pub fn run(&mut self, world: &mut World, dt: f32) {
+        // Buttons pressed since the last run, so a held button only triggers once
+        let just_pressed_buttons = std::mem::take(&mut self.just_pressed_buttons);
+
+        // Play every rumble requested by actions
+        for (low, high, duration_ms) in std::mem::take(&mut world.commands.rumbles) {
+            self.rumble(low, high, duration_ms);
+        }
+
         // Act based up on current key state
 
-        // If a dialog exists, process no future input and instead wait for the e key
+        // If a dialog exists, process no future input and instead wait for the e key,
+        // or close it at once with the skip key
         if world.curr_dialog.is_some() {
             let dialog = world.dialogs.get_mut(world.curr_dialog.as_ref().unwrap()).unwrap();
 
-            if self.key_state.contains(&Keycode::E) || self.button_state.contains(&Button::A) {
+            let skip_key = self.config.skip_key.filter(|k| self.key_state.contains(k));
+            let skip_button = self.config.skip_button.filter(|b| just_pressed_buttons.contains(b));
+
+            if skip_key.is_some() || skip_button.is_some() {
+                // Clear the dialog first so the after actions can show another one
+                dialog.reset();
+                world.curr_dialog = None;
+                dialog.run_after(&mut world.effects, &mut world.curr_dialog, &mut world.commands);
+
+                if let Some(key) = skip_key {
+                    self.key_state.remove(&key);
+                }
+            } else if !dialog.revealed() {
+                // Show the rest of a message still being typed out instead of moving past it
+                if self.key_state.remove(&Keycode::E) || just_pressed_buttons.contains(&Button::A) {
+                    dialog.reveal_all();
+                }
+            } else if !dialog.choices().is_empty() {
+                // Move the highlight with up and down, and pick the highlighted choice with e
+                if self.key_state.remove(&Keycode::Up) || just_pressed_buttons.contains(&Button::DPadUp) {
+                    dialog.select(-1);
+                }
+
+                if self.key_state.remove(&Keycode::Down) || just_pressed_buttons.contains(&Button::DPadDown) {
+                    dialog.select(1);
+                }
+
+                if self.key_state.remove(&Keycode::E) || just_pressed_buttons.contains(&Button::A) {
+                    let mut next_dialog = world.curr_dialog.clone();
+
+                    if !dialog.choose(&mut world.effects, &mut next_dialog, &mut world.commands) {
+                        // Clear the dialog first so the after actions can show another one
+                        dialog.reset();
+                        next_dialog = None;
+                        dialog.run_after(&mut world.effects, &mut next_dialog, &mut world.commands);
+                    }
+
+                    world.curr_dialog = next_dialog;
+                }
+            } else if self.key_state.contains(&Keycode::E) || just_pressed_buttons.contains(&Button::A) {
                 if dialog.finished() {
                     dialog.next();
-                    dialog.run_after(&mut world.effects, &mut world.curr_dialog);
                     world.curr_dialog = None;
+                    dialog.run_after(&mut world.effects, &mut world.curr_dialog, &mut world.commands);
                 } else {
                     dialog.next();
                 }
 
 
                 self.key_state.remove(&Keycode::E);
-                self.button_state.remove(&Button::A);
             }
 
             return;
         }
 
         // Player movement
-        let player = 0;
+        let player = world.player_id;
         if let (Some(pos), Some(physics_state)) = (world.positions[player].as_mut(), world.physics[player].as_mut()) {
             // If the interact key is pressed try to interact with the object that is in front of us
             let player_rect = physics_state.hitbox
@@ -139,11 +287,7 @@ impl InputSystem {
             for key in self.config.keymap.keys() {
                 if self.key_state.contains(key) {
                     let mut effect = self.config.keymap[key].spawn();
-
-                    effect.rect.x += player_rect.x;
-                    effect.rect.y += player_rect.y;
-                    effect.rect.w += player_rect.w;
-                    effect.rect.h += player_rect.h;
+                    effect.place_around(player_rect);
 
                     world.effects.push(effect);
                     self.key_state.remove(key);
@@ -151,49 +295,62 @@ impl InputSystem {
             }
 
             for button in self.config.buttonmap.keys() {
-                if self.button_state.contains(button) {
+                if just_pressed_buttons.contains(button) {
                     let mut effect = self.config.buttonmap[button].spawn();
-
-                    effect.rect.x += player_rect.x;
-                    effect.rect.y += player_rect.y;
-                    effect.rect.w += player_rect.w;
-                    effect.rect.h += player_rect.h;
+                    effect.place_around(player_rect);
 
                     world.effects.push(effect);
-                    self.button_state.remove(button);
                 }
             }
 
             // If joystick connected and its values beyond the deadzone use it, otherwise
             // buttons and keys
-            let max_mag = 55.0;
+            let max_mag = physics_state.speed;
 
-            let (x, y) = if self.controller.is_some() {
+            let joystick = if self.controller.is_some() {
                 let (x, y) = self.joystick_velocity();
 
                 if x.abs() < 0.01 && y.abs() < 0.01 {
-                    self.button_velocity()
+                    None
                 } else {
-                    (x, y)
+                    Some((x, y))
                 }
             } else {
-                self.button_velocity()
+                None
             };
 
-            let mut vel = Vector::from_components(x, y);
+            let mut vel = match joystick {
+                Some((x, y)) => {
+                    self.input_magnitude = 0.0;
+                    Vector::from_components(x, y)
+                }
+                None => {
+                    let (x, y) = self.button_velocity();
+                    self.smoothed_velocity(x, y, dt)
+                }
+            };
 
             if vel.mag > 1.0 {vel.mag=1.0;}
 
             vel.mag *= max_mag;
-            physics_state.velocity = vel;
+
+            // Under gravity input only steers sideways, leaving the fall to physics
+            if world.gravity.is_some() && physics_state.affected_by_gravity {
+                physics_state.velocity = Vector::from_components(vel.x(), physics_state.velocity.y());
+            } else {
+                physics_state.velocity = vel;
+            }
 
             // Set appropriate states for idle and walking
+            let idle = world.state_names.idle.clone();
+            let walking = world.state_names.walking.clone();
+
             if vel.mag != 0.0 {
-                world.remove_entity_state(player, &"idle".to_string());
-                world.add_entity_state(player, "walking".into());
+                world.remove_entity_state(player, &idle);
+                world.add_entity_state(player, walking);
             } else {
-                world.remove_entity_state(player, &"walking".to_string());
-                world.add_entity_state(player, "idle".into());
+                world.remove_entity_state(player, &walking);
+                world.add_entity_state(player, idle);
             }
 
             // If the player is drawable, make sure to flip it when moving the other way
@@ -208,6 +365,48 @@ impl InputSystem {
 
         }
     }
+    /// Open the controller at a device index and make it active, returning whether it succeeded
+    fn open_controller(&mut self, index: u32) -> bool {
+        if !self.controller_system.is_game_controller(index) {
+            return false;
+        }
+
+        match self.controller_system.open(index) {
+            Ok(controller) => {
+                self.controller_id = controller.instance_id();
+                self.controller = Some(controller);
+                true
+            }
+            Err(_) => false
+        }
+    }
+
+    /// Ease key and button movement towards full speed or a stop if smoothing is enabled
+    fn smoothed_velocity(&mut self, x: f32, y: f32, dt: f32) -> Vector {
+        let raw = Vector::from_components(x, y);
+
+        let smoothing = match self.config.smoothing {
+            Some(t) if t > 0.0 => t,
+            _ => return raw
+        };
+
+        let target = if raw.mag != 0.0 {
+            self.input_dir = raw.dir;
+            1.0
+        } else {
+            0.0
+        };
+
+        let step = dt / smoothing;
+        if self.input_magnitude < target {
+            self.input_magnitude = (self.input_magnitude + step).min(target);
+        } else {
+            self.input_magnitude = (self.input_magnitude - step).max(target);
+        }
+
+        Vector::new(self.input_dir, self.input_magnitude)
+    }
+
     /// Move the player using the joysticks
     fn joystick_velocity(&self) -> (f32, f32) {
         let c = self.controller.as_ref().unwrap();
@@ -239,3 +438,179 @@ impl InputSystem {
         (east-west, south-north)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdl2::keyboard::Mod;
+    use crate::actions::{Action, PlaySound};
+    use crate::dialog::Dialog;
+    use crate::geometry::{PositionComponent, Rect};
+    use crate::graphics::{with_sdl, TextureManager};
+    use crate::physics::PhysicsComponent;
+    use crate::state::Sequence;
+    use crate::world::WorldBuilder;
+
+    fn key_down(keycode: Keycode) -> Event {
+        Event::KeyDown { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None, keymod: Mod::NOMOD, repeat: false }
+    }
+
+    fn key_up(keycode: Keycode) -> Event {
+        Event::KeyUp { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None, keymod: Mod::NOMOD, repeat: false }
+    }
+
+    fn button_down(button: Button) -> Event {
+        Event::ControllerButtonDown { timestamp: 0, which: 0, button }
+    }
+
+    /// A world holding only a player at the origin
+    fn player_world(texture_manager: TextureManager) -> World {
+        let physics = PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 10, true);
+
+        WorldBuilder::new(texture_manager)
+            .current_world("test")
+            .entity(Some(PositionComponent::new(0.0, 0.0)), Some(physics), None, None, None, Vec::new())
+            .build()
+    }
+
+    #[test]
+    fn movement_uses_the_configured_state_names() {
+        with_sdl(|sdl, texture_manager| {
+            let mut world = player_world(texture_manager);
+            world.state_names.idle = "standing".into();
+            world.state_names.walking = "running".into();
+            let mut input = InputSystem::new(InputConfig::new(), sdl.game_controller().unwrap());
+
+            input.handle_event(key_down(Keycode::D));
+            input.run(&mut world, 0.1);
+            assert!(world.states[0].contains("running"));
+            assert!(!world.states[0].contains("walking"));
+
+            input.handle_event(key_up(Keycode::D));
+            input.run(&mut world, 0.1);
+            assert!(world.states[0].contains("standing"));
+            assert!(!world.states[0].contains("running"));
+            assert!(!world.states[0].contains("idle"));
+        });
+    }
+
+    #[test]
+    fn held_buttons_fire_once() {
+        with_sdl(|sdl, texture_manager| {
+            let mut world = player_world(texture_manager);
+            let mut config = InputConfig::new();
+            config.buttonmap.insert(Button::X, EffectSpawner::new(vec!["talk".into()], Vec::new(), Rect::new(0.0, 0.0, 4, 4), Some(0.1), None));
+            let mut input = InputSystem::new(config, sdl.game_controller().unwrap());
+
+            input.handle_event(button_down(Button::X));
+            for _ in 0..3 {
+                input.run(&mut world, 0.1);
+            }
+
+            assert_eq!(world.effects.len(), 1);
+        });
+    }
+
+    #[test]
+    fn rumble_without_a_controller_does_nothing() {
+        with_sdl(|sdl, texture_manager| {
+            let mut world = player_world(texture_manager);
+            let mut input = InputSystem::new(InputConfig::new(), sdl.game_controller().unwrap());
+
+            input.rumble(0xFFFF, 0xFFFF, 200);
+
+            world.commands.rumbles.push((0x8000, 0x8000, 100));
+            input.run(&mut world, 0.1);
+
+            assert!(input.controller.is_none());
+            assert!(world.commands.rumbles.is_empty());
+        });
+    }
+
+    #[test]
+    fn smoothed_movement_ramps_up_over_several_frames() {
+        with_sdl(|sdl, texture_manager| {
+            let mut world = player_world(texture_manager);
+            let speed = world.physics[0].as_ref().unwrap().speed;
+            let mut config = InputConfig::new();
+            config.smoothing = Some(0.4);
+            let mut input = InputSystem::new(config, sdl.game_controller().unwrap());
+
+            input.handle_event(key_down(Keycode::D));
+
+            let mut speeds = Vec::new();
+            for _ in 0..5 {
+                input.run(&mut world, 0.1);
+                speeds.push(world.physics[0].as_ref().unwrap().velocity.mag / speed);
+            }
+
+            for (got, expected) in speeds.iter().zip([0.25, 0.5, 0.75, 1.0, 1.0]) {
+                assert!((got - expected).abs() < 1e-4, "ramped as {:?}", speeds);
+            }
+        });
+    }
+
+    #[test]
+    fn full_input_reaches_the_player_speed() {
+        with_sdl(|sdl, texture_manager| {
+            let mut world = player_world(texture_manager);
+            world.physics[0].as_mut().unwrap().speed = 120.0;
+            let mut input = InputSystem::new(InputConfig::new(), sdl.game_controller().unwrap());
+
+            input.handle_event(key_down(Keycode::S));
+            input.run(&mut world, 0.1);
+
+            assert_eq!(world.physics[0].as_ref().unwrap().velocity.mag, 120.0);
+        });
+    }
+
+    #[test]
+    fn skipping_closes_a_dialog_and_runs_its_after_actions_once() {
+        with_sdl(|sdl, texture_manager| {
+            let mut world = player_world(texture_manager);
+            let after = Sequence::new(vec![(0.0, Box::new(PlaySound { path: "chime.wav".into() }) as Box<dyn Action>)]);
+            world.add_dialog("intro".into(), Dialog::new(vec!["One".into(), "Two".into(), "Three".into()], Some(after), true));
+            world.curr_dialog = Some("intro".into());
+
+            let mut config = InputConfig::new();
+            config.skip_key = Some(Keycode::Q);
+            let mut input = InputSystem::new(config, sdl.game_controller().unwrap());
+
+            // Move past the first message before skipping the rest
+            world.dialogs.get_mut("intro").unwrap().reveal_all();
+            input.handle_event(key_down(Keycode::E));
+            input.run(&mut world, 0.1);
+            input.handle_event(key_up(Keycode::E));
+            assert_eq!(world.dialogs["intro"].msg(), "Two");
+
+            input.handle_event(key_down(Keycode::Q));
+            for _ in 0..3 {
+                input.run(&mut world, 0.1);
+            }
+
+            assert!(world.curr_dialog.is_none());
+            assert_eq!(world.commands.sounds, vec!["chime.wav".to_string()]);
+            assert_eq!(world.dialogs["intro"].msg(), "One");
+        });
+    }
+
+    #[test]
+    fn input_moves_whichever_entity_is_the_player() {
+        with_sdl(|sdl, texture_manager| {
+            let mut world = player_world(texture_manager);
+            world.player_id = world.build_entity()
+                .position(40.0, 0.0)
+                .physics(PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 10, true))
+                .spawn();
+            let mut input = InputSystem::new(InputConfig::new(), sdl.game_controller().unwrap());
+
+            input.handle_event(key_down(Keycode::D));
+            input.run(&mut world, 0.1);
+
+            assert!(world.physics[1].as_ref().unwrap().velocity.mag > 0.0);
+            assert!(world.states[1].contains("walking"));
+            assert_eq!(world.physics[0].as_ref().unwrap().velocity.mag, 0.0);
+            assert!(world.states[0].is_empty());
+        });
+    }
+}
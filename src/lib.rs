@@ -14,3 +14,10 @@ pub mod ai;
 pub mod pathfinding;
 pub mod priority_queue;
 pub mod tree;
+pub mod game;
+pub mod follow;
+pub mod projectile;
+pub mod logger;
+pub mod stats;
+pub mod sound;
+pub mod tilemap;
@@ -0,0 +1,81 @@
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Environment variable which sets the most verbose level logged,
+/// options: off, error, warn, info, debug, trace
+pub const LOG_VAR: &str = "LENGINE_LOG";
+
+/// Logger which writes engine diagnostics to stderr
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Install the stderr logger, reading the level from `LENGINE_LOG` (default warn).
+/// Does nothing if another logger is already installed
+pub fn init() {
+    let level = std::env::var(LOG_VAR).ok()
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(LevelFilter::Warn);
+
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Messages logged on this thread while capturing, none when not capturing
+    static CAPTURED: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Logger which keeps the messages of each thread capturing them, so tests can check what was logged
+#[cfg(test)]
+struct CaptureLogger;
+
+#[cfg(test)]
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        CAPTURED.with(|captured| {
+            if let Some(messages) = captured.borrow_mut().as_mut() {
+                messages.push(format!("[{}] {}", record.level(), record.args()));
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+static CAPTURE_LOGGER: CaptureLogger = CaptureLogger;
+
+/// Run f, returning every message it logged at any level
+#[cfg(test)]
+pub(crate) fn capture<F: FnOnce()>(f: F) -> Vec<String> {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        if log::set_logger(&CAPTURE_LOGGER).is_ok() {
+            log::set_max_level(LevelFilter::Trace);
+        }
+    });
+
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+    f();
+    CAPTURED.with(|captured| captured.borrow_mut().take().unwrap_or_default())
+}
@@ -1,26 +1,45 @@
+use std::fs;
 use std::process::exit;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
-use game::animation::AnimationSystem;
-use game::effect::EffectSystem;
+use log::error;
+
+use game::game::Game;
 use game::input::InputSystem;
-use game::physics::PhysicsSystem;
-use game::state::StateSystem;
-use sdl2::event::{Event, WindowEvent};
+use game::sound::SoundSystem;
 use sdl2::image::InitFlag;
-use sdl2::keyboard::Keycode;
 
 use game::graphics::{TextureManager, GraphicsSystem};
 use game::parser::parse_game_file;
+use game::logger;
+use game::world::World;
+
+/// Path of the game file, read once at startup and watched for edits
+const GAME_FILE: &str = "./game.yml";
+
+/// Modification time of a file, if it can be read
+fn file_modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).ok().and_then(|metadata| metadata.modified().ok())
+}
+
+/// Name and modification time of the current world's file, if it can be read
+fn world_file_modified(world: &World) -> (String, Option<SystemTime>) {
+    let modified = world.current_world_path().and_then(|path| file_modified(path));
+
+    (world.current_world.clone(), modified)
+}
 
 
 fn main() {
+    logger::init();
+
     // Create context and relevant subsystems
     let sdl2_context = sdl2::init().unwrap();
     let video_subsystem = sdl2_context.video().unwrap();
     let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG).unwrap();
     let ttf_context = sdl2::ttf::init().unwrap();
     let controller_subsystem = sdl2_context.game_controller().unwrap();
+    let audio_subsystem = sdl2_context.audio().ok();
 
     // Create graphics objects such as window, canvas, and texture manager
     let mut window = video_subsystem.window("title", 1000, 800)
@@ -38,57 +57,50 @@ fn main() {
     let texture_creator = canvas.texture_creator();
     let texture_manager = TextureManager::new(&texture_creator);
 
-    let (mut world, input_config, graphics_config, mut ai_system) = parse_game_file("./game.yml", texture_manager);
+    let (world, input_config, graphics_config, ai_system, sound_config) = match parse_game_file(GAME_FILE, texture_manager) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            error!("Could not load game.yml: {}", e);
+            exit(1);
+        }
+    };
 
     // Create Game Systems
-    let mut input_system = InputSystem::new(input_config, controller_subsystem);
-    let mut physics_system = PhysicsSystem::new();
-    let mut graphics_system = GraphicsSystem::new(graphics_config, &ttf_context, &mut canvas);
-    let mut animation_system = AnimationSystem::new();
-    let mut effects_system = EffectSystem::new();
-    let mut state_system = StateSystem::new();
+    let input_system = InputSystem::new(input_config, controller_subsystem);
+    let graphics_system = GraphicsSystem::new(graphics_config, &ttf_context, &mut canvas);
+    let sound_system = SoundSystem::new(sound_config, audio_subsystem);
+
+    let mut game = Game::new(world, input_system, graphics_system, sound_system, ai_system);
 
     // Run Game Loop
+    let frame_time = Duration::from_secs_f32(1.0 / game.world.frame_rate.max(1.0));
+    let mut last_frame = Instant::now();
+    let mut last_modified = world_file_modified(&game.world);
+    let mut game_modified = file_modified(GAME_FILE);
     loop {
-        // Handle events
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit {..} |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    exit(0);
-                },
-                Event::Window { win_event: WindowEvent::Resized(_, _), .. } => {
-                    graphics_system.refresh();
-                }
-                _ => {input_system.handle_event(event)}
-            }
+        let dt = last_frame.elapsed().as_secs_f32();
+        last_frame = Instant::now();
+
+        let events = event_pump.poll_iter().collect();
+        if !game.step(dt, events) {
+            exit(0);
         }
 
-        // Run all subsystems
-        input_system.run(&mut world);
-        physics_system.run(&mut world);
-        state_system.run(&mut world);
-        animation_system.run(&mut world);
-        graphics_system.run(&mut world);
-        effects_system.run(&mut world);
-
-        // Check if the player is being moved to another world
-        let player_states = world.states[0].clone();
-        for state in player_states {
-            if state.starts_with("__MOVE_TO__=") {
-                let s = state.replace("__MOVE_TO__=", "");
-                let (file, entrance) = s.split_once("/").unwrap();
-                world.deload();
-                world.load(file, entrance);
-
-                world.states[0].remove(&state);
-                break;
-            }
+        // Reload everything when the game file is edited
+        let modified = file_modified(GAME_FILE);
+        if modified != game_modified {
+            game.reload_from_file(GAME_FILE);
         }
+        game_modified = modified;
 
-        ai_system.run(&mut world);
+        // Reload the current world when its file is edited, but not when moving to another world
+        let modified = world_file_modified(&game.world);
+        if modified.0 == last_modified.0 && modified.1 != last_modified.1 {
+            game.reload();
+        }
+        last_modified = modified;
 
-        // Sleep
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        // Sleep for whatever is left of the frame
+        ::std::thread::sleep(frame_time.saturating_sub(last_frame.elapsed()));
     }
 }
@@ -4,7 +4,16 @@
 //! A world file is a yaml file with the following structure:
 //!
 //! ```yaml
+//! asset_root: string  # Directory relative paths are resolved against, relative to the game file (game file only, default the game file's directory)
 //! graphics:           # Configuration for GraphicsSystem
+//!   debug: bool       # Draw hitboxes, effects, and a world grid (default false)
+//!   grid_spacing: u32 # Spacing of the debug grid in world units, 0 for none (default 16)
+//!   overlay:          # Tint drawn over the world, such as darkness in a cave (default none)
+//!     r: u8           # Red component
+//!     g: u8           # Green component
+//!     b: u8           # Blue component
+//!     a: u8           # Opacity (default 255)
+//!   fade_duration: f32    # Seconds to fade to black, and again to fade in, when moving between worlds (default 0.3)
 //!   camera:           # World Camera
 //!     rect:           # Rect defining the position of the camera
 //!       x: f32        # x position of camera in world coords (default 0)
@@ -17,6 +26,22 @@
 //!       w: u32        # width of player box in screen pixels
 //!       h: u32        # height of player box in screen pixels
 //!     zoom: u32       # camera zoom, scalar factor of world units to screen pixels (default 5)
+//!     viewport:       # Area of the window to render into (default whole window)
+//!       x: i32        # x position in screen coordinates
+//!       y: i32        # y position in screen coordinates
+//!       w: u32        # width in screen coordinates
+//!       h: u32        # height in screen coordinates
+//!     target: u32     # Id of the entity the camera follows (default 0, the player)
+//!     projection: string  # How world coordinates map to the screen, options: top_down, iso (default top_down)
+//!   cameras:          # List of cameras for split screen, used instead of camera
+//!     - camera        # Same structure as camera
+//!   minimap:          # Overlay of entity footprints (default none)
+//!     rect:           # Area of the window to draw the minimap into
+//!       x: i32        # x position in screen coordinates
+//!       y: i32        # y position in screen coordinates
+//!       w: u32        # width in screen coordinates
+//!       h: u32        # height in screen coordinates
+//!     scale: f32      # Scaling factor from world units to minimap pixels (default 0.1)
 //!   dialog:           # Configuration for rendering a dialog
 //!     path: string    # Path to dialog texture
 //!     font: string    # Path to dialog font
@@ -31,6 +56,28 @@
 //!       y: i32        # y position ins screen coordinates
 //!       w: u32        # width in screen coordinates
 //!       h: u32        # height in screen coordinates
+//!     portrait_box:   # Box to render the speaker's portrait into, relative to the renderbox (default no portraits)
+//!       x: i32        # x position in screen coordinates
+//!       y: i32        # y position in screen coordinates
+//!       w: u32        # width in screen coordinates
+//!       h: u32        # height in screen coordinates
+//!     text_speed: f32 # Characters per second the text is typed out at, pressing e shows the rest (default all at once)
+//!     insets:         # Border widths of the texture kept at native size when stretched (default stretch the whole texture)
+//!       left: u32     # width of the left border in texture pixels (default 0)
+//!       top: u32      # height of the top border in texture pixels (default 0)
+//!       right: u32    # width of the right border in texture pixels (default 0)
+//!       bottom: u32   # height of the bottom border in texture pixels (default 0)
+//! physics_hz: f32     # Fixed steps per second for physics, projectiles and states, independent of the frame rate (game file only, default 120, 0 for once per frame)
+//! physics:            # Configuration for PhysicsSystem (game file only)
+//!   gravity: f32      # Downward acceleration in pixels/second² for side-view games (default none, top-down)
+//! max_physics_steps: u32  # Most fixed steps run in one frame, dropping time beyond them (game file only, default 8)
+//! frame_rate: f32     # Frames per second the game loop aims for (game file only, default 60)
+//! sounds:             # Sounds played when an entity gains a state (game file only, default none)
+//!   string: string    # state name: path of a wav or ogg file
+//! dialog_skip:        # Key and button which close the current dialog at once (default none)
+//!   key: string       # key name
+//!   button: string    # button name
+//! input_smoothing: f32    # Seconds for key movement to reach full speed (default instant)
 //! inputs:             # List of player inputs and the effects they cause
 //!   - add:            # List of states added by input
 //!     - string        # Individual state added
@@ -43,26 +90,88 @@
 //!       y: f32        # y offset from hitbox (default -2)
 //!       w: u32        # width offset from hitbox (default 4)
 //!       h: u32        # height offset from hitbox (default 4)
+//!     circle:         # Circle for the effect, used instead of rect (default none)
+//!       x: f32        # x offset of the center from the hitbox center (default 0)
+//!       y: f32        # y offset of the center from the hitbox center (default 0)
+//!       r: f32        # radius
+//!     ttl: f32        # Time in seconds the effect lasts (default forever)
+//!     overlap_mode: string  # How much of an entity must be inside, options: intersect, contains, center_inside (default intersect)
+//!     stat_delta:     # Change per second to the stats of entities inside, overlapping effects add together
+//!       string: f32   # Stat name and its change per second
+//!     on_expire:      # Actions to run once the effect expires
+//!       - action      # See actions section
+//! state_names:        # Names of the states used by the engine (game file only)
+//!   idle: string      # Entity is not moving (default idle)
+//!   walking: string   # Entity is moving (default walking)
+//!   colliding: string # Entity is touching something (default colliding)
+//!   invisible: string # Entity is not drawn (default invisible)
+//!   aggro: string     # AI is chasing the player (default aggro)
+//!   lost: string      # AI has lost the player (default lost)
+//!   stuck: string     # Entity is trying to move but blocked (default stuck)
+//!   ai: string        # Entity is moved by the AI system, given to every entity with an ai block (default ai)
+//! ai:                 # AI behavior of entities with the ai state but no ai block of their own (game file only)
+//!   ...               # Same structure as an entity's ai block
+//! globals:            # Entities kept across worlds besides the player (game file only, default none)
+//!   - entity          # Same structure as an entity, see entities section
+//! monster: entity     # A single global entity, same as one entry of globals (game file only, default none)
 //! dialogs:            # List of dialogs that can be displayed to the scren
 //!   - name: string    # Name of the dialog
 //!     messages:       # List of messages to be displayed sequentially
 //!       - string      # A single message
+//!       - text: string    # A message with a speaker or choices
+//!         speaker: string # Name of who says the message, drawn above it (default none)
+//!         portrait: string    # Path of a portrait texture drawn beside the text (default none)
+//!         choices:        # Options offered by the message, picked with up, down, and e (default none)
+//!           - text: string    # Text of the option
+//!             goto: u32       # Index of the message shown once picked (default close the dialog)
+//!             actions:        # Actions to run once picked
+//!               - action      # See actions section
 //!     after:          # Actions to run afterwards
 //!       - action      # See actions section
+//!     freeze_world: bool  # Pause physics, animations, and AI while shown (default false)
 //! background:         # Background of the world
 //!   path: string      # path to the texture
 //!   color:            # Color for the rest of the window
 //!     r: u8           # Red component
 //!     g: u8           # Green component
 //!     b: u8           # Blue component
+//!     a: u8           # Alpha component (default 255)
+//!   out_of_bounds_color:  # Color for the part of the view the texture and layers moving with the world do not cover (default color)
+//!     r: u8           # Red component
+//!     g: u8           # Green component
+//!     b: u8           # Blue component
+//!     a: u8           # Alpha component (default 255)
 //!   renderbox:        # Rectangle to render texture
 //!     x: f32          # x position in the world (default 0)
 //!     y: f32          # y position in the world (default 0)
 //!     w: u32          # Width in world coordinates
 //!     h: u32          # Height in world coordinates
+//! backgrounds:        # Layers drawn in order over the background, scrolling at their own rate (default none)
+//!   - path: string    # path to the texture
+//!     renderbox:      # Rectangle to render texture, as in background
+//!     parallax: f32   # Fraction of the camera's movement the layer scrolls by, 0 is fixed on screen (default 1)
+//! ambient:            # List of effects applied to every entity regardless of position
+//!   - add:            # List of states added
+//!     - string        # Individual state added
+//!     remove:         # List of states removed
+//!     - string        # Individual state removed
+//! strict: bool        # Warn about unrecognized keys in entities, any `meta` key is ignored (game file only, default false)
+//! world_events:       # Events checked against the whole world every frame
+//!   - states:         # World states which must all be active
+//!     - string
+//!     any:            # Entity states which at least one entity must have
+//!     - string
+//!     none:           # Entity states which no entity may have
+//!     - string
+//!     actions:        # Actions to run while the conditions hold, state changes apply to the world
+//!       - action      # See actions section
 //! entitites:          # List of all entities in the world
-//!   - state: string   # Default starting state (default none)
+//!   - state: string   # Default starting state, or a list of them (default none)
+//!     meta:           # Annotations for authors and editors, never read by the engine
+//!     name: string    # Name other entities can refer to this entity by (the player is always "player")
 //!     player: bool    # Whether this entity is a player (default false)
+//!     static: bool    # Entity never moves or animates, only blocking others (default false)
+//!     minimap: bool   # Draw the entity on the minimap, which then only draws flagged entities (default false)
 //!     position:       # Position component for a single entity
 //!       x: f32        # x position in world coords
 //!       y: f32        # y position in world coords
@@ -73,6 +182,21 @@
 //!         w: u32      # width of hitbox
 //!         h: u32      # height of hitbox
 //!       depth: u32    # Depth in the world of the player, replaces height in hitbox (default height)
+//!       speed: f32        # Speed under full input in pixels/second (default 55)
+//!       max_speed: f32    # Maximum speed of the entity in pixels/second (default none)
+//!       stuck_delay: f32  # Seconds of blocked movement before the entity is stuck (default never)
+//!       continuous: bool  # Check movement in steps to stop fast entities passing through walls (default false)
+//!       elevation: f32    # Height of the bottom of the entity above the ground (default 0)
+//!       height: f32       # Vertical size of the entity, used to check if entities can collide (default hitbox height)
+//!       collision_layer: u32  # Bitfield of layers the entity belongs to (default 1)
+//!       collision_mask: u32   # Bitfield of layers the entity collides with (default all)
+//!       layer: u32        # Index of the single layer the entity belongs to, from 0 to 31, instead of collision_layer
+//!       mask: u32         # Same as collision_mask
+//!       trigger: bool     # Only report overlaps instead of blocking (default false)
+//!       affected_by_gravity: bool # Fall under the game's gravity, if any (default false)
+//!       occludes_sight: bool  # Block the line of sight of the AI (default physical)
+//!       see_through_states:   # States in which the entity stops blocking line of sight (default none)
+//!         - string
 //!     graphics:       # Graphics Component (requres position)
 //!       path: string  # Path of the default texture
 //!       renderbox:    # Box to render to the world, acts as offset on position
@@ -85,8 +209,15 @@
 //!         y: i32      # y position in texture
 //!         w: u32      # width of texture
 //!         h: u32      # height of texture
+//!       alpha: u8     # Opacity of the texture, 0 is invisible (default 255)
+//!       z: i32        # Layer drawn in, higher layers cover lower ones regardless of position (default 0)
+//!       tint:         # Color the texture is multiplied by (default none)
+//!         r: u8       # Red component (default 255)
+//!         g: u8       # Green component (default 255)
+//!         b: u8       # Blue component (default 255)
 //!     animations:     # List of animations that the entity can have
 //!       - state: string   # State which triggers the animation
+//!                         # suffix with _n, _ne, ..., or _up, _down, _left, _right for the direction the entity faces
 //!         period: f32     # Time until the animation switches to the next texture
 //!         path: string    # Path to the animation texture
 //!         srcbox:         # Rectangle source for the first frame of the texture (default none)
@@ -96,6 +227,43 @@
 //!           h: u32        # width of srcbox
 //!         frame_width: u32    # width of a single frame
 //!         frame_count: u32    # Number of animation frames
+//!         frame_height: u32   # height of a single frame, used when frames wrap to a new row (default 0)
+//!         columns: u32        # Number of frames in a row before wrapping to the next (default frame_count)
+//!         loop: bool          # Start over after the last frame, otherwise hold it and run after once (default true)
+//!         mode: string        # Playback, options: loop, once (same as loop: false), pingpong (frames forwards then backwards) (default loop)
+//!         after:              # Actions run once the animation completes, see actions section
+//!           - action
+//!         after_each_loop: bool   # Also run after at the end of every loop of a looping animation (default false)
+//!     tilemap:        # Grid of tiles drawn from one tileset, the top left corner at the position (requires position)
+//!       path: string  # Path of the tileset texture
+//!       tile_width: u32   # Width of a tile in the tileset and the world
+//!       tile_height: u32  # Height of a tile in the tileset and the world
+//!       rows:         # Rows of tile indices, counted left to right then top to bottom in the tileset
+//!         - [i32]     # Tile indices of a row, negative for an empty tile
+//!     follow:         # Follow another entity (requires physics)
+//!       target: string    # Name of the entity to follow
+//!       distance: f32     # Distance at which to stop following (default 20)
+//!       speed: f32        # Speed to follow at in pixels/second (default 50)
+//!       update_interval: u32  # Frames between updates while far from the player (default 1)
+//!       throttle_distance: f32    # Distance from the player beyond which updates are throttled (default 0)
+//!     stats:          # Numeric values such as health which effects can change
+//!       string: f32   # Stat name and its starting value
+//!     ai:             # Wander an idle path and chase the player on sight (requires physics)
+//!       path:         # Points walked between while idle, in order (default none, standing still)
+//!         - x: f32    # x position in world coords
+//!           y: f32    # y position in world coords
+//!           t: f32    # Seconds taken to reach this point
+//!       aggro_distance: f32   # Distance within which a visible player is chased (default 1000)
+//!       lost_delay: f32       # Seconds spent lost before returning to idle (default 5)
+//!       chase_speed: f32      # Average speed while chasing the player in pixels/second (default 54)
+//!       chase_wobble: f32     # How far the chase speed swings above and below its average (default 20)
+//!       idle_speed: f32       # Speed while walking the idle path in pixels/second (default 60)
+//!       teleport_delay: f32   # Seconds before a chasing entity appears in the world the player moved to (default 5)
+//!       world: string         # Global entities only: world the entity lives in, following the player out of it while chasing (default none)
+//!     projectile:     # Despawn the entity after a time or once it collides (requires physics)
+//!       ttl: f32      # Time in seconds the projectile lasts
+//!       on_hit:       # Actions to run when the projectile collides
+//!         - action    # See actions section
 //!     events:         # List of events that can occur for this entity
 //!       - states:     # List of necessary states which trigger the event
 //!         - string    # A state string
@@ -103,30 +271,164 @@
 //!           - type: string    # Type of action to run, options: add_state, remove_state, show_dialog
 //!             state: string   # State to add/remove
 //!             dialog: string  # dialog to show
+//!             variants:       # Dialogs shown instead when the entity has every listed state, first match wins
+//!               - states:     # Required states
+//!                 - string
+//!                 dialog: string  # dialog to show
 //!             delay: f32      # delay after the last action until this runs (default 0)
+//!             low: u16        # rumble: low frequency intensity (default 65535)
+//!             high: u16       # rumble: high frequency intensity (default 65535)
+//!             duration: u32   # rumble: duration in milliseconds (default 200)
+//!             r: u8           # flash: red component (default 255)
+//!             g: u8           # flash: green component (default 255)
+//!             b: u8           # flash: blue component (default 255)
+//!             duration: f32   # flash: seconds to fade out (default 0.2)
+//!             r, g, b, a: u8  # set_overlay: color and opacity of the world tint, an opacity of 0 removes it (default 0)
+//!             actions:        # composite: actions run together in a single tick
+//!               - action
+//!             sound: string   # play_sound: path of a wav or ogg file to play once
+//!             magnitude: f32  # shake: furthest the cameras move in world units (default 4)
+//!             duration: f32   # shake: seconds for the shake to settle (default 0.3)
+//!                             # despawn: removes the entity once the frame's systems have run, ignored without an entity
+//!             world: string   # change_world: name of the world to move the player to
+//!             entrance: string    # change_world: entrance the player is placed at (default keep position)
+//!             entity: entity  # spawn: entity added to the world at the end of the frame's systems, its position
+//!                             #   an offset from the spawning entity's position, or the world's origin without one
 //! ```
 
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
 
 use sdl2::pixels::Color;
-use yaml_rust::{Yaml, YamlLoader};
+use sdl2::keyboard::Keycode;
+use sdl2::controller::Button;
+use yaml_rust::{Yaml, YamlLoader, ScanError};
+use log::warn;
 use image::io::Reader as ImageReader;
 use image::{DynamicImage, GenericImageView};
 
-use crate::ai::AISystem;
-use crate::effect::{EffectSpawner, Effect};
+use crate::ai::{AISystem, AIComponent, AIConfig};
+use crate::effect::{EffectSpawner, Effect, OverlapMode};
 use crate::input::InputConfig;
 use crate::world::World;
-use crate::geometry::{Rect, PositionComponent};
+use crate::geometry::{Rect, Circle, PositionComponent};
 use crate::physics::PhysicsComponent;
-use crate::graphics::{GraphicsComponent, GraphicsConfig, TextureManager, Camera};
+use crate::graphics::{GraphicsComponent, GraphicsConfig, TextureManager, Camera, Minimap, Projection, Insets};
 use crate::animation::{AnimationComponent, Animation};
-use crate::state::{ActionComponent, Sequence};
-use crate::actions::{Action, AddState, RemoveState, ShowDialog, AddEffect, ExitGame, Goto};
-use crate::dialog::Dialog;
+use crate::state::{ActionComponent, Sequence, StateNames, WorldEvent};
+use crate::actions::{Action, AddState, RemoveState, ShowDialog, AddEffect, ExitGame, ChangeWorld, Rumble, PlayAnimation, StopAnimation, Composite, Flash, SetOverlay, PlaySound, Shake, Despawn, SpawnEntity};
+use crate::dialog::{Dialog, Choice};
+use crate::follow::FollowComponent;
+use crate::projectile::ProjectileComponent;
+use crate::stats::StatsComponent;
+use crate::sound::SoundConfig;
+use crate::tilemap::TilemapComponent;
+
+
+/// Error produced when a game or world file cannot be parsed
+#[derive(Debug)]
+pub enum ParseError {
+    /// A file could not be read
+    Io { path: String, source: std::io::Error },
+    /// A file is not valid yaml
+    Yaml(ScanError),
+    /// A collision map image could not be opened or decoded
+    Image { path: String, source: image::ImageError },
+    /// A texture named by a field could not be loaded
+    Texture { field: String, path: String, reason: String },
+    /// A required field is missing or has the wrong type
+    Missing { field: String },
+    /// A field is present but its value cannot be used
+    Invalid { field: String, reason: String },
+    /// A world was loaded which the game file does not list
+    UnknownWorld(String),
+    /// The world references dialogs which are not defined
+    UnknownDialogs(Vec<String>)
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Io { path, source } => write!(f, "could not read {}: {}", path, source),
+            ParseError::Yaml(e) => write!(f, "invalid yaml: {}", e),
+            ParseError::Image { path, source } => write!(f, "could not load image {}: {}", path, source),
+            ParseError::Texture { field, path, reason } => write!(f, "{} could not load texture {}: {}", field, path, reason),
+            ParseError::Missing { field } => write!(f, "{} missing", field),
+            ParseError::Invalid { field, reason } => write!(f, "{} invalid: {}", field, reason),
+            ParseError::UnknownWorld(name) => write!(f, "unknown world '{}'", name),
+            ParseError::UnknownDialogs(errors) => write!(f, "world references missing dialogs:\n{}", errors.join("\n"))
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io { source, .. } => Some(source),
+            ParseError::Yaml(e) => Some(e),
+            ParseError::Image { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
 
+/// Turn a missing value into an error naming the field it came from
+fn require<T>(value: Option<T>, field: &str) -> Result<T, ParseError> {
+    value.ok_or_else(|| ParseError::Missing { field: field.into() })
+}
+
+/// Load the texture at a path, naming the field it came from if it cannot be loaded
+fn load_texture(texture_manager: &mut TextureManager, path: &str, field: &str) -> Result<usize, ParseError> {
+    texture_manager.load_texture(path).map_err(|reason| ParseError::Texture {
+        field: field.into(),
+        path: path.into(),
+        reason
+    })
+}
+
+/// Read a whole file into a string
+fn read_file(path: &str) -> Result<String, ParseError> {
+    let io_error = |source| ParseError::Io { path: path.into(), source };
+
+    let mut file = File::open(path).map_err(io_error)?;
+    let file_size = file.metadata().map_err(io_error)?.len();
+    let mut contents = String::with_capacity(file_size as usize);
+    file.read_to_string(&mut contents).map_err(io_error)?;
+
+    Ok(contents)
+}
+
+/// Load the first yaml document of a string
+fn load_document(contents: &str) -> Result<Yaml, ParseError> {
+    let docs = YamlLoader::load_from_str(contents).map_err(ParseError::Yaml)?;
+    require(docs.into_iter().next(), "document")
+}
+
+
+/// Parse an optional f32 field, which is an error if present but not a number
+fn parse_f32_field(yaml: &Yaml, field: &str) -> Result<Option<f32>, ParseError> {
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        _ => parse_f32(yaml).map(Some).ok_or_else(|| ParseError::Invalid {
+            field: field.into(),
+            reason: "expected a number".into()
+        })
+    }
+}
+
+/// Parse an optional u32 field, which is an error if present but not a whole number
+fn parse_u32_field(yaml: &Yaml, field: &str) -> Result<Option<u32>, ParseError> {
+    match yaml {
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        _ => yaml.as_i64().and_then(|i| u32::try_from(i).ok()).map(Some).ok_or_else(|| ParseError::Invalid {
+            field: field.into(),
+            reason: "expected a whole number".into()
+        })
+    }
+}
 
 /// Parse yaml into an f32
 /// Acceps either an integer or a floating point as input
@@ -197,6 +499,21 @@ fn parse_world_rect_with_defaults(yaml: &Yaml, default: (Option<f32>, Option<f32
     }
 }
 
+/// Parse yaml into a world rect, naming the field which is missing or invalid if it cannot be
+fn parse_world_rect_field(yaml: &Yaml, field: &str, default: (Option<f32>, Option<f32>, Option<u32>, Option<u32>)) -> Result<Rect, ParseError> {
+    if yaml.as_hash().is_none() {
+        return Err(ParseError::Missing { field: field.into() });
+    }
+
+    let key = |k: &str| format!("{}.{}", field, k);
+    let x = parse_f32_field(&yaml["x"], &key("x"))?.or(default.0);
+    let y = parse_f32_field(&yaml["y"], &key("y"))?.or(default.1);
+    let w = parse_u32_field(&yaml["w"], &key("w"))?.or(default.2);
+    let h = parse_u32_field(&yaml["h"], &key("h"))?.or(default.3);
+
+    Ok(Rect::new(require(x, &key("x"))?, require(y, &key("y"))?, require(w, &key("w"))?, require(h, &key("h"))?))
+}
+
 /// Parse yaml into world rect
 fn parse_world_rect(yaml: &Yaml) -> Option<Rect> {
     parse_world_rect_with_defaults(yaml, (None, None, None, None))
@@ -231,6 +548,16 @@ fn parse_sdl2_rect_or(yaml: &Yaml, default: (i32, i32, u32, u32)) -> sdl2::rect:
     parse_sdl2_rect_with_defaults(yaml, (Some(default.0), Some(default.1), Some(default.2), Some(default.3))).unwrap()
 }
 
+/// Parse yaml into a color, with each missing channel defaulting to 255
+fn parse_color(yaml: &Yaml) -> Color {
+    let red = parse_u32_or(&yaml["r"], 255);
+    let green = parse_u32_or(&yaml["g"], 255);
+    let blue = parse_u32_or(&yaml["b"], 255);
+    let alpha = parse_u32_or(&yaml["a"], 255);
+
+    Color::RGBA(red as u8, green as u8, blue as u8, alpha as u8)
+}
+
 /// Parse yaml into a sequence
 fn parse_sequence(yaml: &Yaml) -> Option<Sequence> {
     let a_iter = yaml.as_vec();
@@ -249,9 +576,14 @@ fn parse_sequence(yaml: &Yaml) -> Option<Sequence> {
                     Some((delay, actions.unwrap()))
                 }
             })
-            .collect();
+            .collect::<Vec<_>>();
 
-        Some(Sequence::new(actions))
+        // A sequence without any actions would never do anything
+        if actions.is_empty() {
+            None
+        } else {
+            Some(Sequence::new(actions))
+        }
     }
 }
 
@@ -273,12 +605,34 @@ fn parse_event(yaml: &Yaml) -> Option<(Vec<String>, Sequence)> {
     }
 }
 
+/// Parse yaml into a world event
+fn parse_world_event(yaml: &Yaml) -> Option<WorldEvent> {
+    let strings = |key: &str| -> Vec<String> {
+        yaml[key].as_vec().unwrap_or(&Vec::new()).iter()
+            .filter_map(parse_string)
+            .collect()
+    };
+
+    parse_sequence(&yaml["actions"])
+        .map(|sequence| WorldEvent::new(strings("states"), strings("any"), strings("none"), sequence))
+}
+
 /// Parse yaml into an action
 fn parse_action(yaml: &Yaml) -> Option<Box<dyn Action>> {
     match yaml["type"].as_str() {
         Some("dialog") => {
+            let variants = yaml["variants"].as_vec().unwrap_or(&Vec::new()).iter()
+                .filter_map(|v| {
+                    let states: Vec<String> = v["states"].as_vec().unwrap_or(&Vec::new()).iter()
+                        .filter_map(parse_string)
+                        .collect();
+
+                    parse_string(&v["dialog"]).map(|d| (states, d))
+                })
+                .collect();
+
             parse_string(&yaml["dialog"])
-                .map(|s| Box::new(ShowDialog { dialog: s }) as Box<dyn Action>)
+                .map(|s| Box::new(ShowDialog { dialog: s, variants }) as Box<dyn Action>)
         }
         Some("add_state") => {
             parse_string(&yaml["state"])
@@ -295,151 +649,476 @@ fn parse_action(yaml: &Yaml) -> Option<Box<dyn Action>> {
         Some("exit_game") => {
             Some(Box::new(ExitGame {}) as Box<dyn Action>)
         }
-        Some("goto") => {
-            parse_string(&yaml["location"])
-                .map(|s| Box::new(Goto { location: s }) as Box<dyn Action>)
+        Some("change_world") | Some("goto") => {
+            parse_destination(yaml)
+                .map(|(world, entrance)| Box::new(ChangeWorld { world, entrance }) as Box<dyn Action>)
+        }
+        Some("play_animation") => {
+            parse_string(&yaml["state"])
+                .map(|s| Box::new(PlayAnimation { state: s }) as Box<dyn Action>)
+        }
+        Some("stop_animation") => {
+            Some(Box::new(StopAnimation {}) as Box<dyn Action>)
+        }
+        Some("composite") => {
+            let actions = yaml["actions"].as_vec().unwrap_or(&Vec::new()).iter()
+                .filter_map(|y| parse_action(y))
+                .collect();
+            Some(Box::new(Composite { actions }) as Box<dyn Action>)
+        }
+        Some("flash") => {
+            let r = parse_u32_or(&yaml["r"], 255) as u8;
+            let g = parse_u32_or(&yaml["g"], 255) as u8;
+            let b = parse_u32_or(&yaml["b"], 255) as u8;
+            let duration = parse_f32_or(&yaml["duration"], 0.2);
+            Some(Box::new(Flash { r, g, b, duration }) as Box<dyn Action>)
+        }
+        Some("set_overlay") => {
+            let r = parse_u32_or(&yaml["r"], 0) as u8;
+            let g = parse_u32_or(&yaml["g"], 0) as u8;
+            let b = parse_u32_or(&yaml["b"], 0) as u8;
+            let a = parse_u32_or(&yaml["a"], 0) as u8;
+            Some(Box::new(SetOverlay { r, g, b, a }) as Box<dyn Action>)
+        }
+        Some("shake") => {
+            let magnitude = parse_f32_or(&yaml["magnitude"], 4.0);
+            let duration = parse_f32_or(&yaml["duration"], 0.3);
+            Some(Box::new(Shake { magnitude, duration }) as Box<dyn Action>)
+        }
+        Some("despawn") => {
+            Some(Box::new(Despawn {}) as Box<dyn Action>)
+        }
+        Some("spawn") => {
+            yaml["entity"].as_hash()
+                .map(|_| Box::new(SpawnEntity { template: yaml["entity"].clone() }) as Box<dyn Action>)
+        }
+        Some("play_sound") => {
+            parse_string(&yaml["sound"])
+                .map(|s| Box::new(PlaySound { path: s }) as Box<dyn Action>)
+        }
+        Some("rumble") => {
+            let low = parse_u32_or(&yaml["low"], 0xFFFF) as u16;
+            let high = parse_u32_or(&yaml["high"], 0xFFFF) as u16;
+            let duration_ms = parse_u32_or(&yaml["duration"], 200);
+            Some(Box::new(Rumble { low, high, duration_ms }) as Box<dyn Action>)
         }
         _ => None
     }
 }
 
-/// Parse yaml into a dialog
-fn parse_dialog(yaml: &Yaml) -> Option<(String, Dialog)> {
+/// Parse yaml into a dialog, none if it has no name
+fn parse_dialog(yaml: &Yaml, field: &str, texture_manager: &mut TextureManager) -> Result<Option<(String, Dialog)>, ParseError> {
     let name = parse_string(&yaml["name"]);
-    let messages: Vec<String> = yaml["messages"].as_vec().unwrap_or(&Vec::new())
-        .iter()
-        .map(|e| parse_string(e).unwrap())
-        .collect();
+    let message_yaml = yaml["messages"].as_vec().unwrap_or(&Vec::new()).clone();
+
+    // Each message is either plain text or text with choices
+    let messages = message_yaml.iter()
+        .enumerate()
+        .map(|(i, e)| require(parse_string(e).or_else(|| parse_string(&e["text"])), &format!("{}.messages[{}]", field, i)))
+        .collect::<Result<Vec<String>, ParseError>>()?;
+
+    let mut speakers: Vec<(Option<String>, Option<usize>)> = Vec::new();
+    for (i, message) in message_yaml.iter().enumerate() {
+        let portrait = match parse_string(&message["portrait"]) {
+            Some(path) => Some(load_texture(texture_manager, &path, &format!("{}.messages[{}].portrait", field, i))?),
+            None => None
+        };
+
+        speakers.push((parse_string(&message["speaker"]), portrait));
+    }
+
+    let mut choices = Vec::new();
+    for (i, message) in message_yaml.iter().enumerate() {
+        let mut message_choices = Vec::new();
+
+        for (j, choice) in message["choices"].as_vec().unwrap_or(&Vec::new()).iter().enumerate() {
+            let choice_field = format!("{}.messages[{}].choices[{}]", field, i, j);
+            let goto = parse_u32(&choice["goto"]).map(|g| g as usize);
+
+            if goto.is_some_and(|g| g >= messages.len()) {
+                return Err(ParseError::Invalid {
+                    field: format!("{}.goto", choice_field),
+                    reason: format!("there are only {} messages", messages.len())
+                });
+            }
+
+            message_choices.push(Choice {
+                text: require(parse_string(&choice["text"]), &format!("{}.text", choice_field))?,
+                goto,
+                actions: parse_sequence(&choice["actions"])
+            });
+        }
+
+        choices.push(message_choices);
+    }
 
     let after = parse_sequence(&yaml["after"]);
+    let freeze_world = parse_bool_or(&yaml["freeze_world"], false);
 
-    if name.is_none() {
-        None
-    } else {
-        Some((name.unwrap(), Dialog::new(messages, after)))
+    if let (Some(name), true) = (name.as_ref(), messages.is_empty()) {
+        return Err(ParseError::Invalid {
+            field: format!("{}.messages", field),
+            reason: format!("dialog '{}' has no messages", name)
+        });
     }
+
+    Ok(name.map(|name| {
+        let mut dialog = Dialog::new(messages, after, freeze_world);
+        for (i, message_choices) in choices.into_iter().enumerate() {
+            dialog.set_choices(i, message_choices);
+        }
+        for (i, (speaker, portrait)) in speakers.into_iter().enumerate() {
+            dialog.set_speaker(i, speaker, portrait);
+        }
+        (name, dialog)
+    }))
 }
 
 /// Parse yaml into animation
-fn parse_animation(yaml: &Yaml, texture_manager: &mut TextureManager) -> Option<(String, Animation)> {
-    let state = parse_string(&yaml["state"]);
-    let period = parse_f32(&yaml["period"]);
+fn parse_animation(yaml: &Yaml, field: &str, texture_manager: &mut TextureManager) -> Result<(String, Animation), ParseError> {
+    let key = |k: &str| format!("{}.{}", field, k);
+    let state = require(parse_string(&yaml["state"]), &key("state"))?;
+    let period = require(parse_f32_field(&yaml["period"], &key("period"))?, &key("period"))?;
     let after = parse_sequence(&yaml["after"]);
 
-    let texture = parse_texture(&yaml, texture_manager);
+    let texture = require(parse_texture(yaml, field, texture_manager)?, &key("path"))?;
     let frame_width = parse_u32_or(&yaml["frame_width"], 0);
-    let frame_count = parse_u32_or(&yaml["frame_count"], 1);
+    let frame_height = parse_u32_or(&yaml["frame_height"], 0);
+    let frame_count = parse_u32_field(&yaml["frame_count"], &key("frame_count"))?.unwrap_or(1);
+    let columns = parse_u32(&yaml["columns"]).filter(|c| *c > 0).unwrap_or(frame_count.max(1));
+
+    if frame_count == 0 {
+        return Err(ParseError::Invalid {
+            field: key("frame_count"),
+            reason: "an animation needs at least one frame".into()
+        });
+    }
 
     let textures: Vec<(usize, Option<sdl2::rect::Rect>)> = (0..frame_count)
-        .filter_map(|frame_num| {
-            if let Some(tex) = texture {
-                Some((tex.0, tex.1.map(|mut b| {
-                    b.x += frame_num as i32 * frame_width as i32;
-                    b
-                })))
-            } else {
-                None
-            }
-        })
+        .map(|frame_num| (texture.0, texture.1.map(|b| frame_srcbox(b, frame_num, columns, frame_width, frame_height))))
         .collect();
 
-    if state.is_none() || period.is_none() || textures.len() == 0 {
-        None
-    } else {
-        Some((state.unwrap(), Animation::new(textures, period.unwrap(), after)))
+    let mut animation = Animation::new(textures, period, after);
+    animation.looping = parse_bool_or(&yaml["loop"], true);
+
+    match yaml["mode"].as_str() {
+        Some("once") => animation.looping = false,
+        Some("pingpong") => animation.ping_pong = true,
+        Some("loop") | None => {}
+        Some(mode) => warn!("Unknown animation mode '{}', using loop", mode)
     }
+
+    animation.after_each_loop = parse_bool_or(&yaml["after_each_loop"], false);
+    Ok((state, animation))
+}
+
+/// Source rectangle of a frame in a sprite sheet whose first frame is at first,
+/// with frames filling rows of the given number of columns
+fn frame_srcbox(first: sdl2::rect::Rect, frame: u32, columns: u32, frame_width: u32, frame_height: u32) -> sdl2::rect::Rect {
+    let mut srcbox = first;
+    srcbox.x += (frame % columns) as i32 * frame_width as i32;
+    srcbox.y += (frame / columns) as i32 * frame_height as i32;
+    srcbox
 }
 
 
-/// Parse yaml into texture
-fn parse_texture(yaml: &Yaml, texture_manager: &mut TextureManager) -> Option<(usize, Option<sdl2::rect::Rect>)> {
-    let path = parse_string(&yaml["path"]);
+/// Parse yaml into texture, none if it has no path
+fn parse_texture(yaml: &Yaml, field: &str, texture_manager: &mut TextureManager) -> Result<Option<(usize, Option<sdl2::rect::Rect>)>, ParseError> {
+    let path = match parse_string(&yaml["path"]) {
+        Some(path) => path,
+        None => return Ok(None)
+    };
     let srcbox = parse_sdl2_rect(&yaml["srcbox"]);
 
-    if path.is_none() {
-        None
-    } else {
-        let tex_id = texture_manager.load_texture(&path.unwrap());
-        Some((tex_id, srcbox))
-    }
+    let tex_id = load_texture(texture_manager, &path, &format!("{}.path", field))?;
+    Ok(Some((tex_id, srcbox)))
 }
 
-/// Parse yaml into an entity
-fn parse_entity(yaml: &Yaml, texture_manager: &mut TextureManager) -> (
+/// Parse yaml into an entity, naming the field of the first component which cannot be parsed
+fn parse_entity(yaml: &Yaml, field: &str, texture_manager: &mut TextureManager) -> Result<(
     Option<PositionComponent>,
     Option<PhysicsComponent>,
     Option<GraphicsComponent>,
     Option<AnimationComponent>,
     Option<ActionComponent>,
-    Option<String>
-) {
-    let position = parse_position_component(&yaml["position"]);
-    let physics = parse_physics_component(&yaml["physics"]);
-    let graphics = parse_graphics_component(&yaml["graphics"], texture_manager);
-    let animation = parse_animations_component(&yaml["animations"], texture_manager);
+    Vec<String>
+), ParseError> {
+    let key = |k: &str| format!("{}.{}", field, k);
+    let position = parse_position_component(&yaml["position"], &key("position"))?;
+    let physics = parse_physics_component(&yaml["physics"], &key("physics"))?;
+    let graphics = parse_graphics_component(&yaml["graphics"], &key("graphics"), texture_manager)?;
+    let animation = parse_animations_component(&yaml["animations"], &key("animations"), texture_manager)?;
     let actions = parse_actions_component(&yaml["events"]);
 
-    let default_state = parse_string(&yaml["state"]);
+    // Either a single starting state or a list of them
+    let default_state = match yaml["state"].as_vec() {
+        Some(states) => states.iter().filter_map(parse_string).collect(),
+        None => parse_string(&yaml["state"]).into_iter().collect()
+    };
 
-    (position, physics, graphics, animation, actions, default_state)
+    Ok((position, physics, graphics, animation, actions, default_state))
 }
 
-/// Parse yaml into a position component
-fn parse_position_component(yaml: &Yaml) -> Option<PositionComponent> {
-    let x = parse_f32(&yaml["x"]);
-    let y = parse_f32(&yaml["y"]);
+/// Parse yaml into a follow component, none if there is no follow block
+fn parse_follow_component(yaml: &Yaml, field: &str) -> Result<Option<FollowComponent>, ParseError> {
+    if yaml.as_hash().is_none() {
+        return Ok(None);
+    }
 
-    if x.is_none() || y.is_none() {
-        None
-    } else {
-        Some(PositionComponent::new(x.unwrap(), y.unwrap()))
+    let target = require(parse_string(&yaml["target"]), &format!("{}.target", field))?;
+    let distance = parse_f32_or(&yaml["distance"], 20.0);
+    let speed = parse_f32_or(&yaml["speed"], 50.0);
+
+    let update_interval = parse_u32_or(&yaml["update_interval"], 1).max(1);
+    let throttle_distance = parse_f32_or(&yaml["throttle_distance"], 0.0);
+
+    let mut follow = FollowComponent::new(target, distance, speed);
+    follow.update_interval = update_interval;
+    follow.throttle_distance = throttle_distance;
+    Ok(Some(follow))
+}
+
+/// Parse yaml into a projectile component, none if there is no projectile block
+fn parse_projectile_component(yaml: &Yaml, field: &str) -> Result<Option<ProjectileComponent>, ParseError> {
+    if yaml.as_hash().is_none() {
+        return Ok(None);
     }
+
+    let ttl = parse_f32_field(&yaml["ttl"], &format!("{}.ttl", field))?;
+    let on_hit = parse_sequence(&yaml["on_hit"]);
+
+    Ok(Some(ProjectileComponent::new(require(ttl, &format!("{}.ttl", field))?, on_hit)))
 }
 
-/// Parse yaml into a physics component
-fn parse_physics_component(yaml: &Yaml) -> Option<PhysicsComponent> {
-    let hitbox = parse_world_rect_with_defaults(&yaml["hitbox"], (Some(0.0), Some(0.0), None, None));
-    let physical = parse_bool_or(&yaml["physical"], true);
-    let depth = parse_u32(&yaml["depth"]).map(|d| Some(d)).unwrap_or(hitbox.map(|h| h.h as u32));
+/// Parse a yaml hash of stat names to values
+fn parse_stats(yaml: &Yaml) -> HashMap<String, f32> {
+    yaml.as_hash()
+        .map(|h| h.iter()
+            .filter_map(|(k, v)| Some((parse_string(k)?, parse_f32(v)?)))
+            .collect())
+        .unwrap_or_default()
+}
 
-    if hitbox.is_none() {
-        None
-    } else {
-        Some(PhysicsComponent::new(hitbox.unwrap(), depth.unwrap(), physical))
+/// Warn about any key of a yaml hash which is not known, ignoring the `meta` key used for annotations
+fn warn_unknown_keys(yaml: &Yaml, block: &str, known: &[&str]) {
+    for key in yaml.as_hash().map(|h| h.keys().collect::<Vec<_>>()).unwrap_or_default() {
+        match key.as_str() {
+            Some("meta") => {}
+            Some(k) if known.contains(&k) => {}
+            Some(k) => warn!("Unknown key '{}' in {}", k, block),
+            None => warn!("Unknown key {:?} in {}", key, block)
+        }
     }
 }
 
-/// Parse yaml into graphics component
-fn parse_graphics_component(yaml: &Yaml, texture_manager: &mut TextureManager) -> Option<GraphicsComponent> {
-    let path = parse_string(&yaml["path"]);
-    let renderbox = parse_world_rect_with_defaults(&yaml["renderbox"], (Some(0.0), Some(0.0), None, None));
-    let srcbox = parse_sdl2_rect(&yaml["srcbox"]);
+/// Warn about unknown keys in an entity and its components
+fn warn_unknown_entity_keys(yaml: &Yaml) {
+    warn_unknown_keys(yaml, "entity", &[
+        "state", "name", "player", "position", "physics", "graphics", "animations",
+        "events", "follow", "projectile", "stats", "static", "ai", "tilemap", "minimap"
+    ]);
+    warn_unknown_keys(&yaml["position"], "position", &["x", "y"]);
+    warn_unknown_keys(&yaml["physics"], "physics", &[
+        "hitbox", "depth", "physical", "trigger", "speed", "max_speed", "continuous", "stuck_delay",
+        "elevation", "height", "collision_layer", "collision_mask", "occludes_sight", "see_through_states",
+        "affected_by_gravity", "layer", "mask"
+    ]);
+    warn_unknown_keys(&yaml["graphics"], "graphics", &["path", "renderbox", "srcbox", "alpha", "tint", "z"]);
+    warn_unknown_keys(&yaml["follow"], "follow", &["target", "distance", "speed", "update_interval", "throttle_distance"]);
+    warn_unknown_keys(&yaml["projectile"], "projectile", &["ttl", "on_hit"]);
+    warn_unknown_keys(&yaml["tilemap"], "tilemap", &["path", "tile_width", "tile_height", "rows"]);
+    warn_unknown_keys(&yaml["ai"], "ai", &[
+        "path", "aggro_distance", "lost_delay", "chase_speed", "chase_wobble", "idle_speed", "teleport_delay", "world"
+    ]);
+}
 
-    if path.is_none() || renderbox.is_none() {
-        None
-    } else {
-        let tex_id = texture_manager.load_texture(&path.unwrap());
-        Some(GraphicsComponent::new(tex_id, renderbox.unwrap(), srcbox))
+/// Parse the parts of an entity which are set after it has been added to the world
+fn parse_entity_extras(yaml: &Yaml, field: &str, world: &mut World, id: usize) -> Result<(), ParseError> {
+    if world.strict {
+        warn_unknown_entity_keys(yaml);
+    }
+
+    if let Some(name) = parse_string(&yaml["name"]) {
+        world.set_entity_name(id, name);
+    }
+
+    let key = |k: &str| format!("{}.{}", field, k);
+    world.follows[id] = parse_follow_component(&yaml["follow"], &key("follow"))?;
+    world.projectiles[id] = parse_projectile_component(&yaml["projectile"], &key("projectile"))?;
+    world.statics[id] = parse_bool_or(&yaml["static"], false);
+    world.on_minimap[id] = parse_bool_or(&yaml["minimap"], false);
+    world.tilemaps[id] = parse_tilemap_component(&yaml["tilemap"], &key("tilemap"), &mut world.texture_manager)?;
+
+    if yaml["stats"].as_hash().is_some() {
+        world.stats[id] = Some(StatsComponent::new(parse_stats(&yaml["stats"])));
     }
+
+    // The ai state is what puts an entity under the AI system's control, so give it along with the component
+    world.ais[id] = match yaml["ai"].as_hash() {
+        Some(_) => Some(AIComponent::new(parse_ai_config(&yaml["ai"], &key("ai"))?)),
+        None => None
+    };
+    if world.ais[id].is_some() {
+        let state = world.state_names.ai.clone();
+        world.add_entity_state(id, state);
+    }
+
+    Ok(())
 }
 
-/// Parse yaml into animations component
-fn parse_animations_component(yaml: &Yaml, texture_manager: &mut TextureManager) -> Option<AnimationComponent> {
-    let mut animations = HashMap::new();
+/// Parse yaml into an ai config, missing keys keep their defaults
+fn parse_ai_config(yaml: &Yaml, field: &str) -> Result<AIConfig, ParseError> {
+    let default = AIConfig::default();
+    let mut idle_path = Vec::new();
 
-    let a_iter = yaml.as_vec();
+    for (i, item) in yaml["path"].as_vec().unwrap_or(&Vec::new()).iter().enumerate() {
+        let coord = |key: &str| require(parse_f32(&item[key]), &format!("{}.path[{}].{}", field, i, key));
+        idle_path.push((coord("x")?, coord("y")?, coord("t")?));
+    }
 
-    if a_iter.is_none() {
-        None
-    } else {
-        a_iter.unwrap().iter()
-            .for_each(|y| {
-                let (state, animation) = parse_animation(&y, texture_manager).unwrap();
-                animations.insert(state, animation);
-            });
+    Ok(AIConfig {
+        idle_path,
+        aggro_distance: parse_f32_or(&yaml["aggro_distance"], default.aggro_distance),
+        lost_delay: parse_f32_or(&yaml["lost_delay"], default.lost_delay),
+        chase_speed: parse_f32_or(&yaml["chase_speed"], default.chase_speed),
+        chase_wobble: parse_f32_or(&yaml["chase_wobble"], default.chase_wobble),
+        idle_speed: parse_f32_or(&yaml["idle_speed"], default.idle_speed),
+        teleport_delay: parse_f32_or(&yaml["teleport_delay"], default.teleport_delay),
+        home_world: parse_string(&yaml["world"])
+    })
+}
+
+/// Parse yaml into a tilemap component, negative indices are empty tiles.
+/// None if there is no tilemap block
+fn parse_tilemap_component(yaml: &Yaml, field: &str, texture_manager: &mut TextureManager) -> Result<Option<TilemapComponent>, ParseError> {
+    if yaml.as_hash().is_none() {
+        return Ok(None);
+    }
+
+    let key = |k: &str| format!("{}.{}", field, k);
+    let path = require(parse_string(&yaml["path"]), &key("path"))?;
+    let tile_width = require(parse_u32_field(&yaml["tile_width"], &key("tile_width"))?, &key("tile_width"))?;
+    let tile_height = require(parse_u32_field(&yaml["tile_height"], &key("tile_height"))?, &key("tile_height"))?;
+
+    let tiles = yaml["rows"].as_vec().unwrap_or(&Vec::new())
+        .iter()
+        .map(|row| row.as_vec().unwrap_or(&Vec::new())
+            .iter()
+            .map(|tile| tile.as_i64().filter(|t| *t >= 0).map(|t| t as u32))
+            .collect())
+        .collect();
+
+    let texture_id = load_texture(texture_manager, &path, &key("path"))?;
+    Ok(Some(TilemapComponent::new(texture_id, tile_width, tile_height, tiles)))
+}
+
+/// Parse yaml into a position component, none if there is no position block
+fn parse_position_component(yaml: &Yaml, field: &str) -> Result<Option<PositionComponent>, ParseError> {
+    if yaml.as_hash().is_none() {
+        return Ok(None);
+    }
+
+    let key = |k: &str| format!("{}.{}", field, k);
+    let x = require(parse_f32_field(&yaml["x"], &key("x"))?, &key("x"))?;
+    let y = require(parse_f32_field(&yaml["y"], &key("y"))?, &key("y"))?;
+
+    Ok(Some(PositionComponent::new(x, y)))
+}
+
+/// Parse yaml into a physics component, none if there is no physics block
+fn parse_physics_component(yaml: &Yaml, field: &str) -> Result<Option<PhysicsComponent>, ParseError> {
+    if yaml.as_hash().is_none() {
+        return Ok(None);
+    }
+
+    let key = |k: &str| format!("{}.{}", field, k);
+    let hitbox = parse_world_rect_field(&yaml["hitbox"], &key("hitbox"), (Some(0.0), Some(0.0), None, None))?;
+    let physical = parse_bool_or(&yaml["physical"], true);
+    let trigger = parse_bool_or(&yaml["trigger"], false);
+    let occludes_sight = parse_bool_or(&yaml["occludes_sight"], physical);
+    let see_through_states = yaml["see_through_states"].as_vec().unwrap_or(&Vec::new()).iter()
+        .filter_map(parse_string)
+        .collect();
+    let depth = parse_u32_field(&yaml["depth"], &key("depth"))?.unwrap_or(hitbox.h);
+    let speed = parse_f32_field(&yaml["speed"], &key("speed"))?.unwrap_or(55.0);
+    let max_speed = parse_f32_field(&yaml["max_speed"], &key("max_speed"))?;
+    let continuous = parse_bool_or(&yaml["continuous"], false);
+    let stuck_delay = parse_f32_field(&yaml["stuck_delay"], &key("stuck_delay"))?;
+    let affected_by_gravity = parse_bool_or(&yaml["affected_by_gravity"], false);
+    let elevation = parse_f32_field(&yaml["elevation"], &key("elevation"))?.unwrap_or(0.0);
+    let height = parse_f32_field(&yaml["height"], &key("height"))?;
+    // A layer index is shorthand for a bitfield with only that layer
+    let collision_layer = match parse_u32_field(&yaml["layer"], &key("layer"))? {
+        Some(layer) => 1u32.checked_shl(layer).ok_or_else(|| ParseError::Invalid {
+            field: key("layer"),
+            reason: format!("layer {} is out of range, layers go from 0 to 31", layer)
+        })?,
+        None => parse_u32_or(&yaml["collision_layer"], 1)
+    };
+    let collision_mask = parse_u32(&yaml["mask"]).unwrap_or(parse_u32_or(&yaml["collision_mask"], u32::MAX));
+
+    let mut physics = PhysicsComponent::new(hitbox, depth, physical);
+    physics.speed = speed;
+    physics.max_speed = max_speed;
+    physics.continuous = continuous;
+    physics.stuck_delay = stuck_delay;
+    physics.affected_by_gravity = affected_by_gravity;
+    physics.elevation = elevation;
+    physics.height = height.unwrap_or(physics.height);
+    physics.collision_layer = collision_layer;
+    physics.collision_mask = collision_mask;
+    physics.trigger = trigger;
+    physics.occludes_sight = occludes_sight;
+    physics.see_through_states = see_through_states;
+    Ok(Some(physics))
+}
 
-        Some(AnimationComponent::new(animations))
+/// Parse yaml into graphics component, none if there is no graphics block
+fn parse_graphics_component(yaml: &Yaml, field: &str, texture_manager: &mut TextureManager) -> Result<Option<GraphicsComponent>, ParseError> {
+    if yaml.as_hash().is_none() {
+        return Ok(None);
     }
+
+    let key = |k: &str| format!("{}.{}", field, k);
+    let path = require(parse_string(&yaml["path"]), &key("path"))?;
+    let renderbox = parse_world_rect_field(&yaml["renderbox"], &key("renderbox"), (Some(0.0), Some(0.0), None, None))?;
+    let srcbox = parse_sdl2_rect(&yaml["srcbox"]);
+
+    let tex_id = load_texture(texture_manager, &path, &key("path"))?;
+    let mut graphics = GraphicsComponent::new(tex_id, renderbox, srcbox);
+    graphics.alpha = parse_u32_or(&yaml["alpha"], 255).min(255) as u8;
+    graphics.tint = yaml["tint"].as_hash().map(|_| parse_color(&yaml["tint"]));
+    graphics.z = yaml["z"].as_i64().unwrap_or(0) as i32;
+    Ok(Some(graphics))
+}
+
+/// Parse yaml into animations component, none if there is no list of animations.
+/// Malformed animations are skipped with a warning
+fn parse_animations_component(yaml: &Yaml, field: &str, texture_manager: &mut TextureManager) -> Result<Option<AnimationComponent>, ParseError> {
+    let a_iter = match yaml.as_vec() {
+        Some(a_iter) => a_iter,
+        None => return Ok(None)
+    };
+
+    let mut animations = HashMap::new();
+
+    for (i, y) in a_iter.iter().enumerate() {
+        // A malformed animation is left out rather than failing the whole entity
+        let key = format!("{}[{}]", field, i);
+        let (state, animation) = match parse_animation(y, &key, texture_manager) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                warn!("Skipping malformed animation {}: {}", key, e);
+                continue;
+            }
+        };
+
+        if animations.insert(state.clone(), animation).is_some() {
+            warn!("Duplicate animation for state '{}', using the last one", state);
+        }
+    }
+
+    Ok(Some(AnimationComponent::new(animations)))
 }
 
 /// Parse yaml into actions component
@@ -472,20 +1151,47 @@ fn parse_effect(yaml: &Yaml) -> EffectSpawner {
 
     let ttl = parse_f32(&yaml["ttl"]);
     let rect = parse_world_rect_or(&yaml["rect"], (-2.0, -2.0, 4, 4));
+    let on_expire = parse_sequence(&yaml["on_expire"]);
+
+    let overlap_mode = parse_string(&yaml["overlap_mode"])
+        .and_then(|m| OverlapMode::from_name(&m))
+        .unwrap_or(OverlapMode::Intersect);
+
+    let mut spawner = EffectSpawner::new(added, removed, rect, ttl, on_expire);
+    spawner.circle = parse_f32(&yaml["circle"]["r"])
+        .map(|r| Circle::new(parse_f32_or(&yaml["circle"]["x"], 0.0), parse_f32_or(&yaml["circle"]["y"], 0.0), r));
+    spawner.overlap_mode = overlap_mode;
+    spawner.stat_deltas = parse_stats(&yaml["stat_delta"]);
+    spawner
+}
 
-    EffectSpawner::new(added, removed, rect, ttl)
+/// Parse the world and entrance moved to by an exit or action, from `world` and `entrance` keys,
+/// or from the older `world/entrance` form of `to` or `location`. None if there is no world
+fn parse_destination(yaml: &Yaml) -> Option<(String, String)> {
+    if let Some(world) = parse_string(&yaml["world"]) {
+        return Some((world, parse_string_or(&yaml["entrance"], "")));
+    }
+
+    parse_string(&yaml["to"])
+        .or_else(|| parse_string(&yaml["location"]))
+        .map(|s| match s.split_once('/') {
+            Some((world, entrance)) => (world.to_string(), entrance.to_string()),
+            None => (s, String::new())
+        })
 }
 
 /// Parse yaml into exit
-fn parse_exit(yaml: &Yaml) -> Effect {
-    let location = yaml["to"].as_str().unwrap();
+fn parse_exit(yaml: &Yaml, field: &str) -> Result<Effect, ParseError> {
+    let destination = require(parse_destination(yaml), &format!("{}.world", field))?;
+    let rect = parse_world_rect(yaml).ok_or_else(|| ParseError::Invalid {
+        field: field.into(),
+        reason: "needs x, y, w, and h".into()
+    })?;
 
-    Effect::new(
-        vec![format!("__MOVE_TO__={}", location)],
-        vec![],
-        parse_world_rect(yaml).unwrap(),
-        None
-    )
+    let mut exit = Effect::new(vec![], vec![], rect, None, None);
+    exit.change_world = Some(destination);
+
+    Ok(exit)
 }
 
 /// Parse yaml into input
@@ -521,54 +1227,134 @@ fn parse_input_config(yaml: &Yaml) -> InputConfig {
     config
 }
 
+/// Parse yaml into camera
+fn parse_camera(yaml: &Yaml, field: &str) -> Result<Camera, ParseError> {
+    let cam_rect = parse_world_rect_with_defaults(&yaml["rect"], (Some(0.0), Some(0.0), Some(800), Some(600))).unwrap();
+    let cam_zoom = parse_u32_or(&yaml["zoom"], 5);
+    let viewport = parse_sdl2_rect(&yaml["viewport"]);
+    let target = parse_u32_or(&yaml["target"], 0) as usize;
+
+    let cam_player_box = {
+        let w = require(parse_u32(&yaml["player_box"]["w"]), &format!("{}.player_box.w", field))?;
+        let h = require(parse_u32(&yaml["player_box"]["h"]), &format!("{}.player_box.h", field))?;
+        let x = parse_f32(&yaml["player_box"]["x"]).unwrap_or((cam_rect.w-w) as f32/2.0);
+        let y = parse_f32(&yaml["player_box"]["y"]).unwrap_or((cam_rect.h-h) as f32/2.0);
+
+        Rect::new(x, y, w, h)
+    };
+
+    let projection = match yaml["projection"].as_str() {
+        Some("iso") => Projection::Iso,
+        _ => Projection::TopDown
+    };
+
+    Ok(Camera {
+        rect: cam_rect,
+        player_box: cam_player_box,
+        zoom: cam_zoom,
+        viewport,
+        target,
+        projection
+    })
+}
+
 /// Parse yaml into graphics config
-fn parse_graphics_config(yaml: &Yaml, texture_manager: &mut TextureManager) -> GraphicsConfig {
+fn parse_graphics_config(yaml: &Yaml, texture_manager: &mut TextureManager) -> Result<GraphicsConfig, ParseError> {
     let debug = parse_bool_or(&yaml["debug"], false);
+    let grid_spacing = parse_u32_or(&yaml["grid_spacing"], 16);
+    let overlay = yaml["overlay"].as_hash().map(|_| parse_color(&yaml["overlay"]));
+    let fade_duration = parse_f32_or(&yaml["fade_duration"], 0.3);
 
     let dialog_tex_path = parse_string(&yaml["dialog"]["path"]);
-    let dialog_tex_id = dialog_tex_path.map(|path| texture_manager.load_texture(&path));
-    let dialog_font_path = parse_string(&yaml["dialog"]["font"]);
+    let dialog_tex_id = match dialog_tex_path {
+        Some(path) => Some(load_texture(texture_manager, &path, "graphics.dialog.path")?),
+        None => None
+    };
+    let dialog_font_path = parse_string(&yaml["dialog"]["font"]).map(|path| texture_manager.resolve(&path));
     let dialog_font_size = parse_u32(&yaml["dialog"]["fontsize"]).map(|u| u as u16);
     let dialog_renderbox = parse_sdl2_rect(&yaml["dialog"]["renderbox"]);
     let dialog_textbox = parse_sdl2_rect(&yaml["dialog"]["textbox"]);
-
-    let cam_rect = parse_world_rect_with_defaults(&yaml["camera"]["rect"], (Some(0.0), Some(0.0), Some(800), Some(600))).unwrap();
-    let cam_zoom = parse_u32_or(&yaml["camera"]["zoom"], 5);
-
-    let cam_player_box = {
-        let w = parse_u32(&yaml["camera"]["player_box"]["w"]).unwrap();
-        let h = parse_u32(&yaml["camera"]["player_box"]["h"]).unwrap();
-        let x = parse_f32(&yaml["camera"]["player_box"]["x"]).unwrap_or((cam_rect.w-w) as f32/2.0);
-        let y = parse_f32(&yaml["camera"]["player_box"]["y"]).unwrap_or((cam_rect.h-h) as f32/2.0);
-
-        Rect::new(x, y, w, h)
+    let dialog_portrait_box = parse_sdl2_rect(&yaml["dialog"]["portrait_box"]);
+    let dialog_text_speed = parse_f32(&yaml["dialog"]["text_speed"]).filter(|s| *s > 0.0);
+    let dialog_insets = yaml["dialog"]["insets"].as_hash().map(|_| {
+        let insets = &yaml["dialog"]["insets"];
+        Insets {
+            left: parse_u32_or(&insets["left"], 0),
+            top: parse_u32_or(&insets["top"], 0),
+            right: parse_u32_or(&insets["right"], 0),
+            bottom: parse_u32_or(&insets["bottom"], 0)
+        }
+    });
+
+    // Either a list of cameras for split screen or a single camera
+    let cameras = match yaml["cameras"].as_vec() {
+        Some(cameras) => cameras.iter()
+            .enumerate()
+            .map(|(i, y)| parse_camera(y, &format!("graphics.cameras[{}]", i)))
+            .collect::<Result<Vec<Camera>, ParseError>>()?,
+        None => vec![parse_camera(&yaml["camera"], "graphics.camera")?]
     };
 
+    let minimap = parse_sdl2_rect(&yaml["minimap"]["rect"])
+        .map(|rect| Minimap { rect, scale: parse_f32_or(&yaml["minimap"]["scale"], 0.1) });
 
-    GraphicsConfig {
+    Ok(GraphicsConfig {
         debug,
+        grid_spacing,
+        overlay,
+        fade_duration,
+        minimap,
         dialog_tex_id,
         dialog_font_path,
         dialog_font_size,
         dialog_renderbox,
         dialog_textbox,
-        camera: Camera {
-            rect: cam_rect,
-            player_box: cam_player_box,
-            zoom: cam_zoom
-        }
-    }
+        dialog_insets,
+        dialog_text_speed,
+        dialog_portrait_box,
+        cameras
+    })
 }
 
-/// Parse yaml into collision map
-fn parse_collision_map(yaml: &Yaml) -> Option<Vec<Vec<bool>>> {
-    let image_path = parse_string(yaml);
+/// Parse yaml into sound config, mapping state names to sound paths
+fn parse_sound_config(yaml: &Yaml, texture_manager: &TextureManager) -> SoundConfig {
+    let sounds = yaml.as_hash()
+        .map(|hash| hash.iter()
+            .filter_map(|(state, path)| Some((parse_string(state)?, texture_manager.resolve(&parse_string(path)?))))
+            .collect())
+        .unwrap_or_default();
 
-    if image_path.is_none() {
-        return None;
+    SoundConfig { sounds }
+}
+
+/// Parse yaml into state names, keeping the default for any name not given
+fn parse_state_names(yaml: &Yaml) -> StateNames {
+    let default = StateNames::default();
+
+    StateNames {
+        idle: parse_string_or(&yaml["idle"], &default.idle),
+        walking: parse_string_or(&yaml["walking"], &default.walking),
+        colliding: parse_string_or(&yaml["colliding"], &default.colliding),
+        invisible: parse_string_or(&yaml["invisible"], &default.invisible),
+        aggro: parse_string_or(&yaml["aggro"], &default.aggro),
+        lost: parse_string_or(&yaml["lost"], &default.lost),
+        stuck: parse_string_or(&yaml["stuck"], &default.stuck),
+        ai: parse_string_or(&yaml["ai"], &default.ai)
     }
+}
 
-    let img = ImageReader::open(image_path.unwrap()).unwrap().decode().unwrap();
+/// Parse yaml into collision map
+fn parse_collision_map(yaml: &Yaml, texture_manager: &TextureManager) -> Result<Option<Vec<Vec<bool>>>, ParseError> {
+    let image_path = match parse_string(yaml) {
+        Some(path) => texture_manager.resolve(&path),
+        None => return Ok(None)
+    };
+
+    let image_error = |source| ParseError::Image { path: image_path.clone(), source };
+    let img = ImageReader::open(&image_path)
+        .map_err(|e| ParseError::Io { path: image_path.clone(), source: e })?
+        .decode()
+        .map_err(image_error)?;
 
     let width = img.width();
     let height = img.height();
@@ -583,164 +1369,481 @@ fn parse_collision_map(yaml: &Yaml) -> Option<Vec<Vec<bool>>> {
         map.push(a);
     }
 
-    Some(map)
+    Ok(Some(map))
 }
 
 /// Parse yaml into entrances
-fn parse_entrances(yaml: &Yaml) -> HashMap<String, PositionComponent> {
+fn parse_entrances(yaml: &Yaml) -> Result<HashMap<String, PositionComponent>, ParseError> {
     let mut entrances = HashMap::new();
 
-    for val in yaml.as_vec().unwrap() {
-        let name = val["name"].as_str().unwrap().to_string();
-        let comp = parse_position_component(val).unwrap();
+    for (i, val) in require(yaml.as_vec(), "entrances")?.iter().enumerate() {
+        let name = require(parse_string(&val["name"]), &format!("entrances[{}].name", i))?;
+        let field = format!("entrances[{}]", i);
+        let comp = require(parse_position_component(val, &field)?, &field)?;
 
         entrances.insert(name, comp);
     }
 
-    entrances
+    Ok(entrances)
 }
 
-fn parse_game_worlds(yaml: &Yaml) -> (HashMap<String, String>, HashMap<String, Option<Vec<Vec<bool>>>>) {
+fn parse_game_worlds(yaml: &Yaml, texture_manager: &TextureManager) -> Result<(HashMap<String, String>, HashMap<String, Option<Vec<Vec<bool>>>>), ParseError> {
     let mut worlds = HashMap::new();
     let mut maps = HashMap::new();
 
-    for val in yaml.as_vec().unwrap() {
-        let name = parse_string(&val["name"]).unwrap();
-        let path = parse_string(&val["path"]).unwrap();
+    for (i, val) in require(yaml.as_vec(), "worlds")?.iter().enumerate() {
+        let name = require(parse_string(&val["name"]), &format!("worlds[{}].name", i))?;
+        let path = texture_manager.resolve(&require(parse_string(&val["path"]), &format!("worlds[{}].path", i))?);
 
         worlds.insert(name.clone(), path);
-        maps.insert(name, parse_collision_map(&val["map"]));
+        maps.insert(name, parse_collision_map(&val["map"], texture_manager)?);
     }
 
-    (worlds, maps)
+    Ok((worlds, maps))
 }
 
 /// Parse Game File
-pub fn parse_game_file<'a>(path: &str, texture_manager: TextureManager<'a>) -> (World<'a>, InputConfig, GraphicsConfig, AISystem) {
-    let mut file = File::open(path).unwrap();
-    let file_size = file.metadata().unwrap().len();
-    let mut contents = String::with_capacity(file_size as usize);
-    file.read_to_string(&mut contents).unwrap();
+pub fn parse_game_file<'a>(path: &str, mut texture_manager: TextureManager<'a>) -> Result<(World<'a>, InputConfig, GraphicsConfig, AISystem, SoundConfig), ParseError> {
+    let contents = read_file(path)?;
+
+    // Assets are found relative to the game file rather than the working directory
+    if let Some(dir) = Path::new(path).parent() {
+        texture_manager.asset_root = dir.to_path_buf();
+    }
 
-    parse_game_string(&contents, texture_manager)
+    let mut parsed = parse_game_string(&contents, texture_manager)?;
+    parsed.0.game_file = Some(path.into());
+    Ok(parsed)
 }
 
 /// Parse World File
-pub fn parse_world_file(path: &str, world: &mut World, entrance: &str) {
-    let mut file = File::open(path).unwrap();
-    let file_size = file.metadata().unwrap().len();
-    let mut contents = String::with_capacity(file_size as usize);
-    file.read_to_string(&mut contents).unwrap();
+pub fn parse_world_file(path: &str, world: &mut World, entrance: &str) -> Result<(), ParseError> {
+    parse_world_document(&load_world_document(path)?, world, entrance)
+}
 
-    parse_world_string(&contents, world, entrance)
+/// Read a world file into a yaml document without touching any world,
+/// so a file which is not valid yaml can be caught before the current world is deloaded
+pub fn load_world_document(path: &str) -> Result<Yaml, ParseError> {
+    load_document(&read_file(path)?)
 }
 
 /// Parse Game String
-pub fn parse_game_string<'a>(contents: &str, texture_manager: TextureManager<'a>) -> (World<'a>, InputConfig, GraphicsConfig, AISystem) {
-    let docs = YamlLoader::load_from_str(contents).unwrap();
-    let doc = &docs[0];
+pub fn parse_game_string<'a>(contents: &str, mut texture_manager: TextureManager<'a>) -> Result<(World<'a>, InputConfig, GraphicsConfig, AISystem, SoundConfig), ParseError> {
+    let doc = &load_document(contents)?;
+
+    if let Some(root) = parse_string(&doc["asset_root"]) {
+        texture_manager.asset_root = texture_manager.resolve(&root).into();
+    }
 
-    let (worlds, maps) = parse_game_worlds(&doc["worlds"]);
+    let (worlds, maps) = parse_game_worlds(&doc["worlds"], &texture_manager)?;
     let mut world = World::new(texture_manager, worlds, maps);
+    world.state_names = parse_state_names(&doc["state_names"]);
+    world.strict = parse_bool_or(&doc["strict"], false);
+    world.physics_hz = parse_f32_or(&doc["physics_hz"], 120.0);
+    world.gravity = parse_f32(&doc["physics"]["gravity"]);
+    world.max_physics_steps = parse_u32_or(&doc["max_physics_steps"], 8);
+    world.frame_rate = parse_f32_or(&doc["frame_rate"], 60.0);
 
     // Parse the System Configs
-    let input_config = parse_input_config(&doc["inputs"]);
-    let graphics_config = parse_graphics_config(&doc["graphics"], &mut world.texture_manager);
+    let mut input_config = parse_input_config(&doc["inputs"]);
+    input_config.smoothing = parse_f32(&doc["input_smoothing"]);
+    input_config.skip_key = parse_string(&doc["dialog_skip"]["key"]).and_then(|k| Keycode::from_name(&k));
+    input_config.skip_button = parse_string(&doc["dialog_skip"]["button"]).and_then(|b| Button::from_string(&b));
+    let graphics_config = parse_graphics_config(&doc["graphics"], &mut world.texture_manager)?;
+    let sound_config = parse_sound_config(&doc["sounds"], &world.texture_manager);
 
     // Parse the player components
-    let comps = parse_entity(&doc["player"], &mut world.texture_manager);
+    let comps = parse_entity(&doc["player"], "player", &mut world.texture_manager)?;
     let pid = world.add_global_entity(comps.0, comps.1, comps.2, comps.3, comps.4);
+    world.set_entity_name(pid, "player".into());
+    world.player_id = pid;
+    parse_entity_extras(&doc["player"], "player", &mut world, pid)?;
 
-    if let Some(state) = comps.5 {
+    for state in comps.5 {
         world.add_entity_state(pid, state);
     }
 
-    // Parse the monster components
-    let mcomps = parse_entity(&doc["monster"], &mut world.texture_manager);
-    let mid = world.add_global_entity(mcomps.0, mcomps.1, mcomps.2, mcomps.3, mcomps.4);
+    // Parse the other global entities, such as those which follow the player between worlds
+    let no_globals = Vec::new();
+    let globals = doc["monster"].as_hash().map(|_| ("monster".to_string(), &doc["monster"])).into_iter()
+        .chain(doc["globals"].as_vec().unwrap_or(&no_globals).iter().enumerate().map(|(i, y)| (format!("globals[{}]", i), y)));
 
-    if let Some(state) = mcomps.5 {
-        world.add_entity_state(mid, state);
+    for (field, entity) in globals {
+        let comps = parse_entity(entity, &field, &mut world.texture_manager)?;
+        let id = world.add_global_entity(comps.0, comps.1, comps.2, comps.3, comps.4);
+        parse_entity_extras(entity, &field, &mut world, id)?;
+
+        for state in comps.5 {
+            world.add_entity_state(id, state);
+        }
     }
 
     // Load Entry Point
-    let entry = doc["entry"].as_str().unwrap();
-    let (world_name, entrance) = entry.split_once("/").unwrap();
-
-    world.load(world_name, entrance);
-
-    let mut path = Vec::new();
+    let entry = require(parse_string(&doc["entry"]), "entry")?;
+    let (world_name, entrance) = entry.split_once("/").ok_or_else(|| ParseError::Invalid {
+        field: "entry".into(),
+        reason: format!("expected world/entrance, got '{}'", entry)
+    })?;
 
-    for item in doc["monster"]["ai"]["path"].as_vec().unwrap() {
-        path.push((parse_f32(&item["x"]).unwrap(), parse_f32(&item["y"]).unwrap(), parse_f32(&item["t"]).unwrap()));
-    }
-
-    let aggro_distance = parse_f32_or(&doc["monster"]["ai"]["aggro_distance"], 1000.0);
-    let lost_delay = parse_f32_or(&doc["monster"]["ai"]["lost_delay"], 5.0);
+    world.load(world_name, entrance)?;
 
-    let ai_system = AISystem::new(
-        path,
-        aggro_distance,
-        lost_delay
-    );
+    let ai_system = AISystem::new(parse_ai_config(&doc["ai"], "ai")?);
 
-    (world, input_config, graphics_config, ai_system)
+    Ok((world, input_config, graphics_config, ai_system, sound_config))
 }
 
 /// Parse World String
-pub fn parse_world_string(contents: &str, world: &mut World, entrance: &str) {
-    let docs = YamlLoader::load_from_str(contents).unwrap();
-    let doc = &docs[0];
+pub fn parse_world_string(contents: &str, world: &mut World, entrance: &str) -> Result<(), ParseError> {
+    parse_world_document(&load_document(contents)?, world, entrance)
+}
+
+/// Add the entities, effects, and dialogs of a world document to the world
+pub fn parse_world_document(doc: &Yaml, world: &mut World, entrance: &str) -> Result<(), ParseError> {
 
-    // World
-    let background = parse_graphics_component(&doc["background"], &mut world.texture_manager);
+    // World, whose background may be only a color
+    let background = if doc["background"]["path"].is_badvalue() {
+        None
+    } else {
+        parse_graphics_component(&doc["background"], "background", &mut world.texture_manager)?
+    };
     let width = parse_u32_or(&doc["w"], 0);
     let height = parse_u32_or(&doc["h"], 0);
 
-    let b_red = parse_u32_or(&doc["background"]["color"]["r"], 255);
-    let b_blue = parse_u32_or(&doc["background"]["color"]["g"], 255);
-    let b_green = parse_u32_or(&doc["background"]["color"]["b"], 255);
+    let background_color = parse_color(&doc["background"]["color"]);
 
-    let background_color = Color::RGB(b_red as u8, b_blue as u8, b_green as u8);
-
-    world.background = background;
+    // The background is the first layer, moving with the world
+    world.backgrounds = background.into_iter().map(|background| (background, 1.0)).collect();
+    for (i, y) in doc["backgrounds"].as_vec().unwrap_or(&Vec::new()).iter().enumerate() {
+        if let Some(layer) = parse_graphics_component(y, &format!("backgrounds[{}]", i), &mut world.texture_manager)? {
+            world.backgrounds.push((layer, parse_f32_or(&y["parallax"], 1.0)));
+        }
+    }
     world.background_color = background_color;
+    world.out_of_bounds_color = doc["background"]["out_of_bounds_color"].as_hash()
+        .map(|_| parse_color(&doc["background"]["out_of_bounds_color"]));
     world.world_width = width;
     world.world_height = height;
 
     // Parse the Entities
-    for entity in doc["entities"].as_vec().unwrap_or(&Vec::new()) {
-        let comps = parse_entity(entity, &mut world.texture_manager);
-        let id = world.add_entity(
-            comps.0,
-            comps.1,
-            comps.2,
-            comps.3,
-            comps.4
-        );
-
-        if let Some(state) = comps.5 {
-            world.add_entity_state(id, state);
-        }
+    let first_entity = world.states.len();
+    for (i, entity) in doc["entities"].as_vec().unwrap_or(&Vec::new()).iter().enumerate() {
+        add_parsed_entity(entity, &format!("entities[{}]", i), world)?;
     }
 
     // Parse entrances
-    let entrances = parse_entrances(&doc["entrances"]);
+    let entrances = parse_entrances(&doc["entrances"])?;
 
     // If entrance is in entrances, set players position component
     if let Some(comp) = entrances.get(entrance) {
-        world.positions[0] = Some(comp.clone());
+        world.positions[world.player_id] = Some(comp.clone());
+    } else if !entrance.is_empty() {
+        warn!("Unknown entrance '{}', the player keeps their position", entrance);
     }
 
     // Parse exits
-    for exit in doc["exits"].as_vec().unwrap_or(&Vec::new()) {
-        let exit = parse_exit(exit);
+    for (i, exit) in doc["exits"].as_vec().unwrap_or(&Vec::new()).iter().enumerate() {
+        let exit = parse_exit(exit, &format!("exits[{}]", i))?;
         world.effects.push(exit);
     }
 
-    // Parse Dialogs
-    doc["dialogs"].as_vec().unwrap_or(&Vec::new())
+    // Parse ambient effects
+    for effect in doc["ambient"].as_vec().unwrap_or(&Vec::new()) {
+        world.ambient.push(parse_effect(effect).spawn());
+    }
+
+    // Parse world events
+    world.world_events = doc["world_events"].as_vec().unwrap_or(&Vec::new())
         .iter()
-        .filter_map(|y| parse_dialog(y))
-        .for_each(|(name, dialog)| world.add_dialog(name, dialog));
+        .filter_map(parse_world_event)
+        .collect();
+
+    // Parse Dialogs
+    for (i, y) in doc["dialogs"].as_vec().unwrap_or(&Vec::new()).iter().enumerate() {
+        if let Some((name, dialog)) = parse_dialog(y, &format!("dialogs[{}]", i), &mut world.texture_manager)? {
+            world.add_dialog(name, dialog);
+        }
+    }
+
+    let errors = unknown_dialogs(world, first_entity);
+    if !errors.is_empty() {
+        return Err(ParseError::UnknownDialogs(errors));
+    }
+
+    Ok(())
+}
+
+/// Parse yaml into an entity and add it to the world, returning its id
+fn add_parsed_entity(yaml: &Yaml, field: &str, world: &mut World) -> Result<usize, ParseError> {
+    let comps = parse_entity(yaml, field, &mut world.texture_manager)?;
+    let id = world.add_entity(
+        comps.0,
+        comps.1,
+        comps.2,
+        comps.3,
+        comps.4
+    );
+    parse_entity_extras(yaml, field, world, id)?;
+
+    for state in comps.5 {
+        world.add_entity_state(id, state);
+    }
+
+    Ok(id)
+}
+
+/// Add an entity spawned by an action to the world, returning its id.
+/// The entity's position is an offset from origin, defaulting to origin itself
+pub fn parse_spawned_entity(yaml: &Yaml, world: &mut World, origin: Option<&PositionComponent>) -> Result<usize, ParseError> {
+    let id = add_parsed_entity(yaml, "spawn", world)?;
+
+    if let Some(origin) = origin {
+        let position = world.positions[id].get_or_insert_with(|| PositionComponent::new(0.0, 0.0));
+        position.x += origin.x;
+        position.y += origin.y;
+    }
+
+    Ok(id)
+}
+
+/// Find every dialog referenced by the world's entities, effects, and dialogs which is not defined,
+/// only checking entities from first_entity onwards since global entities outlive a world's dialogs
+fn unknown_dialogs(world: &World, first_entity: usize) -> Vec<String> {
+    let mut references: Vec<(String, String)> = Vec::new();
+
+    for id in first_entity..world.states.len() {
+        let dialogs = world.actions[id].iter().flat_map(|a| a.dialogs())
+            .chain(world.animations[id].iter().flat_map(|a| a.dialogs()))
+            .chain(world.projectiles[id].iter().filter_map(|p| p.on_hit.as_ref()).flat_map(|s| s.dialogs()));
+
+        references.extend(dialogs.map(|d| (format!("entity {}", id), d)));
+    }
+
+    for effect in world.effects.iter().chain(world.ambient.iter()) {
+        references.extend(effect.dialogs().into_iter().map(|d| ("effect".to_string(), d)));
+    }
+
+    for event in world.world_events.iter() {
+        references.extend(event.sequence.dialogs().into_iter().map(|d| ("world event".to_string(), d)));
+    }
+
+    for (name, dialog) in world.dialogs.iter() {
+        references.extend(dialog.dialogs().into_iter().map(|d| (format!("dialog '{}'", name), d)));
+    }
+
+    references.into_iter()
+        .filter(|(_, d)| !world.dialogs.contains_key(d))
+        .map(|(source, d)| format!("{} shows unknown dialog '{}'", source, d))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use crate::graphics::with_texture_manager;
+    use crate::logger;
+    use crate::world::WorldBuilder;
+
+    fn yaml(source: &str) -> Yaml {
+        load_document(source).unwrap()
+    }
+
+    #[test]
+    fn colors_keep_their_channels_in_order() {
+        assert_eq!(parse_color(&yaml("{r: 10, g: 20, b: 30}")), Color::RGB(10, 20, 30));
+    }
+
+    #[test]
+    fn colors_read_alpha_defaulting_to_opaque() {
+        assert_eq!(parse_color(&yaml("{r: 10, g: 20, b: 30, a: 40}")), Color::RGBA(10, 20, 30, 40));
+        assert_eq!(parse_color(&yaml("{r: 10, g: 20, b: 30}")).a, 255);
+    }
+
+    #[test]
+    fn missing_component_fields_name_their_path() {
+        let physics = yaml("hitbox: {x: 0, y: 0, h: 8}");
+
+        match parse_physics_component(&physics, "entities[3].physics") {
+            Err(ParseError::Missing { field }) => assert_eq!(field, "entities[3].physics.hitbox.w"),
+            other => panic!("expected a missing field, got {:?}", other.map(|_| ()))
+        }
+
+        let position = yaml("{x: 4, y: ten}");
+        let error = parse_position_component(&position, "entities[0].position").unwrap_err();
+        assert_eq!(error.to_string(), "entities[0].position.y invalid: expected a number");
+    }
+
+    #[test]
+    fn layers_past_31_are_invalid() {
+        let physics = parse_physics_component(&yaml("{hitbox: {w: 8, h: 8}, layer: 31}"), "physics").unwrap().unwrap();
+        assert_eq!(physics.collision_layer, 1 << 31);
+
+        match parse_physics_component(&yaml("{hitbox: {w: 8, h: 8}, layer: 32}"), "physics") {
+            Err(ParseError::Invalid { field, .. }) => assert_eq!(field, "physics.layer"),
+            other => panic!("expected an invalid layer, got {:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn absent_components_are_none() {
+        let entity = yaml("name: rock");
+
+        assert!(parse_position_component(&entity["position"], "position").unwrap().is_none());
+        assert!(parse_physics_component(&entity["physics"], "physics").unwrap().is_none());
+        assert!(parse_follow_component(&entity["follow"], "follow").unwrap().is_none());
+    }
+
+    #[test]
+    fn frames_wrap_onto_the_next_row_of_a_sheet() {
+        let first = sdl2::rect::Rect::new(2, 4, 16, 16);
+
+        assert_eq!(frame_srcbox(first, 5, 4, 16, 16), sdl2::rect::Rect::new(18, 20, 16, 16));
+        assert_eq!(frame_srcbox(first, 3, 4, 16, 16), sdl2::rect::Rect::new(50, 4, 16, 16));
+
+        // A single row when there is no frame height
+        assert_eq!(frame_srcbox(first, 5, 8, 16, 0), sdl2::rect::Rect::new(82, 4, 16, 16));
+    }
+
+    #[test]
+    fn dialogs_without_messages_are_rejected() {
+        with_texture_manager(|mut texture_manager| {
+            let error = parse_dialog(&yaml("{name: intro, messages: []}"), "dialogs[2]", &mut texture_manager).err().unwrap();
+            assert_eq!(error.to_string(), "dialogs[2].messages invalid: dialog 'intro' has no messages");
+
+            let error = parse_dialog(&yaml("name: intro"), "dialogs[2]", &mut texture_manager).err().unwrap();
+            assert!(matches!(error, ParseError::Invalid { .. }));
+        });
+    }
+
+    #[test]
+    fn empty_action_lists_have_no_sequence() {
+        assert!(parse_sequence(&yaml("[]")).is_none());
+        assert!(parse_event(&yaml("{states: [open], actions: []}")).is_none());
+    }
+
+    #[test]
+    fn duplicate_animation_states_warn_and_keep_the_last() {
+        with_texture_manager(|mut texture_manager| {
+            let animations = yaml("
+                - {state: walking, path: assets/box.png, period: 0.1}
+                - {state: walking, path: assets/box.png, period: 0.1, loop: false}
+            ");
+
+            let mut parsed = None;
+            let logs = logger::capture(|| {
+                parsed = Some(parse_animations_component(&animations, "player.animations", &mut texture_manager));
+            });
+
+            let animations = parsed.unwrap().unwrap().unwrap();
+            assert!(!animations.get(&"walking".to_string()).unwrap().looping);
+            assert!(logs.contains(&"[WARN] Duplicate animation for state 'walking', using the last one".to_string()), "logged {:?}", logs);
+        });
+    }
+
+    #[test]
+    fn malformed_animations_are_skipped_with_a_warning() {
+        with_texture_manager(|mut texture_manager| {
+            let animations = yaml("
+                - {state: idle, path: assets/box.png, period: 0.1}
+                - {state: walking, path: assets/box.png}
+            ");
+
+            let mut parsed = None;
+            let logs = logger::capture(|| {
+                parsed = Some(parse_animations_component(&animations, "player.animations", &mut texture_manager));
+            });
+
+            let animations = parsed.unwrap().unwrap().unwrap();
+            assert!(animations.get(&"idle".to_string()).is_some());
+            assert!(animations.get(&"walking".to_string()).is_none());
+            assert_eq!(logs, vec!["[WARN] Skipping malformed animation player.animations[1]: player.animations[1].period missing"]);
+        });
+    }
+
+    #[test]
+    fn actions_showing_missing_dialogs_are_rejected() {
+        with_texture_manager(|texture_manager| {
+            let mut world = WorldBuilder::new(texture_manager).current_world("test").build();
+            let source = "
+                entities:
+                  - position: {x: 0, y: 0}
+                    events:
+                      - states: [talking]
+                        actions:
+                          - {type: dialog, dialog: greeting}
+                entrances: []
+                dialogs:
+                  - {name: farewell, messages: [Bye]}
+            ";
+
+            match parse_world_string(source, &mut world, "") {
+                Err(ParseError::UnknownDialogs(errors)) => assert_eq!(errors, vec!["entity 0 shows unknown dialog 'greeting'"]),
+                other => panic!("expected unknown dialogs, got {:?}", other)
+            }
+        });
+    }
+
+    #[test]
+    fn strict_mode_warns_on_typos_but_not_meta() {
+        with_texture_manager(|texture_manager| {
+            let mut world = WorldBuilder::new(texture_manager).current_world("test").build();
+            let source = "
+                entities:
+                  - positoin: {x: 0, y: 0}
+                    position: {x: 4, y: 2, meta: from the map}
+                    meta: {note: the cellar door}
+                entrances: []
+            ";
+
+            let logs = logger::capture(|| parse_world_string(source, &mut world, "").unwrap());
+            assert!(logs.iter().all(|l| !l.starts_with("[WARN] Unknown key")), "logged {:?}", logs);
+
+            world.strict = true;
+            let logs = logger::capture(|| parse_world_string(source, &mut world, "").unwrap());
+            let unknown: Vec<&String> = logs.iter().filter(|l| l.starts_with("[WARN] Unknown key")).collect();
+            assert_eq!(unknown, vec!["[WARN] Unknown key 'positoin' in entity"]);
+        });
+    }
+
+    #[test]
+    fn entities_start_in_every_listed_state() {
+        with_texture_manager(|texture_manager| {
+            let mut world = WorldBuilder::new(texture_manager).current_world("test").build();
+            let source = "
+                entities:
+                  - position: {x: 0, y: 0}
+                    state: [idle, friendly]
+                  - position: {x: 8, y: 0}
+                    state: idle
+                entrances: []
+            ";
+
+            parse_world_string(source, &mut world, "").unwrap();
+
+            assert_eq!(world.states[0], HashSet::from(["idle".to_string(), "friendly".to_string()]));
+            assert_eq!(world.states[1], HashSet::from(["idle".to_string()]));
+        });
+    }
+
+    #[test]
+    fn the_background_is_the_first_layer_moving_with_the_world() {
+        with_texture_manager(|texture_manager| {
+            let mut world = WorldBuilder::new(texture_manager).current_world("test").build();
+            let source = "
+                background:
+                  path: assets/forest.png
+                  renderbox: {w: 64, h: 64}
+                backgrounds:
+                  - {path: assets/lake.png, renderbox: {w: 32, h: 32}, parallax: 0.5}
+                entities: []
+                entrances: []
+            ";
+
+            parse_world_string(source, &mut world, "").unwrap();
+
+            let parallax: Vec<f32> = world.backgrounds.iter().map(|(_, parallax)| *parallax).collect();
+            assert_eq!(parallax, vec![1.0, 0.5]);
+            assert_eq!(world.backgrounds[0].0.renderbox.w, 64);
+        });
+    }
 }
@@ -1,15 +1,27 @@
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use crate::priority_queue::PriorityQueue;
 
+use crate::geometry::Rect;
 use crate::tree::Tree;
 
+/// Most cells searched before giving up, so an unreachable target cannot search forever
+const MAX_VISITS: usize = 1024;
+
+/// Cost of a straight step, per pixel moved, in tenths so diagonals stay whole numbers
+const STRAIGHT: u32 = 10;
+/// Cost of a diagonal step, per pixel moved along each axis, about √2 times a straight step
+const DIAGONAL: u32 = 14;
+
+/// Cell of a search: (tree id, priority g + h, x, y)
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub struct Node (pub usize, pub u32, pub i32, pub i32);
 
+/// Nodes with a lower priority compare greater, so the cheapest node is the maximum
 impl Ord for Node {
     fn cmp(&self, other: &Self) -> Ordering {
-        other.0.cmp(&self.0)
+        other.1.cmp(&self.1)
+            .then_with(|| (self.0, self.2, self.3).cmp(&(other.0, other.2, other.3)))
     }
 }
 
@@ -19,24 +31,54 @@ impl PartialOrd for Node {
     }
 }
 
-pub fn shortest_path_segment(from: (i32, i32), to: (i32, i32), delta: i32) -> Option<(i32, i32)> {
+/// Next step on a path from one point to another over a grid of cells delta apart,
+/// for a mover whose footprint is size with its top left corner at the point.
+/// Cells where the footprint would intersect a blocked rectangle are not walked through, nor are diagonal steps past them,
+/// except for rectangles already overlapping the start so a mover pressed against a wall can still leave it.
+/// Returns none if the target cannot be reached within the search limit
+pub fn shortest_path_segment(from: (i32, i32), to: (i32, i32), delta: i32, size: (u32, u32), blocked: &[Rect]) -> Option<(i32, i32)> {
+    let delta = delta.max(1);
+    let cell = |x: i32, y: i32| Rect::new(x as f32, y as f32, size.0, size.1);
+
+    let start = cell(from.0, from.1);
+    let blocked: Vec<&Rect> = blocked.iter()
+        .filter(|rect| !rect.has_intersection(start))
+        .collect();
+    let walkable = |x: i32, y: i32| {
+        let footprint = cell(x, y);
+        !blocked.iter().any(|rect| rect.has_intersection(footprint))
+    };
+
     let mut tree = Tree::new(from);
 
+    // Reaching anywhere within delta of the target ends the search, so discount that from the estimate
+    let heuristic = |x: i32, y: i32| octile(x, y, to.0, to.1).saturating_sub(STRAIGHT * delta as u32);
+
     let mut queue = PriorityQueue::new();
-    queue.push(Node(0, 0, from.0, from.1));
+    queue.push(Node(0, heuristic(from.0, from.1), from.0, from.1));
 
+    // Cheapest known step cost from the start to each cell, g(n)
+    let mut costs = HashMap::from([(from, 0)]);
     let mut visits = HashSet::new();
 
-    while let Some(Node(node, curr_cost, x, y)) = queue.pop() {
-        visits.insert((x, y));
+    while let Some(Node(node, _, x, y)) = queue.pop() {
+        if !visits.insert((x, y)) {
+            continue;
+        }
+
+        if visits.len() > MAX_VISITS {
+            break;
+        }
 
-        // If we have reached our destination return the next step
+        // If we have reached our destination return the next step, the path starts with the start itself
         if dist(x, y, to.0, to.1) < delta as u32 {
             let path = tree.path_to(node);
-            return Some(path[0]);
+            return Some(*path.get(1).unwrap_or(&path[0]));
         }
 
-        // For each direction add an adjacent node and its cost
+        let curr_cost = costs[&(x, y)];
+
+        // For each direction add an adjacent node, prioritized by
         // f(n) = g(n) + h(n)
         for i in -1..=1 {
             for j in -1..=1 {
@@ -49,9 +91,25 @@ pub fn shortest_path_segment(from: (i32, i32), to: (i32, i32), delta: i32) -> Op
                     continue;
                 }
 
-                let cost = curr_cost + 1 + dist(x, y, to.0, to.1);
+                if !walkable(new_x, new_y) {
+                    continue;
+                }
+
+                // Never cut the corner between two cells when either side is blocked
+                if i != 0 && j != 0 && (!walkable(new_x, y) || !walkable(x, new_y)) {
+                    continue;
+                }
+
+                let step = if i != 0 && j != 0 { DIAGONAL } else { STRAIGHT };
+                let cost = curr_cost + step * delta as u32;
+
+                if costs.get(&(new_x, new_y)).is_some_and(|known| *known <= cost) {
+                    continue;
+                }
+                costs.insert((new_x, new_y), cost);
+
                 let id = tree.insert(node, (new_x, new_y));
-                let new_node = Node(id, cost, new_x, new_y);
+                let new_node = Node(id, cost + heuristic(new_x, new_y), new_x, new_y);
                 queue.insert_or_replace(new_node);
             }
         }
@@ -60,7 +118,68 @@ pub fn shortest_path_segment(from: (i32, i32), to: (i32, i32), delta: i32) -> Op
     None
 }
 
+/// Cost of the cheapest path between two points moving only straight and diagonally, ignoring obstacles
+fn octile(x0: i32, y0: i32, x1: i32, y1: i32) -> u32 {
+    let dx = (x1-x0).unsigned_abs();
+    let dy = (y1-y0).unsigned_abs();
+
+    STRAIGHT * dx.max(dy) + (DIAGONAL - STRAIGHT) * dx.min(dy)
+}
+
 fn dist(x0: i32, y0: i32, x1: i32, y1: i32) -> u32 {
     ((y1-y0).pow(2) as f32 + (x1-x0).pow(2) as f32).sqrt() as u32
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every cell a unit sized mover passes through walking one cell at a time from one point to another
+    fn walk(from: (i32, i32), to: (i32, i32), blocked: &[Rect]) -> Vec<(i32, i32)> {
+        let mut path = vec![from];
+
+        while *path.last().unwrap() != to {
+            assert!(path.len() < 100, "never reached {:?}: {:?}", to, path);
+            path.push(shortest_path_segment(*path.last().unwrap(), to, 1, (1, 1), blocked).unwrap());
+        }
+
+        path
+    }
+
+    #[test]
+    fn paths_on_an_open_grid_are_optimal() {
+        // 3 diagonal steps and 2 straight ones, costing 3√2 + 2
+        let path = walk((0, 0), (5, 3), &[]);
+        assert_eq!(path.len() - 1, 5);
+
+        let cost: u32 = path.windows(2)
+            .map(|step| octile(step[0].0, step[0].1, step[1].0, step[1].1))
+            .sum();
+        assert_eq!(cost, octile(0, 0, 5, 3));
+    }
+
+    #[test]
+    fn straight_routes_do_not_zigzag() {
+        // A zigzag takes as many steps, but each diagonal costs about √2 of a straight one
+        assert_eq!(walk((0, 0), (4, 0), &[]), vec![(0, 0), (1, 0), (2, 0), (3, 0), (4, 0)]);
+    }
+
+    #[test]
+    fn diagonals_never_cut_between_touching_obstacles() {
+        let cell = |x: i32, y: i32| Rect::new(x as f32, y as f32, 1, 1);
+        let blocked = [cell(1, 0), cell(0, 1)];
+
+        assert_ne!(shortest_path_segment((0, 0), (1, 1), 1, (1, 1), &blocked), Some((1, 1)));
+
+        // The mover has to go around, and never steps diagonally past a blocked side
+        let path = walk((0, 0), (1, 1), &blocked);
+        for step in path.windows(2) {
+            let ((x0, y0), (x1, y1)) = (step[0], step[1]);
+            for side in [(x1, y0), (x0, y1), (x1, y1)] {
+                assert!(!blocked.iter().any(|b| b.has_intersection(cell(side.0, side.1))), "{:?} cuts past {:?}", step, side);
+            }
+        }
+        assert_eq!(path.len() - 1, 6);
+    }
+}
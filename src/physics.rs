@@ -1,7 +1,7 @@
-use std::f32::consts::FRAC_PI_2;
 use crate::{vector::Vector, world::World, geometry::PositionComponent};
-use std::time::Instant;
 use std::collections::HashSet;
+use std::f32::consts::FRAC_PI_2;
+use log::trace;
 use crate::geometry::Rect;
 
 /// Physics information for a single entity
@@ -13,8 +13,36 @@ pub struct PhysicsComponent {
     pub velocity: Vector,
     /// Whether this object is physical and thus stops other physical objects
     physical: bool,
+    /// Whether this object only reports overlaps in `World::collisions` and never blocks
+    pub trigger: bool,
+    /// Whether this object blocks line of sight
+    pub occludes_sight: bool,
+    /// States in which this object stops blocking line of sight, such as an open door
+    pub see_through_states: Vec<String>,
     /// Hitbox of the entity
-    pub hitbox: Rect
+    pub hitbox: Rect,
+    /// Speed the entity moves at under full input in pixels/second
+    pub speed: f32,
+    /// Maximum magnitude of the velocity, if any
+    pub max_speed: Option<f32>,
+    /// Whether movement is checked in steps so fast entities cannot pass through thin objects
+    pub continuous: bool,
+    /// Height of the bottom of the entity above the ground
+    pub elevation: f32,
+    /// Vertical size of the entity, starting from its elevation
+    pub height: f32,
+    /// Bitfield of the layers this entity belongs to
+    pub collision_layer: u32,
+    /// Bitfield of the layers this entity collides with
+    pub collision_mask: u32,
+    /// Whether the world's gravity pulls on this entity
+    pub affected_by_gravity: bool,
+    /// Seconds of blocked movement before the entity is considered stuck, never if none
+    pub stuck_delay: Option<f32>,
+    /// Seconds the entity has been trying to move without getting anywhere
+    stuck_time: f32,
+    /// Direction of the last nonzero velocity
+    facing: Option<f32>
 }
 
 impl PhysicsComponent {
@@ -24,34 +52,118 @@ impl PhysicsComponent {
             depth,
             velocity: Vector::zero(),
             physical,
-            hitbox
+            trigger: false,
+            occludes_sight: physical,
+            see_through_states: Vec::new(),
+            hitbox,
+            speed: 55.0,
+            max_speed: None,
+            continuous: false,
+            elevation: 0.0,
+            height: hitbox.h as f32,
+            collision_layer: 1,
+            collision_mask: u32::MAX,
+            affected_by_gravity: false,
+            stuck_delay: None,
+            stuck_time: 0.0,
+            facing: None
+        }
+    }
+
+    /// Direction the entity is pointing in radians,
+    /// which is the last nonzero velocity direction so it persists once the entity stops
+    pub fn facing_direction(&self) -> Option<f32> {
+        if self.velocity.mag != 0.0 {
+            Some(self.velocity.dir)
+        } else {
+            self.facing
         }
     }
+
+    /// Check if the vertical ranges of two entities overlap, ie they are able to collide
+    pub fn overlaps_vertically(&self, other: &PhysicsComponent) -> bool {
+        self.elevation < other.elevation + other.height && other.elevation < self.elevation + self.height
+    }
+
+    /// Check if this object blocks line of sight while it has the given states
+    pub fn occludes(&self, states: &HashSet<String>) -> bool {
+        self.occludes_sight && !self.see_through_states.iter().any(|s| states.contains(s))
+    }
+
+    /// Check if this object stops other physical objects, rather than only reporting overlaps
+    pub fn blocks(&self) -> bool {
+        self.physical && !self.trigger
+    }
+
+    /// Check if the collision layers and masks of two entities allow them to collide
+    pub fn layers_collide(&self, other: &PhysicsComponent) -> bool {
+        (self.collision_layer & other.collision_mask) != 0 && (other.collision_layer & self.collision_mask) != 0
+    }
 }
 
-/// System for handling physics interactions
-pub struct PhysicsSystem {
-    last_tick: Instant
+/// Record of an entity overlapping a trigger, a trigger overlapping an entity,
+/// or an entity blocked by another entity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Collision {
+    /// Id of the moving entity
+    pub entity: usize,
+    /// Id of the entity it overlaps
+    pub other: usize,
+    /// Normal of the side of the other entity which was hit, pointing back towards the moving entity.
+    /// Horizontal hits have a normal of (±1, 0), vertical hits (0, ±1), and corner hits both
+    pub normal: (f32, f32)
 }
 
+/// System for handling physics interactions
+pub struct PhysicsSystem;
+
 impl PhysicsSystem {
     /// Create a new PhysicsSystem
     pub fn new() -> PhysicsSystem {
-        PhysicsSystem {
-            last_tick: Instant::now()
-        }
+        PhysicsSystem {}
     }
 
-    /// Handle collisions with other entities and apply relevant velocites
-    pub fn run(&mut self, world: &mut World) {
+    /// Handle collisions with other entities and apply relevant velocities over dt seconds
+    pub fn step(&mut self, world: &mut World, dt: f32) {
+        if world.frozen() {
+            return;
+        }
+
+        let colliding = world.state_names.colliding.clone();
+        let stuck = world.state_names.stuck.clone();
+        let statics = world.statics.clone();
+        let gravity = world.gravity;
+
         // Sum all forces and calculate velocities
         let (entities, map) = world.physics_mut();
         let mut entities: Vec<(usize, (&mut HashSet<String>, &mut PositionComponent, &mut PhysicsComponent))> = entities.collect();
+        let mut collisions = Vec::new();
 
         for i in 0..entities.len() {
+            // Static entities are only ever collided against
+            if statics[entities[i].0] {
+                continue;
+            }
+
+            // Accelerate falling entities downwards
+            if let (Some(gravity), true) = (gravity, entities[i].1.2.affected_by_gravity) {
+                entities[i].1.2.velocity += Vector::new(FRAC_PI_2, gravity * dt);
+            }
+
+            // Clamp velocity to the entity's max speed
+            if let Some(max_speed) = entities[i].1.2.max_speed {
+                if entities[i].1.2.velocity.mag > max_speed {
+                    entities[i].1.2.velocity.mag = max_speed;
+                }
+            }
+
+            // Remember which way the entity is pointing
+            if entities[i].1.2.velocity.mag != 0.0 {
+                entities[i].1.2.facing = Some(entities[i].1.2.velocity.dir);
+            }
+
             // Apply final velocities
-            let t = self.last_tick.elapsed().as_secs_f32();
-            let mut delta_vec = entities[i].1.2.velocity * t;
+            let mut delta_vec = entities[i].1.2.velocity * dt;
 
             let depth = entities[i].1.2.depth;
 
@@ -59,34 +171,39 @@ impl PhysicsSystem {
                 .after_position(entities[i].1.1)
                 .after_depth(depth);
 
-            let mut after_x = footprint.clone();
-            let mut after_y = footprint.clone();
+            // Stop fast movers before they can pass through anything between frames
+            if entities[i].1.2.continuous && entities[i].1.2.physical && !entities[i].1.2.trigger {
+                let obstacles: Vec<Rect> = entities.iter()
+                    .enumerate()
+                    .filter(|(j, e)| *j != i && e.1.2.physical && !e.1.2.trigger)
+                    .filter(|(_, e)| e.1.2.overlaps_vertically(entities[i].1.2) && e.1.2.layers_collide(entities[i].1.2))
+                    .map(|(_, e)| e.1.2.hitbox.after_position(e.1.1).after_depth(e.1.2.depth))
+                    .collect();
 
-            after_x.x += delta_vec.x();
-            after_y.y += delta_vec.y();
+                delta_vec = sweep(footprint, delta_vec, &obstacles, map);
+            }
+
+            let (dx, dy) = (delta_vec.x(), delta_vec.y());
+
+            let mut after_x = footprint;
+            let mut after_y = footprint;
+            let mut after_xy = footprint;
+
+            after_x.x += dx;
+            after_y.y += dy;
+            after_xy.x += dx;
+            after_xy.y += dy;
 
             let mut collides = false;
 
+            // Footprints of every physical object which blocks this entity
+            let mut blockers = Vec::new();
+
             // Check map collisions
             if let Some(map) = map {
-                let x_collision = map_collision(map, after_x);
-                let y_collision = map_collision(map, after_y);
-
-                if x_collision || y_collision {
+                if map_collision(map, after_x) || map_collision(map, after_y) || map_collision(map, after_xy) {
                     collides = true;
                 }
-
-                if entities[i].1.2.physical {
-                    if x_collision && y_collision {
-                        delta_vec.mag = 0.0;
-                    } else if x_collision {
-                        delta_vec.mag *= delta_vec.dir.sin();
-                        delta_vec.dir = FRAC_PI_2;
-                    } else if y_collision {
-                        delta_vec.mag *= delta_vec.dir.cos();
-                        delta_vec.dir = 0.0;
-                    }
-                }
             }
 
             // Check and handle collisions
@@ -94,6 +211,12 @@ impl PhysicsSystem {
                 // If we are comparing the same rectangle skip
                 if i==j {continue;}
 
+                // Entities at different heights pass over/under each other
+                if !entities[i].1.2.overlaps_vertically(entities[j].1.2) {continue;}
+
+                // Entities on layers which ignore each other pass through each other
+                if !entities[i].1.2.layers_collide(entities[j].1.2) {continue;}
+
                 let other_depth = entities[j].1.2.depth;
                 let other_footprint = entities[j].1.2.hitbox
                     .after_position(entities[j].1.1)
@@ -101,37 +224,151 @@ impl PhysicsSystem {
 
                 let x_collision = after_x.has_intersection(other_footprint);
                 let y_collision = after_y.has_intersection(other_footprint);
+                let xy_collision = after_xy.has_intersection(other_footprint);
 
-                if x_collision || y_collision {
+                if x_collision || y_collision || xy_collision {
                     collides = true;
                 }
 
+                let normal = collision_normal(x_collision, y_collision, xy_collision, dx, dy);
+
+                // Triggers only report that they overlap
+                if entities[i].1.2.trigger || entities[j].1.2.trigger {
+                    if xy_collision {
+                        collisions.push(Collision { entity: entities[i].0, other: entities[j].0, normal });
+                    }
+
+                    continue;
+                }
+
                 if entities[i].1.2.physical && entities[j].1.2.physical {
-                    if x_collision && y_collision {
-                        delta_vec.mag = 0.0;
-                    } else if x_collision {
-                        delta_vec.mag *= delta_vec.dir.sin();
-                        delta_vec.dir = FRAC_PI_2;
-                    } else if y_collision {
-                        delta_vec.mag *= delta_vec.dir.cos();
-                        delta_vec.dir = 0.0;
+                    blockers.push(other_footprint);
+
+                    if x_collision || y_collision || xy_collision {
+                        collisions.push(Collision { entity: entities[i].0, other: entities[j].0, normal });
                     }
                 }
             }
 
+            // Slide along whatever blocks us, checking the final position against every obstacle at once
+            let (moved_x, moved_y) = if entities[i].1.2.physical {
+                resolve_movement(footprint, dx, dy, &blockers, map)
+            } else {
+                (dx, dy)
+            };
+
+            if moved_x != dx || moved_y != dy {
+                delta_vec = Vector::from_components(moved_x, moved_y);
+            }
+
+            let blocked_y = moved_y != dy;
+
+            // Landing on or hitting the underside of something ends a fall,
+            // so resting entities do not build up speed and jitter against the surface
+            if gravity.is_some() && entities[i].1.2.affected_by_gravity && blocked_y {
+                let vx = entities[i].1.2.velocity.x();
+                entities[i].1.2.velocity = Vector::from_components(vx, 0.0);
+            }
+
             if collides {
-                entities[i].1.0.insert("colliding".to_string());
+                entities[i].1.0.insert(colliding.clone());
             } else {
-                entities[i].1.0.remove(&"colliding".to_string());
+                entities[i].1.0.remove(&colliding);
+            }
+
+            // An entity moving under a tenth of its commanded distance is making no progress
+            if let Some(stuck_delay) = entities[i].1.2.stuck_delay {
+                let commanded = entities[i].1.2.velocity.mag * dt;
+
+                if commanded > 0.0 && delta_vec.mag < commanded * 0.1 {
+                    entities[i].1.2.stuck_time += dt;
+                } else {
+                    entities[i].1.2.stuck_time = 0.0;
+                }
+
+                if entities[i].1.2.stuck_time >= stuck_delay {
+                    entities[i].1.0.insert(stuck.clone());
+                } else {
+                    entities[i].1.0.remove(&stuck);
+                }
             }
 
             entities[i].1.1.apply_vector(delta_vec);
         }
 
-        self.last_tick = Instant::now();
+        if !collisions.is_empty() {
+            trace!("{} collisions", collisions.len());
+        }
+
+        world.collisions = collisions;
     }
 }
 
+/// Normal of the side hit by a movement of (dx, dy), from which of the axis probes collided.
+/// Only the diagonal probe colliding means a corner was hit
+fn collision_normal(x_collision: bool, y_collision: bool, xy_collision: bool, dx: f32, dy: f32) -> (f32, f32) {
+    // Points against the movement, unlike signum which is 1 for 0
+    let against = |d: f32| if d > 0.0 { -1.0 } else if d < 0.0 { 1.0 } else { 0.0 };
+
+    let corner = xy_collision && !x_collision && !y_collision;
+    let nx = if x_collision || corner { against(dx) } else { 0.0 };
+    let ny = if y_collision || corner { against(dy) } else { 0.0 };
+
+    (nx, ny)
+}
+
+/// Movement of a footprint by (dx, dy) that stays clear of every obstacle and the map.
+/// The whole movement is kept if its destination is clear. Otherwise the entity slides along
+/// whichever single axis is still clear, and stops if both or neither are
+fn resolve_movement(footprint: Rect, dx: f32, dy: f32, obstacles: &[Rect], map: Option<&Vec<Vec<bool>>>) -> (f32, f32) {
+    let clear = |dx: f32, dy: f32| {
+        let mut rect = footprint;
+        rect.x += dx;
+        rect.y += dy;
+
+        !obstacles.iter().any(|o| rect.has_intersection(*o))
+            && !map.is_some_and(|m| map_collision(m, rect))
+    };
+
+    if clear(dx, dy) {
+        return (dx, dy);
+    }
+
+    match (clear(dx, 0.0), clear(0.0, dy)) {
+        (true, false) => (dx, 0.0),
+        (false, true) => (0.0, dy),
+        _ => (0.0, 0.0)
+    }
+}
+
+/// Move a footprint along a vector in steps no larger than its smallest dimension,
+/// returning the part of the vector that can be travelled before an intermediate step is blocked.
+/// The final step is left to the regular collision check so that sliding still works.
+fn sweep(footprint: Rect, delta_vec: Vector, obstacles: &[Rect], map: Option<&Vec<Vec<bool>>>) -> Vector {
+    let step_size = footprint.w.min(footprint.h).max(1) as f32;
+    let steps = (delta_vec.mag / step_size).ceil() as u32;
+
+    if steps <= 1 {
+        return delta_vec;
+    }
+
+    let step = delta_vec / steps as f32;
+
+    for k in 1..steps {
+        let mut rect = footprint;
+        rect.apply_vector(step * k as f32);
+
+        let blocked = obstacles.iter().any(|o| rect.has_intersection(*o))
+            || map.is_some_and(|m| map_collision(m, rect));
+
+        if blocked {
+            return step * (k - 1).max(1) as f32;
+        }
+    }
+
+    delta_vec
+}
+
 fn map_collision(map: &Vec<Vec<bool>>, rect: Rect) -> bool {
     for x in rect.x as usize..rect.x as usize + rect.w as usize {
         for y in rect.y as usize..rect.y as usize + rect.h as usize {
@@ -143,3 +380,276 @@ fn map_collision(map: &Vec<Vec<bool>>, rect: Rect) -> bool {
 
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialog::Dialog;
+    use crate::graphics::{with_texture_manager, TextureManager};
+    use crate::world::WorldBuilder;
+
+    /// A physical entity at (x, y) whose whole hitbox is its footprint
+    fn body(x: f32, y: f32, w: u32, h: u32) -> (PositionComponent, PhysicsComponent) {
+        (PositionComponent::new(x, y), PhysicsComponent::new(Rect::new(0.0, 0.0, w, h), h, true))
+    }
+
+    /// Build a world with no collision map holding the given entities, in order
+    fn world_with(texture_manager: TextureManager, bodies: Vec<(PositionComponent, PhysicsComponent)>) -> World {
+        bodies.into_iter()
+            .fold(WorldBuilder::new(texture_manager).current_world("test"), |builder, (pos, phys)| {
+                builder.entity(Some(pos), Some(phys), None, None, None, Vec::new())
+            })
+            .build()
+    }
+
+    fn x(world: &World, id: usize) -> f32 {
+        world.positions[id].as_ref().unwrap().x
+    }
+
+    #[test]
+    fn velocity_is_capped_after_integration() {
+        with_texture_manager(|texture_manager| {
+            let (pos, mut phys) = body(0.0, 0.0, 10, 10);
+            phys.max_speed = Some(20.0);
+            phys.velocity = Vector::new(0.0, 100.0);
+            let mut world = world_with(texture_manager, vec![(pos, phys)]);
+
+            PhysicsSystem::new().step(&mut world, 0.5);
+
+            assert_eq!(world.physics[0].as_ref().unwrap().velocity.mag, 20.0);
+            assert!((x(&world, 0) - 10.0).abs() < 1e-3);
+        });
+    }
+
+    #[test]
+    fn fast_movers_stop_at_thin_walls() {
+        with_texture_manager(|texture_manager| {
+            // 400 pixels in one step would jump clean over a 2 pixel wall 100 pixels away
+            let (pos, mut bullet) = body(0.0, 0.0, 4, 4);
+            bullet.continuous = true;
+            bullet.velocity = Vector::new(0.0, 400.0);
+            let mut world = world_with(texture_manager, vec![(pos, bullet), body(100.0, -10.0, 2, 30)]);
+
+            PhysicsSystem::new().step(&mut world, 1.0);
+
+            assert!(x(&world, 0) + 4.0 <= 100.0, "bullet passed the wall to {}", x(&world, 0));
+            assert!(x(&world, 0) > 50.0);
+        });
+    }
+
+    /// Where an entity moving diagonally down and right ends up against the given walls
+    fn slide_against(walls: Vec<(PositionComponent, PhysicsComponent)>) -> (f32, f32) {
+        let mut result = (0.0, 0.0);
+
+        with_texture_manager(|texture_manager| {
+            let (pos, mut mover) = body(0.0, 0.0, 10, 10);
+            mover.velocity = Vector::from_components(5.0, 5.0);
+            let mut bodies = vec![(pos, mover)];
+            bodies.extend(walls);
+            let mut world = world_with(texture_manager, bodies);
+
+            PhysicsSystem::new().step(&mut world, 1.0);
+
+            let pos = world.positions[0].as_ref().unwrap();
+            result = (pos.x, pos.y);
+        });
+
+        result
+    }
+
+    #[test]
+    fn diagonal_movement_slides_along_walls() {
+        let vertical_wall = body(12.0, -100.0, 10, 200);
+        let horizontal_wall = body(-100.0, 12.0, 200, 10);
+
+        let (x, y) = slide_against(vec![vertical_wall.clone()]);
+        assert!(x.abs() < 1e-3 && (y - 5.0).abs() < 1e-3, "slid to ({}, {})", x, y);
+
+        let (x, y) = slide_against(vec![horizontal_wall.clone()]);
+        assert!((x - 5.0).abs() < 1e-3 && y.abs() < 1e-3, "slid to ({}, {})", x, y);
+
+        let (x, y) = slide_against(vec![vertical_wall, horizontal_wall]);
+        assert!(x.abs() < 1e-3 && y.abs() < 1e-3, "moved into the corner to ({}, {})", x, y);
+    }
+
+    #[test]
+    fn sliding_along_a_wall_stops_flush_at_the_next_obstacle() {
+        with_texture_manager(|texture_manager| {
+            // Pressed against a wall on the right while sliding down towards a ledge 4 pixels below
+            let (pos, mover) = body(0.0, 0.0, 10, 10);
+            let walls = vec![(pos, mover), body(10.0, -100.0, 10, 200), body(-20.0, 14.0, 30, 10)];
+            let mut world = world_with(texture_manager, walls);
+            let mut system = PhysicsSystem::new();
+
+            for _ in 0..20 {
+                world.physics[0].as_mut().unwrap().velocity = Vector::from_components(5.0, 5.0);
+                system.step(&mut world, 0.1);
+            }
+
+            let pos = world.positions[0].as_ref().unwrap();
+            assert!(pos.x.abs() < 1e-3, "clipped into the wall to x = {}", pos.x);
+            assert!((pos.y - 4.0).abs() < 1e-3, "stopped at y = {} rather than against the ledge", pos.y);
+        });
+    }
+
+    #[test]
+    fn entities_at_different_elevations_pass_each_other() {
+        with_texture_manager(|texture_manager| {
+            let (pos, mut bird) = body(0.0, 0.0, 10, 10);
+            bird.elevation = 20.0;
+            bird.velocity = Vector::new(0.0, 20.0);
+            let mut world = world_with(texture_manager, vec![(pos, bird), body(15.0, 0.0, 10, 10)]);
+
+            PhysicsSystem::new().step(&mut world, 1.0);
+            assert!((x(&world, 0) - 20.0).abs() < 1e-3);
+
+            // Brought down to the same height, the same move is blocked
+            let pos = world.positions[0].as_mut().unwrap();
+            pos.x = 0.0;
+            world.physics[0].as_mut().unwrap().elevation = 0.0;
+
+            PhysicsSystem::new().step(&mut world, 1.0);
+            assert!(x(&world, 0) <= 5.0);
+        });
+    }
+
+    #[test]
+    fn facing_direction_survives_a_stop() {
+        with_texture_manager(|texture_manager| {
+            let (pos, mut phys) = body(0.0, 0.0, 10, 10);
+            phys.velocity = Vector::new(FRAC_PI_2, 30.0);
+            let mut world = world_with(texture_manager, vec![(pos, phys)]);
+            let mut system = PhysicsSystem::new();
+
+            system.step(&mut world, 0.1);
+            world.physics[0].as_mut().unwrap().velocity.mag = 0.0;
+            system.step(&mut world, 0.1);
+
+            assert_eq!(world.physics[0].as_ref().unwrap().facing_direction(), Some(FRAC_PI_2));
+        });
+    }
+
+    #[test]
+    fn only_matching_layers_collide() {
+        let with_layers = |layer: u32, mask: u32| {
+            let (_, mut phys) = body(0.0, 0.0, 10, 10);
+            phys.collision_layer = layer;
+            phys.collision_mask = mask;
+            phys
+        };
+
+        // Enemies hit the player and projectiles, but projectiles pass the player who fired them
+        let player = with_layers(0b001, 0b010);
+        let enemy = with_layers(0b010, 0b101);
+        let projectile = with_layers(0b100, 0b010);
+
+        assert!(player.layers_collide(&enemy));
+        assert!(enemy.layers_collide(&projectile));
+        assert!(!player.layers_collide(&projectile));
+        assert!(!projectile.layers_collide(&player));
+        assert!(!enemy.layers_collide(&enemy));
+    }
+
+    #[test]
+    fn triggers_record_overlaps_without_blocking() {
+        with_texture_manager(|texture_manager| {
+            let (pos, mut player) = body(0.0, 0.0, 10, 10);
+            player.velocity = Vector::new(0.0, 5.0);
+            let (trigger_pos, mut pickup) = body(12.0, 0.0, 10, 10);
+            pickup.trigger = true;
+            let mut world = world_with(texture_manager, vec![(pos, player), (trigger_pos, pickup)]);
+
+            PhysicsSystem::new().step(&mut world, 1.0);
+
+            assert!((x(&world, 0) - 5.0).abs() < 1e-3);
+            assert_eq!(world.physics[0].as_ref().unwrap().velocity.mag, 5.0);
+            assert!(world.collisions.iter().any(|c| c.entity == 0 && c.other == 1));
+        });
+    }
+
+    #[test]
+    fn only_freezing_dialogs_pause_movement() {
+        with_texture_manager(|texture_manager| {
+            let (pos, mut phys) = body(0.0, 0.0, 10, 10);
+            phys.velocity = Vector::new(0.0, 10.0);
+            let mut world = world_with(texture_manager, vec![(pos, phys)]);
+            world.add_dialog("cutscene".into(), Dialog::new(vec!["Wait".into()], None, true));
+            world.add_dialog("chatter".into(), Dialog::new(vec!["Hey".into()], None, false));
+            let mut system = PhysicsSystem::new();
+
+            world.curr_dialog = Some("cutscene".into());
+            system.step(&mut world, 1.0);
+            assert_eq!(x(&world, 0), 0.0);
+
+            world.curr_dialog = Some("chatter".into());
+            system.step(&mut world, 1.0);
+            assert!((x(&world, 0) - 10.0).abs() < 1e-3);
+        });
+    }
+
+    #[test]
+    fn static_entities_block_without_moving() {
+        with_texture_manager(|texture_manager| {
+            let (pos, mut mover) = body(0.0, 0.0, 10, 10);
+            mover.velocity = Vector::new(0.0, 30.0);
+            let (wall_pos, mut wall) = body(20.0, 0.0, 10, 10);
+            wall.velocity = Vector::new(std::f32::consts::PI, 50.0);
+            let mut world = world_with(texture_manager, vec![(pos, mover), (wall_pos, wall), body(24.0, 0.0, 10, 10)]);
+            world.statics[1] = true;
+
+            PhysicsSystem::new().step(&mut world, 1.0);
+
+            assert!(x(&world, 0) <= 10.0, "mover passed into the wall to {}", x(&world, 0));
+            assert_eq!(x(&world, 1), 20.0);
+        });
+    }
+
+    #[test]
+    fn pushing_into_a_wall_becomes_stuck_after_the_delay() {
+        with_texture_manager(|texture_manager| {
+            let (pos, mut mover) = body(0.0, 0.0, 10, 10);
+            mover.stuck_delay = Some(0.5);
+            let mut world = world_with(texture_manager, vec![(pos, mover), body(10.0, -10.0, 10, 30)]);
+            let mut system = PhysicsSystem::new();
+            let mut push = |world: &mut World, dir: f32| {
+                // Like AI, command the same velocity every step
+                world.physics[0].as_mut().unwrap().velocity = Vector::new(dir, 30.0);
+                system.step(world, 0.1);
+                world.states[0].contains("stuck")
+            };
+
+            for _ in 0..4 {
+                assert!(!push(&mut world, 0.0));
+            }
+            assert!(push(&mut world, 0.0));
+            assert!(x(&world, 0) <= 0.0, "mover passed into the wall to {}", x(&world, 0));
+
+            // Backing away makes progress again
+            assert!(!push(&mut world, std::f32::consts::PI));
+        });
+    }
+
+    #[test]
+    fn collisions_report_the_side_that_was_hit() {
+        // Normal of the moving entity's collision after heading in dir into a wall at (x, y)
+        let normal = |dir: f32, x: f32, y: f32| {
+            let mut normal = None;
+
+            with_texture_manager(|texture_manager| {
+                let (pos, mut mover) = body(0.0, 0.0, 10, 10);
+                mover.velocity = Vector::new(dir, 30.0);
+                let mut world = world_with(texture_manager, vec![(pos, mover), body(x, y, 10, 10)]);
+
+                PhysicsSystem::new().step(&mut world, 0.5);
+                normal = world.collisions.iter().find(|c| c.entity == 0 && c.other == 1).map(|c| c.normal);
+            });
+
+            normal
+        };
+
+        assert_eq!(normal(0.0, 20.0, 0.0), Some((-1.0, 0.0)));
+        assert_eq!(normal(std::f32::consts::PI, -20.0, 0.0), Some((1.0, 0.0)));
+        assert_eq!(normal(FRAC_PI_2, 0.0, 20.0), Some((0.0, -1.0)));
+        assert_eq!(normal(-FRAC_PI_2, 0.0, -20.0), Some((0.0, 1.0)));
+    }
+}
@@ -19,17 +19,10 @@ impl PriorityQueue {
         self.nodes.insert(insert_at, n);
     }
 
-    // Search for an element in the vec
+    // Search for an element in the vec, kept in ascending order
+    // so the greatest node, the one with the lowest cost, is last
     fn binary_search(&self, n: &Node) -> usize {
-        match self.nodes.binary_search_by(|node| {
-            if n.1 < node.1 {
-                std::cmp::Ordering::Less
-            } else if n.1 > node.1 {
-                std::cmp::Ordering::Greater
-            } else {
-                std::cmp::Ordering::Equal
-            }
-        }) {
+        match self.nodes.binary_search_by(|node| node.cmp(n)) {
             Ok(e) => e,
             Err(e) => e
         }
@@ -0,0 +1,128 @@
+use crate::state::Sequence;
+use crate::actions::set_origin;
+use crate::world::World;
+
+/// Makes an entity disappear after a lifetime or once it hits something
+#[derive(Debug, Clone)]
+pub struct ProjectileComponent {
+    /// Time in seconds the projectile lasts
+    pub ttl: f32,
+    /// Time in seconds the projectile has existed
+    pub age: f32,
+    /// Actions to run when the projectile hits something
+    /// Note: like effects, state changes have no effect since the projectile is removed
+    pub on_hit: Option<Sequence>
+}
+
+impl ProjectileComponent {
+    /// Create a new ProjectileComponent
+    pub fn new(ttl: f32, on_hit: Option<Sequence>) -> ProjectileComponent {
+        ProjectileComponent {
+            ttl,
+            age: 0.0,
+            on_hit
+        }
+    }
+
+    /// Check if the projectile has outlived its ttl
+    pub fn finished(&self) -> bool {
+        self.age > self.ttl
+    }
+}
+
+/// System which ages projectiles and removes them once they expire or collide
+#[derive(Default)]
+pub struct ProjectileSystem;
+
+impl ProjectileSystem {
+    /// Create a new ProjectileSystem
+    pub fn new() -> ProjectileSystem {
+        ProjectileSystem {}
+    }
+
+    /// Age every projectile by dt seconds, removing those which have expired.
+    /// Projectiles which are colliding run their on hit actions once as they are removed
+    pub fn run(&mut self, world: &mut World, dt: f32) {
+        if world.frozen() {
+            return;
+        }
+
+        for i in 0..world.states.len() {
+            let projectile = match world.projectiles[i].as_mut() {
+                Some(projectile) => projectile,
+                None => continue
+            };
+
+            projectile.age += dt;
+
+            if world.states[i].contains(&world.state_names.colliding) {
+                if let Some(sequence) = &mut projectile.on_hit {
+                    let queued = world.commands.spawns.len();
+                    sequence.run_all(Some(i), &mut world.states[i], &mut world.effects, &mut world.curr_dialog, &mut world.commands);
+                    set_origin(&mut world.commands.spawns[queued..], world.positions[i].clone());
+                }
+
+                world.remove_entity(i);
+            } else if projectile.finished() {
+                world.remove_entity(i);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::{Action, PlaySound};
+    use crate::geometry::{PositionComponent, Rect};
+    use crate::graphics::with_texture_manager;
+    use crate::physics::{PhysicsComponent, PhysicsSystem};
+    use crate::vector::Vector;
+    use crate::world::WorldBuilder;
+
+    #[test]
+    fn projectiles_expire_and_run_on_hit_when_they_collide() {
+        with_texture_manager(|texture_manager| {
+            let body = || PhysicsComponent::new(Rect::new(0.0, 0.0, 4, 4), 4, true);
+            let mut bullet = body();
+            bullet.velocity = Vector::new(0.0, 100.0);
+
+            let mut world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .entity(Some(PositionComponent::new(0.0, 100.0)), Some(body()), None, None, None, Vec::new())
+                .entity(Some(PositionComponent::new(0.0, 0.0)), Some(bullet), None, None, None, Vec::new())
+                .entity(Some(PositionComponent::new(30.0, 0.0)), Some(body()), None, None, None, Vec::new())
+                .build();
+
+            let on_hit = Sequence::new(vec![(0.0, Box::new(PlaySound { path: "hit.wav".into() }) as Box<dyn Action>)]);
+            world.projectiles[0] = Some(ProjectileComponent::new(1.0, None));
+            world.projectiles[1] = Some(ProjectileComponent::new(1.0, Some(on_hit)));
+
+            let (mut physics, mut projectiles) = (PhysicsSystem::new(), ProjectileSystem::new());
+            let mut step = |world: &mut World| {
+                physics.step(world, 0.1);
+                projectiles.run(world, 0.1);
+            };
+
+            // The bullet reaches the wall on its third step
+            for _ in 0..3 {
+                step(&mut world);
+            }
+            assert!(!world.is_alive(1));
+            assert_eq!(world.commands.sounds, vec!["hit.wav".to_string()]);
+            assert!(world.is_alive(0));
+
+            for _ in 0..6 {
+                step(&mut world);
+            }
+            assert!(world.is_alive(0));
+
+            for _ in 0..3 {
+                step(&mut world);
+            }
+            assert!(!world.is_alive(0));
+            assert!(world.is_alive(2));
+            assert_eq!(world.commands.sounds.len(), 1);
+        });
+    }
+}
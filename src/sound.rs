@@ -0,0 +1,123 @@
+use std::collections::{HashMap, HashSet};
+
+use log::{debug, warn};
+use sdl2::AudioSubsystem;
+use sdl2::mixer::{self, Channel, Chunk, InitFlag, Sdl2MixerContext, AUDIO_S16LSB, DEFAULT_CHANNELS};
+
+use crate::world::World;
+
+/// Sounds played when entities enter states
+#[derive(Debug, Clone, Default)]
+pub struct SoundConfig {
+    /// Map of state names to the path of the sound played when an entity gains that state
+    pub sounds: HashMap<String, String>
+}
+
+/// System which plays a sound effect whenever an entity newly gains a state with a sound
+pub struct SoundSystem {
+    /// Loaded sound for each state, empty if there is no audio device
+    chunks: HashMap<String, Chunk>,
+    /// Sounds played by actions, loaded the first time they are played
+    queued_chunks: HashMap<String, Chunk>,
+    /// States of every entity during the last run
+    last_states: Vec<HashSet<String>>,
+    /// World the last states were taken from
+    last_world: String,
+    /// Keeps the audio device open while the system exists
+    audio: Option<(AudioSubsystem, Sdl2MixerContext)>
+}
+
+impl SoundSystem {
+    /// Create a new SoundSystem, opening the audio device and loading every sound.
+    /// Without an audio subsystem, or if the device cannot be opened, the system stays silent
+    pub fn new(config: SoundConfig, audio: Option<AudioSubsystem>) -> SoundSystem {
+        let audio = audio.and_then(|audio| {
+            if let Err(e) = mixer::open_audio(44_100, AUDIO_S16LSB, DEFAULT_CHANNELS, 1_024) {
+                warn!("Could not open audio device, sounds are disabled: {}", e);
+                return None;
+            }
+
+            match mixer::init(InitFlag::OGG) {
+                Ok(context) => Some((audio, context)),
+                Err(e) => {
+                    warn!("Could not initialize the mixer, sounds are disabled: {}", e);
+                    None
+                }
+            }
+        });
+
+        let mut system = SoundSystem {
+            chunks: HashMap::new(),
+            queued_chunks: HashMap::new(),
+            last_states: Vec::new(),
+            last_world: String::new(),
+            audio
+        };
+
+        system.apply_config(config);
+        system
+    }
+
+    /// Replace the sound of every state with those of a new SoundConfig, loading each one
+    pub fn apply_config(&mut self, config: SoundConfig) {
+        self.chunks.clear();
+
+        if self.audio.is_none() {
+            return;
+        }
+
+        for (state, path) in config.sounds {
+            match Chunk::from_file(&path) {
+                Ok(chunk) => { self.chunks.insert(state, chunk); }
+                Err(e) => warn!("Could not load sound {} for state '{}': {}", path, state, e)
+            }
+        }
+    }
+
+    /// Play every sound queued by actions, clearing the queue,
+    /// then the sound of every state an entity has gained since the last run.
+    /// Entities of a newly loaded world start with their states and do not play anything
+    pub fn run(&mut self, world: &mut World) {
+        for path in world.commands.sounds.drain(..) {
+            if self.audio.is_none() {
+                continue;
+            }
+
+            let path = world.texture_manager.resolve(&path);
+            if !self.queued_chunks.contains_key(&path) {
+                match Chunk::from_file(&path) {
+                    Ok(chunk) => { self.queued_chunks.insert(path.clone(), chunk); }
+                    Err(e) => {
+                        warn!("Could not load sound {}: {}", path, e);
+                        continue;
+                    }
+                }
+            }
+
+            if let Err(e) = Channel::all().play(&self.queued_chunks[&path], 0) {
+                debug!("Could not play sound {}: {}", path, e);
+            }
+        }
+
+        if !self.chunks.is_empty() && world.current_world == self.last_world {
+            for (id, states) in world.states.iter().enumerate() {
+                let last = self.last_states.get(id);
+
+                for state in states.iter() {
+                    if last.is_some_and(|last| last.contains(state)) {
+                        continue;
+                    }
+
+                    if let Some(chunk) = self.chunks.get(state) {
+                        if let Err(e) = Channel::all().play(chunk, 0) {
+                            debug!("Could not play sound for state '{}': {}", state, e);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.last_states = world.states.clone();
+        self.last_world = world.current_world.clone();
+    }
+}
@@ -1,15 +1,51 @@
 use std::collections::{HashMap, HashSet};
-use std::time::Instant;
 
 use crate::world::World;
-use crate::actions::Action;
+use crate::actions::{Action, Commands, set_origin};
 use crate::effect::Effect;
 
+/// Names of the states which the engine itself adds and checks,
+/// allowing a game to use its own vocabulary
+#[derive(Debug, Clone)]
+pub struct StateNames {
+    /// Entity is not moving
+    pub idle: String,
+    /// Entity is moving
+    pub walking: String,
+    /// Entity is touching another entity or the collision map
+    pub colliding: String,
+    /// Entity is not drawn
+    pub invisible: String,
+    /// AI is chasing the player
+    pub aggro: String,
+    /// AI has lost sight of the player
+    pub lost: String,
+    /// Entity has been trying to move without getting anywhere
+    pub stuck: String,
+    /// Entity is moved by the AI system
+    pub ai: String
+}
+
+impl Default for StateNames {
+    fn default() -> StateNames {
+        StateNames {
+            idle: "idle".into(),
+            walking: "walking".into(),
+            colliding: "colliding".into(),
+            invisible: "invisible".into(),
+            aggro: "aggro".into(),
+            lost: "lost".into(),
+            stuck: "stuck".into(),
+            ai: "ai".into()
+        }
+    }
+}
+
 /// A sequence of actions, to be run in order after specified delays
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Sequence {
-    /// Last time the curr_index switched
-    last_switch: Instant,
+    /// Seconds the sequence has been running, advanced by the game clock
+    elapsed: f32,
     /// List of (delay, action) pairs.
     ///
     /// Each action is only be run after a certain delay
@@ -27,7 +63,7 @@ impl Sequence {
     /// Create a new Sequence
     pub fn new(actions: Vec<(f32, Box<dyn Action>)>) -> Sequence {
         Sequence {
-            last_switch: Instant::now(),
+            elapsed: 0.0,
             actions,
             curr_index: 0
         }
@@ -38,23 +74,82 @@ impl Sequence {
         &mut self.actions[self.curr_index].1
     }
 
+    /// Get the delay of the current action, 0 for an empty sequence
+    pub fn current_delay(&self) -> f32 {
+        self.actions.get(self.curr_index).map(|(delay, _)| *delay).unwrap_or(0.0)
+    }
+
+    /// Get the index of the current action
+    pub fn index(&self) -> usize {
+        self.curr_index
+    }
+
+    /// Get the number of actions in the sequence
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Check if the sequence has no actions
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Seconds the sequence has been running
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+
+    /// Advance the sequence's clock by dt seconds
+    pub fn advance(&mut self, dt: f32) {
+        self.elapsed += dt;
+    }
+
     /// Check the current delay, moving the index only after the delay has passed
     pub fn tick(&mut self) {
-        if self.curr_index == self.actions.len()-1 {
+        if self.curr_index + 1 >= self.actions.len() {
             self.curr_index = 0;
         } else {
             self.curr_index += 1;
         }
     }
 
+    /// Check if the delay of the current action has passed, never true for an empty sequence
     pub fn ready(&mut self) -> bool {
-        self.last_switch.elapsed().as_secs_f32() >= self.actions[self.curr_index].0
+        self.actions.get(self.curr_index)
+            .is_some_and(|(delay, _)| self.elapsed >= *delay)
     }
 
-    pub fn run_all(&mut self, s: &mut HashSet<String>, e: &mut Vec<Effect>, curr_dialog: &mut Option<String>) {
+    /// Run every action at once, on behalf of an entity if there is one
+    pub fn run_all(&mut self, entity: Option<usize>, s: &mut HashSet<String>, e: &mut Vec<Effect>, curr_dialog: &mut Option<String>, commands: &mut Commands) {
+        commands.entity = entity;
+
         for (_, action) in self.actions.iter_mut() {
-            action.tick(s, e, curr_dialog);
+            action.tick(s, e, curr_dialog, commands);
+        }
+
+        commands.entity = None;
+    }
+
+    /// Run every action whose delay has passed, on behalf of an entity if there is one,
+    /// stopping once the sequence wraps around
+    pub fn run_ready(&mut self, entity: Option<usize>, s: &mut HashSet<String>, e: &mut Vec<Effect>, curr_dialog: &mut Option<String>, commands: &mut Commands) {
+        commands.entity = entity;
+
+        while self.ready() {
+            self.current().tick(s, e, curr_dialog, commands);
+            self.tick();
+
+            if self.curr_index==0 {
+                break;
+            }
         }
+
+        commands.entity = None;
+    }
+
+    /// Names of every dialog the actions in this sequence can show
+    pub fn dialogs(&self) -> Vec<String> {
+        self.actions.iter().flat_map(|(_, action)| action.dialogs()).collect()
     }
 }
 
@@ -86,6 +181,50 @@ impl ActionComponent {
 
         res
     }
+
+    /// Advance the clocks of every sequence of this component by dt seconds
+    pub fn advance(&mut self, dt: f32) {
+        for sequence in self.actions.values_mut() {
+            sequence.advance(dt);
+        }
+    }
+
+    /// Names of every dialog the sequences of this component can show
+    pub fn dialogs(&self) -> Vec<String> {
+        self.actions.values().flat_map(|sequence| sequence.dialogs()).collect()
+    }
+}
+
+/// An event checked against the whole world rather than a single entity
+#[derive(Debug, Clone)]
+pub struct WorldEvent {
+    /// World states which must all be active
+    pub states: Vec<String>,
+    /// Entity states which at least one entity must have
+    pub any: Vec<String>,
+    /// Entity states which no entity may have
+    pub none: Vec<String>,
+    /// Actions to run while the event's conditions hold, modifying the world's states
+    pub sequence: Sequence
+}
+
+impl WorldEvent {
+    /// Create a new WorldEvent
+    pub fn new(states: Vec<String>, any: Vec<String>, none: Vec<String>, sequence: Sequence) -> WorldEvent {
+        WorldEvent {
+            states,
+            any,
+            none,
+            sequence
+        }
+    }
+
+    /// Check if every condition of the event holds in a world
+    pub fn active(&self, world: &World) -> bool {
+        self.states.iter().all(|s| world.world_states.contains(s))
+            && self.any.iter().all(|s| world.entities_with_state(s).next().is_some())
+            && self.none.iter().all(|s| world.entities_with_state(s).next().is_none())
+    }
 }
 
 /// Handles pairing specific states to triggers
@@ -97,21 +236,95 @@ impl StateSystem {
         StateSystem {}
     }
 
-    /// For each entity in the world, run the sequences that correspond to their current states
-    pub fn run(&mut self, world: &mut World) {
+    /// Advance every sequence by dt seconds, then for each entity in the world,
+    /// run the sequences that correspond to their current states
+    pub fn run(&mut self, world: &mut World, dt: f32) {
         for i in 0..world.states.len() {
             if world.actions[i].is_some() {
+                world.actions[i].as_mut().unwrap().advance(dt);
+
+                let queued = world.commands.spawns.len();
+
                 for sequence in world.actions[i].as_mut().unwrap().get_mut(&world.states[i]) {
-                    while sequence.ready() {
-                        sequence.current().tick(&mut world.states[i], &mut world.effects, &mut world.curr_dialog);
-                        sequence.tick();
-
-                        if sequence.curr_index==0 {
-                            break;
-                        }
-                    }
+                    sequence.run_ready(Some(i), &mut world.states[i], &mut world.effects, &mut world.curr_dialog, &mut world.commands);
                 }
+
+                set_origin(&mut world.commands.spawns[queued..], world.positions[i].clone());
             }
         }
+
+        // Run world events whose conditions hold, against the world's own states
+        let active: Vec<bool> = world.world_events.iter().map(|e| e.active(world)).collect();
+
+        for (event, active) in world.world_events.iter_mut().zip(active) {
+            event.sequence.advance(dt);
+
+            if active {
+                event.sequence.run_ready(None, &mut world.world_states, &mut world.effects, &mut world.curr_dialog, &mut world.commands);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::AddState;
+    use crate::geometry::PositionComponent;
+    use crate::graphics::with_texture_manager;
+    use crate::world::WorldBuilder;
+
+    /// A sequence adding each named state after its delay
+    fn sequence(steps: &[(f32, &str)]) -> Sequence {
+        Sequence::new(steps.iter()
+            .map(|(delay, state)| (*delay, Box::new(AddState { state: state.to_string() }) as Box<dyn Action>))
+            .collect())
+    }
+
+    #[test]
+    fn accessors_follow_a_stepped_sequence() {
+        let mut sequence = sequence(&[(0.0, "a"), (0.5, "b"), (1.5, "c")]);
+        let mut states = HashSet::new();
+        let run = |sequence: &mut Sequence, states: &mut HashSet<String>| {
+            sequence.run_ready(None, states, &mut Vec::new(), &mut None, &mut Commands::default());
+        };
+
+        assert_eq!((sequence.index(), sequence.len(), sequence.current_delay()), (0, 3, 0.0));
+
+        sequence.advance(0.6);
+        run(&mut sequence, &mut states);
+        assert_eq!((sequence.index(), sequence.current_delay()), (2, 1.5));
+        assert_eq!(states.len(), 2);
+
+        sequence.advance(1.0);
+        run(&mut sequence, &mut states);
+        assert_eq!((sequence.index(), sequence.current_delay()), (0, 0.0));
+        assert!(states.contains("c"));
+        assert!(!sequence.is_empty());
+        assert_eq!(Sequence::new(Vec::new()).current_delay(), 0.0);
+    }
+
+    #[test]
+    fn world_events_fire_once_no_enemy_is_left_alive() {
+        with_texture_manager(|texture_manager| {
+            let mut builder = WorldBuilder::new(texture_manager).current_world("test");
+            for x in [0.0, 20.0] {
+                builder = builder.entity(Some(PositionComponent::new(x, 0.0)), None, None, None, None, vec!["enemy".into(), "alive".into()]);
+            }
+            let mut world = builder.build();
+            world.world_events.push(WorldEvent::new(Vec::new(), vec!["enemy".into()], vec!["alive".into()], sequence(&[(0.0, "exit_open")])));
+            let mut system = StateSystem::new();
+
+            system.run(&mut world, 0.1);
+            assert!(world.world_states.is_empty());
+
+            world.states[0].remove("alive");
+            system.run(&mut world, 0.1);
+            assert!(world.world_states.is_empty());
+
+            world.states[1].remove("alive");
+            system.run(&mut world, 0.1);
+            assert!(world.world_states.contains("exit_open"));
+        });
     }
 }
@@ -0,0 +1,29 @@
+use std::collections::HashMap;
+
+/// Numeric values of an entity, such as health, which effects can change over time
+#[derive(Debug, Clone)]
+pub struct StatsComponent {
+    /// Map of stat names to their current values
+    pub stats: HashMap<String, f32>
+}
+
+impl StatsComponent {
+    /// Create a new StatsComponent
+    pub fn new(stats: HashMap<String, f32>) -> StatsComponent {
+        StatsComponent {
+            stats
+        }
+    }
+
+    /// Get the value of a stat, if the entity has it
+    pub fn get(&self, name: &str) -> Option<f32> {
+        self.stats.get(name).copied()
+    }
+
+    /// Add to the value of a stat, ignoring stats the entity does not have
+    pub fn add(&mut self, name: &str, delta: f32) {
+        if let Some(value) = self.stats.get_mut(name) {
+            *value += delta;
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::geometry::{PositionComponent, Rect};
+
+/// Grid of tiles drawn from a single tileset texture, instead of one entity per tile
+#[derive(Debug, Clone)]
+pub struct TilemapComponent {
+    /// Index of the tileset texture
+    pub texture_id: usize,
+    /// Width of a tile in both the tileset and the world
+    pub tile_width: u32,
+    /// Height of a tile in both the tileset and the world
+    pub tile_height: u32,
+    /// Rows of tile indices into the tileset, counted left to right then top to bottom.
+    /// Empty cells draw nothing
+    pub tiles: Vec<Vec<Option<u32>>>
+}
+
+impl TilemapComponent {
+    /// Create a new TilemapComponent
+    pub fn new(texture_id: usize, tile_width: u32, tile_height: u32, tiles: Vec<Vec<Option<u32>>>) -> TilemapComponent {
+        TilemapComponent {
+            texture_id,
+            tile_width,
+            tile_height,
+            tiles
+        }
+    }
+
+    /// World rectangle of the tile at a row and column, with the map's top left corner at pos
+    pub fn tile_rect(&self, pos: &PositionComponent, row: usize, col: usize) -> Rect {
+        Rect::new(
+            pos.x + (col as u32 * self.tile_width) as f32,
+            pos.y + (row as u32 * self.tile_height) as f32,
+            self.tile_width,
+            self.tile_height
+        )
+    }
+
+    /// Rectangle of a tile inside a tileset which is columns tiles wide
+    pub fn srcbox(&self, index: u32, columns: u32) -> sdl2::rect::Rect {
+        let columns = columns.max(1);
+
+        sdl2::rect::Rect::new(
+            ((index % columns) * self.tile_width) as i32,
+            ((index / columns) * self.tile_height) as i32,
+            self.tile_width,
+            self.tile_height
+        )
+    }
+
+    /// Rows and columns of the tiles which overlap a world rectangle, with the map's top left corner at pos,
+    /// as half open (first row, end row, first column, end column)
+    pub fn visible(&self, pos: &PositionComponent, area: Rect) -> (usize, usize, usize, usize) {
+        if self.tile_width == 0 || self.tile_height == 0 {
+            return (0, 0, 0, 0);
+        }
+
+        let rows = self.tiles.len();
+        let cols = self.tiles.iter().map(|row| row.len()).max().unwrap_or(0);
+
+        let index = |offset: f32, size: u32, len: usize| ((offset / size as f32).max(0.0) as usize).min(len);
+
+        (
+            index(area.y - pos.y, self.tile_height, rows),
+            index(area.y + area.h as f32 - pos.y + self.tile_height as f32, self.tile_height, rows),
+            index(area.x - pos.x, self.tile_width, cols),
+            index(area.x + area.w as f32 - pos.x + self.tile_width as f32, self.tile_width, cols)
+        )
+    }
+}
@@ -2,17 +2,32 @@ use std::collections::HashSet;
 use std::collections::HashMap;
 
 use itertools::izip;
+use log::{debug, info, warn};
 use sdl2::pixels::Color;
+use yaml_rust::Yaml;
 
-use crate::geometry::PositionComponent;
-use crate::physics::PhysicsComponent;
+use crate::geometry::{PositionComponent, Rect};
+use crate::physics::{PhysicsComponent, Collision};
 use crate::graphics::GraphicsComponent;
 use crate::animation::AnimationComponent;
-use crate::state::ActionComponent;
+use crate::state::{ActionComponent, StateNames, WorldEvent};
 use crate::effect::Effect;
+use crate::follow::FollowComponent;
+use crate::projectile::ProjectileComponent;
+use crate::stats::StatsComponent;
+use crate::tilemap::TilemapComponent;
+use crate::ai::AIComponent;
 use crate::dialog::Dialog;
 use crate::graphics::TextureManager;
-use crate::parser::parse_world_file;
+use crate::parser::{load_world_document, parse_game_file, parse_world_document, parse_spawned_entity, ParseError};
+use crate::actions::{Commands, PendingSpawn};
+use crate::ai::AISystem;
+use crate::graphics::GraphicsConfig;
+use crate::input::InputConfig;
+use crate::sound::SoundConfig;
+
+/// System configs read from a reloaded game file, to replace those the systems were created with
+pub type SystemConfigs = (InputConfig, GraphicsConfig, AISystem, SoundConfig);
 
 /// Struct containing all game data and current state
 pub struct World<'a> {
@@ -22,21 +37,43 @@ pub struct World<'a> {
     /// Possible world files, Name -> Path
     pub worlds: HashMap<String, String>,
 
+    /// Path of the game file this world was parsed from, if any
+    pub game_file: Option<String>,
+
     /// Current world
     pub current_world: String,
 
+    /// Document the current world was loaded from, put back if a reload fails
+    document: Option<Yaml>,
+
     /// All effects in the game world
     pub effects: Vec<Effect>,
 
+    /// Effects which apply to every entity regardless of position
+    pub ambient: Vec<Effect>,
+
+    /// States of the world as a whole
+    pub world_states: HashSet<String>,
+
+    /// Events checked against the whole world every frame
+    pub world_events: Vec<WorldEvent>,
+
     /// All Dialogs
     pub dialogs: HashMap<String, Dialog>,
 
     /// Currently selected dialog index
     pub curr_dialog: Option<String>,
 
-    /// Background texture and renderbox
-    pub background: Option<GraphicsComponent>,
+    /// Commands queued by actions for the world's systems
+    pub commands: Commands,
+
+    /// Background layers drawn in order, each with the fraction of the camera's movement
+    /// it scrolls by: 0 stays still on screen, 1 moves with the world
+    pub backgrounds: Vec<(GraphicsComponent, f32)>,
     pub background_color: Color,
+    /// Color for the parts of a camera's view the layers moving with the world do not cover,
+    /// the background color shows through if none
+    pub out_of_bounds_color: Option<Color>,
 
     /// world bounds
     pub world_width: u32,
@@ -45,9 +82,36 @@ pub struct World<'a> {
     /// World collision maps
     pub collision_map: HashMap<String, Option<Vec<Vec<bool>>>>,
 
+    /// Overlaps involving trigger entities and collisions between physical entities during the last physics tick
+    pub collisions: Vec<Collision>,
+
+    /// Names of the states used by the engine's systems
+    pub state_names: StateNames,
+
+    /// Whether the parser warns about unrecognized keys
+    pub strict: bool,
+
+    /// Seconds of game time which have passed, not counting time the world was frozen
+    pub elapsed: f32,
+
+    /// Fixed steps per second physics, projectiles and states run at, once per frame if not positive
+    pub physics_hz: f32,
+
+    /// Downward acceleration in pixels/second² for entities affected by gravity, a top-down world if none
+    pub gravity: Option<f32>,
+
+    /// Most fixed steps run in a single frame when catching up
+    pub max_physics_steps: u32,
+
+    /// Frames per second the game loop aims for
+    pub frame_rate: f32,
+
     /// Number of global entities
     global: usize,
 
+    /// Id of the player entity
+    pub player_id: usize,
+
     // Entity Components
     /// Array of sets of all the current active states for an entity
     pub states: Vec<HashSet<String>>,
@@ -61,6 +125,23 @@ pub struct World<'a> {
     pub animations: Vec<Option<AnimationComponent>>,
     /// Array of optoin actions data for an entity
     pub actions: Vec<Option<ActionComponent>>,
+    /// Array of optional follow data for an entity
+    pub follows: Vec<Option<FollowComponent>>,
+    /// Array of optional projectile data for an entity
+    pub projectiles: Vec<Option<ProjectileComponent>>,
+    /// Array of optional stats data for an entity
+    pub stats: Vec<Option<StatsComponent>>,
+    /// Array of optional tilemap data for an entity
+    pub tilemaps: Vec<Option<TilemapComponent>>,
+    /// Array of optional ai data for an entity
+    pub ais: Vec<Option<AIComponent>>,
+    /// Array of whether an entity is static, never moving or animating
+    pub statics: Vec<bool>,
+    /// Array of whether an entity is flagged to be drawn on the minimap
+    pub on_minimap: Vec<bool>,
+
+    /// Entity ids by name
+    pub names: HashMap<String, usize>,
 }
 
 impl<'a> World<'a> {
@@ -79,16 +160,40 @@ impl<'a> World<'a> {
             graphics: Vec::new(),
             animations: Vec::new(),
             actions: Vec::new(),
+            follows: Vec::new(),
+            projectiles: Vec::new(),
+            stats: Vec::new(),
+            tilemaps: Vec::new(),
+            ais: Vec::new(),
+            statics: Vec::new(),
+            on_minimap: Vec::new(),
+            names: HashMap::new(),
             effects: Vec::new(),
+            ambient: Vec::new(),
+            world_states: HashSet::new(),
+            world_events: Vec::new(),
             dialogs: HashMap::new(),
             curr_dialog: None,
-            background: None,
+            commands: Commands::default(),
+            backgrounds: Vec::new(),
             background_color: Color::RGB(0, 0, 0),
+            out_of_bounds_color: None,
             world_width: 0,
             world_height: 0,
             global: 0,
+            player_id: 0,
+            game_file: None,
             current_world: "".into(),
-            collision_map: maps
+            document: None,
+            collision_map: maps,
+            state_names: StateNames::default(),
+            strict: false,
+            elapsed: 0.0,
+            physics_hz: 120.0,
+            gravity: None,
+            max_physics_steps: 8,
+            frame_rate: 60.0,
+            collisions: Vec::new()
         }
     }
 
@@ -103,6 +208,20 @@ impl<'a> World<'a> {
         self.add_entity(position, physics, graphics, animation, actions)
     }
 
+    /// Start building an entity with named components instead of positional arguments
+    pub fn build_entity(&mut self) -> EntityBuilder<'_, 'a> {
+        EntityBuilder {
+            world: self,
+            position: None,
+            physics: None,
+            graphics: None,
+            animation: None,
+            actions: None,
+            states: Vec::new(),
+            player: false
+        }
+    }
+
     /// Add an entity to the entity manager
     pub fn add_entity(&mut self,
         position: Option<PositionComponent>,
@@ -117,6 +236,13 @@ impl<'a> World<'a> {
         self.graphics.push(graphics);
         self.animations.push(animation);
         self.actions.push(actions);
+        self.follows.push(None);
+        self.projectiles.push(None);
+        self.stats.push(None);
+        self.tilemaps.push(None);
+        self.ais.push(None);
+        self.statics.push(false);
+        self.on_minimap.push(false);
 
         self.states.len()-1
     }
@@ -130,18 +256,182 @@ impl<'a> World<'a> {
             self.graphics.pop();
             self.animations.pop();
             self.actions.pop();
+            self.follows.pop();
+            self.projectiles.pop();
+            self.stats.pop();
+            self.tilemaps.pop();
+            self.ais.pop();
+            self.statics.pop();
+            self.on_minimap.pop();
         }
 
+        let global = self.global;
+        self.names.retain(|_, id| *id < global);
+
         self.dialogs.clear();
         self.effects.clear();
+        self.commands.clear();
+        self.ambient.clear();
+        self.world_states.clear();
+        self.world_events.clear();
+        self.collisions.clear();
+
+        // Drop the world's textures, keeping those loaded by the game file
+        self.backgrounds.clear();
+        self.texture_manager.unload_scope();
+    }
+
+    /// Remove an entity from the world by clearing all of its components,
+    /// keeping its id reserved so the ids of other entities do not change
+    pub fn remove_entity(&mut self, id: usize) {
+        if id >= self.states.len() {
+            return;
+        }
+
+        self.states[id].clear();
+        self.positions[id] = None;
+        self.physics[id] = None;
+        self.graphics[id] = None;
+        self.animations[id] = None;
+        self.actions[id] = None;
+        self.follows[id] = None;
+        self.projectiles[id] = None;
+        self.stats[id] = None;
+        self.tilemaps[id] = None;
+        self.ais[id] = None;
+        self.statics[id] = false;
+        self.on_minimap[id] = false;
+
+        self.names.retain(|_, e| *e != id);
+
+        debug!("Remove entity {}", id);
+    }
+
+    /// Add every entity queued by actions to the world, clearing the queue
+    pub fn spawn_pending(&mut self) {
+        let spawns: Vec<PendingSpawn> = self.commands.spawns.drain(..).collect();
+
+        for spawn in spawns {
+            match parse_spawned_entity(&spawn.template, self, spawn.origin.as_ref()) {
+                Ok(id) => debug!("Spawn entity {}", id),
+                Err(e) => warn!("Could not spawn entity: {}", e)
+            }
+        }
+    }
+
+    /// Remove every entity queued for removal by actions, clearing the queue
+    pub fn despawn_pending(&mut self) {
+        for id in std::mem::take(&mut self.commands.despawns) {
+            self.remove_entity(id);
+        }
+    }
+
+    /// Check if an id belongs to an entity which exists and has not been removed
+    pub fn is_alive(&self, id: usize) -> bool {
+        id < self.states.len() && (
+            self.positions[id].is_some()
+            || self.physics[id].is_some()
+            || self.graphics[id].is_some()
+            || self.animations[id].is_some()
+            || self.actions[id].is_some()
+            || self.follows[id].is_some()
+            || self.projectiles[id].is_some()
+            || self.stats[id].is_some()
+            || self.tilemaps[id].is_some()
+            || self.ais[id].is_some()
+        )
     }
 
     /// Load a world from a world file
-    pub fn load(&mut self, name: &str, entrance: &str) {
-        let path = self.worlds[name].clone();
-        parse_world_file(&path, self, entrance);
-        println!("Load: {} {}", name, entrance);
+    pub fn load(&mut self, name: &str, entrance: &str) -> Result<(), ParseError> {
+        let path = self.worlds.get(name)
+            .ok_or_else(|| ParseError::UnknownWorld(name.into()))?
+            .clone();
+
+        let document = load_world_document(&path)?;
+        self.texture_manager.begin_scope();
+        parse_world_document(&document, self, entrance)?;
+        info!("Load: {} {}", name, entrance);
         self.current_world = name.into();
+        self.document = Some(document);
+
+        Ok(())
+    }
+
+    /// Reload the current world from its file, such as after editing it.
+    /// Global entities like the player keep their positions and states
+    pub fn reload(&mut self) -> Result<(), ParseError> {
+        match self.current_world_path().cloned() {
+            Some(path) => self.reload_from_file(&path).map(|_| ()),
+            None => Err(ParseError::UnknownWorld(self.current_world.clone()))
+        }
+    }
+
+    /// Reload the game file or the current world's file after it has been edited,
+    /// keeping the player where they are. Reloading the game file rebuilds the whole world
+    /// and returns the new system configs, while the file of a world which is not loaded is ignored.
+    /// A file which cannot be parsed leaves everything as it was
+    pub fn reload_from_file(&mut self, path: &str) -> Result<Option<SystemConfigs>, ParseError> {
+        let pid = self.player_id;
+        let position = self.positions.get(pid).cloned().flatten();
+
+        if self.game_file.as_deref() == Some(path) {
+            // Parse into a separate world, which drops the old world and its textures once swapped in
+            let (mut world, input, graphics, ai, sound) = parse_game_file(path, TextureManager::new(self.texture_manager.texture_creator()))?;
+
+            if world.current_world != self.current_world && world.worlds.contains_key(&self.current_world) {
+                world.deload();
+                world.load(&self.current_world, "")?;
+            }
+
+            let wpid = world.player_id;
+            if let Some(states) = self.states.get(pid) {
+                world.states[wpid].extend(states.iter().cloned());
+            }
+            world.positions[wpid] = position.or(world.positions[wpid].take());
+
+            *self = world;
+            return Ok(Some((input, graphics, ai, sound)));
+        }
+
+        if self.current_world_path().map(String::as_str) != Some(path) {
+            return Ok(None);
+        }
+
+        // A file which is not valid yaml is caught before anything is deloaded
+        let document = load_world_document(path)?;
+        let previous = self.document.take();
+
+        self.deload();
+        self.texture_manager.begin_scope();
+        let result = parse_world_document(&document, self, "");
+
+        // Put back the world as it was if the new file has mistakes past its syntax
+        let result = match (result, previous) {
+            (Ok(()), _) => {
+                self.document = Some(document);
+                Ok(None)
+            }
+            (Err(e), Some(previous)) => {
+                self.deload();
+                self.texture_manager.begin_scope();
+                parse_world_document(&previous, self, "")?;
+                self.document = Some(previous);
+                Err(e)
+            }
+            (Err(e), None) => Err(e)
+        };
+
+        if let Some(slot) = self.positions.get_mut(pid) {
+            *slot = position;
+        }
+
+        result
+    }
+
+    /// Path of the file the current world was loaded from
+    pub fn current_world_path(&self) -> Option<&String> {
+        self.worlds.get(&self.current_world)
     }
 
     /// Add a new Dialog to display
@@ -149,20 +439,53 @@ impl<'a> World<'a> {
         self.dialogs.insert(name, dialog);
     }
 
-    /// Apply all effects to the objects who lie inside them
-    pub fn apply_effects(&mut self) {
+    /// Check if the currently shown dialog pauses the world
+    pub fn frozen(&self) -> bool {
+        self.curr_dialog.as_ref()
+            .and_then(|name| self.dialogs.get(name))
+            .is_some_and(|dialog| dialog.freeze_world)
+    }
+
+    /// Apply all effects to the objects who lie inside them,
+    /// and all ambient effects to every object.
+    /// Stat deltas are per second, so overlapping effects add together over dt seconds
+    pub fn apply_effects(&mut self, dt: f32) {
         for i in 0..self.states.len() {
+            // Removed entities must not gain states back
+            if !self.is_alive(i) {
+                continue;
+            }
+
+            for effect in self.ambient.iter() {
+                for state in effect.adds.iter() {
+                    self.states[i].insert(state.clone());
+                }
+
+                for state in effect.removes.iter() {
+                    self.states[i].remove(state);
+                }
+
+                if let Some(stats) = self.stats[i].as_mut() {
+                    for (stat, delta) in effect.stat_deltas.iter() {
+                        stats.add(stat, delta * dt);
+                    }
+                }
+            }
+
             if self.positions[i].is_some() && self.physics[i].is_some() {
                 let footprint = self.physics[i].as_ref().unwrap().hitbox
                     .after_position(&self.positions[i].as_ref().unwrap())
                     .after_depth(self.physics[i].as_ref().unwrap().depth);
 
                 for j in 0..self.effects.len() {
-                    let effect_rect = self.effects[j].rect;
                     let add = &self.effects[j].adds;
                     let remove = &self.effects[j].removes;
 
-                    if footprint.has_intersection(effect_rect) {
+                    if self.effects[j].applies_to(footprint) {
+                        if i == self.player_id && self.effects[j].change_world.is_some() {
+                            self.commands.world_change = self.effects[j].change_world.clone();
+                        }
+
                         for state in add {
                             self.states[i].insert(state.clone());
                         }
@@ -171,6 +494,11 @@ impl<'a> World<'a> {
                             self.states[i].remove(state);
                         }
 
+                        if let Some(stats) = self.stats[i].as_mut() {
+                            for (stat, delta) in self.effects[j].stat_deltas.iter() {
+                                stats.add(stat, delta * dt);
+                            }
+                        }
                     }
                 }
             }
@@ -259,6 +587,23 @@ impl<'a> World<'a> {
     }
 
     // Individual selectors for common properties of entities
+    /// Get the id of an entity by its name
+    pub fn get_entity_by_name(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    /// Give an entity a name it can be found by
+    pub fn set_entity_name(&mut self, id: usize, name: String) {
+        self.names.insert(name, id);
+    }
+
+    /// Ids of every entity which has a state
+    pub fn entities_with_state<'b>(&'b self, state: &'b str) -> impl Iterator<Item = usize> + 'b {
+        self.states.iter().enumerate()
+            .filter(move |(_, states)| states.contains(state))
+            .map(|(id, _)| id)
+    }
+
     /// Get set of states of a single entity
     pub fn get_entity_states(&self, id: usize) -> &HashSet<String> {
         &self.states[id]
@@ -289,6 +634,32 @@ impl<'a> World<'a> {
         (self.positions[id].as_mut(), self.physics[id].as_mut())
     }
 
+    /// Get stats data for a single entity
+    pub fn get_entity_stats(&self, id: usize) -> Option<&StatsComponent> {
+        self.stats[id].as_ref()
+    }
+
+    /// Get mutable stats data for a single entity
+    pub fn get_entity_stats_mut(&mut self, id: usize) -> Option<&mut StatsComponent> {
+        self.stats[id].as_mut()
+    }
+
+    /// Get the direction a single entity is facing
+    pub fn get_entity_facing(&self, id: usize) -> Option<f32> {
+        self.physics[id].as_ref().and_then(|p| p.facing_direction())
+    }
+
+    /// Get the bounding box over the footprints of a set of entities,
+    /// ignoring entities without position or physics data
+    pub fn bounding_box(&self, ids: &[usize]) -> Option<Rect> {
+        ids.iter()
+            .filter_map(|id| match self.get_entity_physics(*id) {
+                (Some(pos), Some(phys)) => Some(phys.hitbox.after_position(pos).after_depth(phys.depth)),
+                _ => None
+            })
+            .reduce(|a, b| a.union(b))
+    }
+
     /// Get graphics data for a single entity
     pub fn get_entity_graphics(&self, id: usize) -> (Option<&PositionComponent>, Option<&GraphicsComponent>) {
         (self.positions[id].as_ref(), self.graphics[id].as_ref())
@@ -321,3 +692,274 @@ impl<'a> World<'a> {
         self.states[id].remove(state);
     }
 }
+
+/// Builder for adding an entity to a World, every component is none unless set
+pub struct EntityBuilder<'w, 'a> {
+    world: &'w mut World<'a>,
+    position: Option<PositionComponent>,
+    physics: Option<PhysicsComponent>,
+    graphics: Option<GraphicsComponent>,
+    animation: Option<AnimationComponent>,
+    actions: Option<ActionComponent>,
+    states: Vec<String>,
+    player: bool
+}
+
+impl<'w, 'a> EntityBuilder<'w, 'a> {
+    /// Set the position of the entity
+    pub fn position(mut self, x: f32, y: f32) -> Self {
+        self.position = Some(PositionComponent::new(x, y));
+        self
+    }
+
+    /// Set the physics component
+    pub fn physics(mut self, physics: PhysicsComponent) -> Self {
+        self.physics = Some(physics);
+        self
+    }
+
+    /// Set the graphics component
+    pub fn graphics(mut self, graphics: GraphicsComponent) -> Self {
+        self.graphics = Some(graphics);
+        self
+    }
+
+    /// Set the animations component
+    pub fn animations(mut self, animation: AnimationComponent) -> Self {
+        self.animation = Some(animation);
+        self
+    }
+
+    /// Set the actions component
+    pub fn actions(mut self, actions: ActionComponent) -> Self {
+        self.actions = Some(actions);
+        self
+    }
+
+    /// Add a starting state
+    pub fn state(mut self, state: &str) -> Self {
+        self.states.push(state.into());
+        self
+    }
+
+    /// Make the entity the player, which persists across world loads.
+    /// Panics if the current world already has entities, since `World::deload`
+    /// only keeps global entities added before them
+    pub fn player(mut self) -> Self {
+        assert_eq!(self.world.states.len(), self.world.global, "the player must be added before the entities of a world");
+        self.player = true;
+        self
+    }
+
+    /// Add the entity to the world, returning its id
+    pub fn spawn(self) -> usize {
+        let id = if self.player {
+            let id = self.world.add_global_entity(self.position, self.physics, self.graphics, self.animation, self.actions);
+            self.world.set_entity_name(id, "player".into());
+            self.world.player_id = id;
+            id
+        } else {
+            self.world.add_entity(self.position, self.physics, self.graphics, self.animation, self.actions)
+        };
+
+        self.world.states[id].extend(self.states);
+        id
+    }
+}
+
+/// Builder for constructing a World in code instead of from a world file
+pub struct WorldBuilder<'a> {
+    world: World<'a>
+}
+
+impl<'a> WorldBuilder<'a> {
+    /// Create a new WorldBuilder with an empty world
+    pub fn new(texture_manager: TextureManager<'a>) -> WorldBuilder<'a> {
+        WorldBuilder {
+            world: World::new(texture_manager, HashMap::new(), HashMap::new())
+        }
+    }
+
+    /// Register a world file and its optional collision map
+    pub fn world_file(mut self, name: &str, path: &str, map: Option<Vec<Vec<bool>>>) -> Self {
+        self.world.worlds.insert(name.into(), path.into());
+        self.world.collision_map.insert(name.into(), map);
+        self
+    }
+
+    /// Set the name of the current world
+    pub fn current_world(mut self, name: &str) -> Self {
+        self.world.current_world = name.into();
+        self
+    }
+
+    /// Set the world bounds
+    pub fn bounds(mut self, width: u32, height: u32) -> Self {
+        self.world.world_width = width;
+        self.world.world_height = height;
+        self
+    }
+
+    /// Set the background texture, as a layer moving with the world behind any others, and color
+    pub fn background(mut self, background: Option<GraphicsComponent>, color: Color) -> Self {
+        if let Some(background) = background {
+            self.world.backgrounds.insert(0, (background, 1.0));
+        }
+        self.world.background_color = color;
+        self
+    }
+
+    /// Add an entity which persists across world loads
+    pub fn global_entity(mut self,
+        position: Option<PositionComponent>,
+        physics: Option<PhysicsComponent>,
+        graphics: Option<GraphicsComponent>,
+        animation: Option<AnimationComponent>,
+        actions: Option<ActionComponent>,
+        states: Vec<String>
+    ) -> Self {
+        let id = self.world.add_global_entity(position, physics, graphics, animation, actions);
+        self.world.states[id].extend(states);
+        self
+    }
+
+    /// Add an entity to the current world
+    pub fn entity(mut self,
+        position: Option<PositionComponent>,
+        physics: Option<PhysicsComponent>,
+        graphics: Option<GraphicsComponent>,
+        animation: Option<AnimationComponent>,
+        actions: Option<ActionComponent>,
+        states: Vec<String>
+    ) -> Self {
+        let id = self.world.add_entity(position, physics, graphics, animation, actions);
+        self.world.states[id].extend(states);
+        self
+    }
+
+    /// Add a dialog
+    pub fn dialog(mut self, name: &str, dialog: Dialog) -> Self {
+        self.world.add_dialog(name.into(), dialog);
+        self
+    }
+
+    /// Add an effect
+    pub fn effect(mut self, effect: Effect) -> Self {
+        self.world.effects.push(effect);
+        self
+    }
+
+    /// Finish building the world
+    pub fn build(mut self) -> World<'a> {
+        // Systems look up the collision map of the current world, so make sure one exists
+        self.world.collision_map.entry(self.world.current_world.clone()).or_insert(None);
+        self.world
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::with_texture_manager;
+    use crate::physics::PhysicsSystem;
+    use crate::vector::Vector;
+
+    #[test]
+    fn builds_a_two_entity_world_and_runs_physics() {
+        with_texture_manager(|texture_manager| {
+            let mut world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .bounds(200, 100)
+                .entity(
+                    Some(PositionComponent::new(0.0, 0.0)),
+                    Some(PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 10, true)),
+                    None, None, None, vec!["walking".into()]
+                )
+                .entity(
+                    Some(PositionComponent::new(30.0, 0.0)),
+                    Some(PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 10, true)),
+                    None, None, None, Vec::new()
+                )
+                .build();
+
+            assert_eq!(world.states.len(), 2);
+            assert!(world.states[0].contains("walking"));
+
+            // Moving right at 100 pixels/second runs into the second entity after 20 pixels
+            world.physics[0].as_mut().unwrap().velocity = Vector::new(0.0, 100.0);
+            let mut physics = PhysicsSystem::new();
+
+            physics.step(&mut world, 0.1);
+            assert!((world.positions[0].as_ref().unwrap().x - 10.0).abs() < 1e-3);
+
+            physics.step(&mut world, 0.1);
+            physics.step(&mut world, 0.1);
+            assert!(world.positions[0].as_ref().unwrap().x <= 20.0);
+            assert_eq!(world.positions[1].as_ref().unwrap().x, 30.0);
+            assert!(world.states[0].contains(&world.state_names.colliding));
+        });
+    }
+
+    #[test]
+    fn bounding_box_covers_entity_footprints() {
+        with_texture_manager(|texture_manager| {
+            let world = WorldBuilder::new(texture_manager)
+                .current_world("test")
+                .entity(
+                    Some(PositionComponent::new(0.0, 0.0)),
+                    Some(PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 4, true)),
+                    None, None, None, Vec::new()
+                )
+                .entity(
+                    Some(PositionComponent::new(40.0, 20.0)),
+                    Some(PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 4, true)),
+                    None, None, None, Vec::new()
+                )
+                .build();
+
+            let bounds = world.bounding_box(&[0, 1]).unwrap();
+            assert_eq!((bounds.x, bounds.y, bounds.w, bounds.h), (0.0, 6.0, 50, 24));
+            assert!(world.bounding_box(&[]).is_none());
+        });
+    }
+
+    #[test]
+    fn built_entities_get_their_components_and_states() {
+        with_texture_manager(|texture_manager| {
+            let mut world = WorldBuilder::new(texture_manager).current_world("test").build();
+
+            let player = world.build_entity()
+                .position(4.0, 8.0)
+                .physics(PhysicsComponent::new(Rect::new(0.0, 0.0, 10, 10), 10, true))
+                .state("idle")
+                .player()
+                .spawn();
+            let crate_id = world.build_entity().position(40.0, 0.0).state("closed").state("heavy").spawn();
+
+            assert_eq!((player, crate_id), (0, 1));
+            assert_eq!(world.player_id, player);
+            assert_eq!(world.get_entity_by_name("player"), Some(player));
+            assert_eq!(world.positions[player].as_ref().map(|p| (p.x, p.y)), Some((4.0, 8.0)));
+            assert!(world.physics[player].is_some() && world.graphics[player].is_none());
+            assert_eq!(world.states[player], HashSet::from(["idle".to_string()]));
+            assert_eq!(world.states[crate_id], HashSet::from(["closed".to_string(), "heavy".to_string()]));
+            assert!(world.physics[crate_id].is_none());
+
+            // Only the player outlives the world
+            world.deload();
+            assert_eq!(world.states.len(), 1);
+            assert_eq!(world.positions[0].as_ref().map(|p| p.x), Some(4.0));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "the player must be added before the entities of a world")]
+    fn players_cannot_follow_world_entities() {
+        with_texture_manager(|texture_manager| {
+            let mut world = WorldBuilder::new(texture_manager).current_world("test").build();
+
+            world.build_entity().position(0.0, 0.0).spawn();
+            world.build_entity().position(8.0, 0.0).player().spawn();
+        });
+    }
+}